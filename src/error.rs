@@ -0,0 +1,35 @@
+use thiserror::Error;
+
+use crate::exchange::ExchangeError;
+use crate::report::ReportError;
+use crate::stock::{ClientError, ReplayError};
+
+// Any failure from the crate's public entry points. The errors of each module
+// are wrapped whole, so callers can still match on their details: duplicate
+// tickers and clients are exchange errors, and IO failures surface as report
+// or replay errors with the path or input they concern.
+#[derive(Debug, Error)]
+pub enum StockError {
+    // The stock has no sector set and its ticker is not a known one.
+    #[error("cannot classify stock {0} into a sector")]
+    Unclassified(String),
+    #[error("invalid configuration: {0}")]
+    InvalidConfig(String),
+    // A configured duration too large to represent once divided by the speed.
+    #[error("{0} overflows when scaled by the simulation speed")]
+    Overflow(String),
+    #[error(transparent)]
+    Exchange(#[from] ExchangeError),
+    #[error(transparent)]
+    Client(#[from] ClientError),
+    #[error(transparent)]
+    Report(#[from] ReportError),
+    #[error(transparent)]
+    Replay(#[from] ReplayError),
+    #[cfg(feature = "parquet")]
+    #[error(transparent)]
+    Arrow(#[from] arrow_schema::ArrowError),
+    #[cfg(feature = "parquet")]
+    #[error(transparent)]
+    Parquet(#[from] parquet::errors::ParquetError),
+}
@@ -0,0 +1,398 @@
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::sync::{Arc, Mutex};
+use std::thread::JoinHandle;
+use std::time::Duration;
+
+use scheduled_thread_pool::ScheduledThreadPool;
+use serde::Serialize;
+use thiserror::Error;
+
+use crate::error::StockError;
+use crate::report::ExchangeReport;
+use crate::stock::{
+    broker_sectors, process_broker_actions, schedule_simulator, seeded_rng, spawn_watchdog, BrokerConfig, BrokerResult,
+    ClientError, ClientPreference, MarketSimulator, OrderThrottle, PriceTick, RandomSource, SimulationConfig, SimulationControl, Stock, StockCatalog, StockType, TickDispatcher, Verbosity,
+};
+
+#[derive(Debug, Clone, PartialEq, Eq, Error)]
+pub enum ExchangeError {
+    // The client is assigned to more than one broker.
+    #[error("client {0} is assigned to more than one broker")]
+    DuplicateClient(String),
+    // The ticker is listed more than once.
+    #[error("stock {0} is listed more than once")]
+    DuplicateTicker(String),
+    // With nothing to tick, brokers would wait forever.
+    #[error("no stocks are listed on the exchange")]
+    NoStocks,
+    // A copy-trading broker follows a broker that does not exist.
+    #[error("no broker named {0} to copy trades from")]
+    UnknownLeader(String),
+    #[error("client {client}: {error}")]
+    InvalidClient {
+        client: String,
+        #[source]
+        error: ClientError,
+    },
+    // A broker or the watchdog panicked, named after its thread.
+    #[error("thread {thread} panicked: {message}")]
+    ThreadPanicked { thread: String, message: String },
+}
+
+// Waits for the thread, turning a panic into an error that names it.
+fn join_thread<T>(thread: JoinHandle<T>) -> Result<T, ExchangeError> {
+    let name = thread.thread().name().unwrap_or("unnamed").to_string();
+    thread.join().map_err(|panic| {
+        let message = match panic.downcast::<String>() {
+            Ok(message) => *message,
+            Err(panic) => panic.downcast_ref::<&str>().map_or("unknown cause", |m| *m).to_string(),
+        };
+        ExchangeError::ThreadPanicked { thread: name, message }
+    })
+}
+
+pub struct BrokerSpec {
+    pub name: String,
+    pub client_preferences: HashMap<String, ClientPreference>,
+    pub config: BrokerConfig,
+    // Falls back to an RNG seeded from `config.seed`.
+    pub quantity_source: Option<Box<dyn RandomSource>>,
+}
+
+// Every stock's price as of one instant, read under a single lock.
+#[derive(Debug, Clone, Serialize)]
+pub struct MarketSnapshot {
+    pub stocks: Vec<Stock>,
+    // Time since the run started (since the exchange was created, before it
+    // starts), and ticks generated by then.
+    pub ts: Duration,
+    pub ticks: u64,
+}
+
+impl MarketSnapshot {
+    fn take(shared_stock: &Mutex<Vec<Stock>>, control: &SimulationControl) -> Self {
+        let stocks = shared_stock.lock().unwrap();
+        MarketSnapshot { stocks: stocks.clone(), ts: control.elapsed(), ticks: control.ticks() }
+    }
+
+    pub fn get(&self, name: &str) -> Option<&Stock> {
+        self.stocks.iter().find(|s| &*s.name == name)
+    }
+
+    pub fn fair_value(&self, name: &str) -> Option<i32> {
+        self.get(name)?.fair_value
+    }
+
+    // Price change of every stock from this snapshot to `other`, by ticker.
+    // A stock missing from either side counts as priced at zero there, so a
+    // newly listed stock shows its full price and a delisted one its negation.
+    pub fn diff(&self, other: &MarketSnapshot) -> Vec<(String, i32)> {
+        let mut prices: BTreeMap<String, (i32, i32)> = BTreeMap::new();
+        for stock in &self.stocks {
+            prices.entry(stock.name.to_string()).or_default().0 = stock.v;
+        }
+        for stock in &other.stocks {
+            prices.entry(stock.name.to_string()).or_default().1 = stock.v;
+        }
+        prices.into_iter().map(|(name, (before, after))| (name, after - before)).collect()
+    }
+}
+
+// An exchange with its listed stocks and brokers, ready to be started.
+pub struct StockExchange {
+    config: SimulationConfig,
+    stocks: Arc<Mutex<Vec<Stock>>>,
+    brokers: Vec<BrokerSpec>,
+    control: SimulationControl,
+    // Falls back to an RNG seeded from `config.seed`.
+    price_source: Option<Box<dyn RandomSource>>,
+    // Recorded prices to trade against instead of simulated ones.
+    replay: Option<Vec<PriceTick>>,
+}
+
+impl StockExchange {
+    pub fn new(config: SimulationConfig) -> Self {
+        StockExchange {
+            config,
+            stocks: Arc::new(Mutex::new(Vec::new())),
+            brokers: Vec::new(),
+            control: SimulationControl::new(),
+            price_source: None,
+            replay: None,
+        }
+    }
+
+    pub fn add_stock(&mut self, stock: Stock) {
+        self.stocks.lock().unwrap().push(stock);
+    }
+
+    pub fn snapshot(&self) -> MarketSnapshot {
+        MarketSnapshot::take(&self.stocks, &self.control)
+    }
+
+    pub fn add_broker(&mut self, name: &str, client_preferences: HashMap<String, ClientPreference>, config: BrokerConfig) {
+        self.brokers.push(BrokerSpec { name: name.to_string(), client_preferences, config, quantity_source: None });
+    }
+
+    pub fn set_price_source(&mut self, source: Box<dyn RandomSource>) {
+        self.price_source = Some(source);
+    }
+
+    // Trades against recorded prices, e.g. from `replay_prices_csv`, instead
+    // of simulated ones: each cycle plays back the ticks of the next `seq`,
+    // and the run ends once they run out. Tickers not yet listed are listed
+    // at their first recorded price.
+    pub fn replay(&mut self, ticks: Vec<PriceTick>) {
+        let mut stocks = self.stocks.lock().unwrap();
+        for tick in &ticks {
+            if !stocks.iter().any(|s| s.name == tick.stock.name) {
+                stocks.push(tick.stock.clone());
+            }
+        }
+        self.replay = Some(ticks);
+    }
+
+    // Returns false if no broker of that name has been added.
+    pub fn set_quantity_source(&mut self, broker_name: &str, source: Box<dyn RandomSource>) -> bool {
+        match self.brokers.iter_mut().find(|b| b.name == broker_name) {
+            Some(spec) => {
+                spec.quantity_source = Some(source);
+                true
+            }
+            None => false,
+        }
+    }
+
+    pub fn control(&self) -> SimulationControl {
+        self.control.clone()
+    }
+
+    // Each client must belong to exactly one broker, or its earnings would be
+    // counted twice in the report. An exchange with no stocks is rejected
+    // rather than started, since its brokers would never finish.
+    pub fn validate(&self) -> Result<(), StockError> {
+        self.config.validate()?;
+        let stocks = self.stocks.lock().unwrap();
+        if stocks.is_empty() {
+            return Err(ExchangeError::NoStocks.into());
+        }
+        let mut tickers = HashSet::new();
+        for stock in stocks.iter() {
+            if !tickers.insert(stock.name.clone()) {
+                return Err(ExchangeError::DuplicateTicker(stock.name.to_string()).into());
+            }
+        }
+        let mut seen = HashSet::new();
+        for spec in &self.brokers {
+            for (client, prefs) in &spec.client_preferences {
+                if !seen.insert(client) {
+                    return Err(ExchangeError::DuplicateClient(client.clone()).into());
+                }
+                self.config.check_scaled(&format!("the stop-loss cooldown of {}", client), prefs.stop_loss_cooldown)?;
+                if let Some((from, until)) = prefs.active_window {
+                    self.config.check_scaled(&format!("the active window of {}", client), from.max(until))?;
+                }
+                prefs
+                    .check()
+                    .map_err(|error| ExchangeError::InvalidClient { client: client.clone(), error })?;
+            }
+            if let Some(copy) = &spec.config.copy {
+                if !self.brokers.iter().any(|b| b.name == copy.leader) {
+                    return Err(ExchangeError::UnknownLeader(copy.leader.clone()).into());
+                }
+            }
+        }
+        Ok(())
+    }
+
+    pub fn start(self) -> Result<ExchangeHandle, StockError> {
+        self.validate()?;
+        let StockExchange { config, stocks, brokers, control, price_source, replay } = self;
+        // Order times, windows and the watchdog count from here rather than
+        // from when the exchange was built.
+        control.restart();
+        let sched = ScheduledThreadPool::builder()
+            .num_threads(5)
+            .thread_name_pattern("simulator-{}")
+            .build();
+        let opening = stocks.lock().unwrap().clone();
+        let shared_stock = stocks;
+        control.set_sector_limits(config.sector_transaction_limits.clone());
+        control.set_retention(config.retention);
+        control.set_throttles(
+            config
+                .stock_settings
+                .iter()
+                .filter_map(|(name, settings)| settings.throttle.map(|t| (name.clone(), t)))
+                .map(|(name, t)| (name, OrderThrottle { interval: config.scaled(t.interval), ..t }))
+                .collect(),
+        );
+
+        let watchdog = spawn_watchdog(
+            control.clone(),
+            config.scaled(config.watchdog_timeout),
+            config.watchdog_shutdown,
+            config.verbosity,
+        );
+
+        // Brokers draw from seeds just above `config.seed`; the jitter draws
+        // from one far away so it follows neither them nor the price path.
+        let mut jitter = seeded_rng(config.seed.map(|seed| seed ^ 0x9e37_79b9_7f4a_7c15));
+        let mut dispatcher = TickDispatcher::with_buffer(config.tick_buffer);
+        let broker_threads = brokers
+            .into_iter()
+            .enumerate()
+            .map(|(i, mut spec)| {
+                for prefs in spec.client_preferences.values_mut() {
+                    prefs.stop_loss_cooldown = config.scaled(prefs.stop_loss_cooldown);
+                    prefs.active_window = prefs.active_window.map(|(from, until)| (config.scaled(from), config.scaled(until)));
+                }
+                if spec.config.order_retention.is_none() {
+                    spec.config.order_retention = config.retention;
+                }
+                if spec.config.start_delay.is_none() && !config.broker_start_jitter.is_zero() {
+                    let max_micros = config.broker_start_jitter.as_micros().min(i32::MAX as u128) as i32;
+                    let delay = Duration::from_micros(jitter.delta(0..max_micros.max(1)) as u64);
+                    spec.config.start_delay = Some(config.scaled(delay));
+                }
+                if spec.config.seed.is_none() {
+                    spec.config.seed = config.seed.map(|seed| seed.wrapping_add(i as u64 + 1));
+                }
+                // A broker without clients is done as soon as it starts. It is
+                // left off the tick feed so that no ticks pile up for it.
+                let sel_r = if spec.client_preferences.is_empty() {
+                    crossbeam_channel::never()
+                } else {
+                    dispatcher.subscribe(&spec.name, broker_sectors(&spec.client_preferences))
+                };
+                let quantity_source = spec.quantity_source.unwrap_or_else(|| Box::new(seeded_rng(spec.config.seed)));
+                process_broker_actions(
+                    spec.name,
+                    sel_r,
+                    spec.client_preferences,
+                    config.transaction_limit,
+                    control.clone(),
+                    spec.config,
+                    quantity_source,
+                    config.verbosity,
+                )
+            })
+            .collect();
+
+        let price_source = price_source.unwrap_or_else(|| Box::new(seeded_rng(config.seed)));
+        let mut simulator = MarketSimulator::new(config.clone(), dispatcher, control.clone(), price_source);
+        if let Some(ticks) = replay {
+            simulator = simulator.with_replay(ticks);
+        }
+        schedule_simulator(&sched, shared_stock.clone(), simulator);
+
+        Ok(ExchangeHandle {
+            _sched: sched,
+            broker_threads,
+            watchdog,
+            shared_stock,
+            catalog: Mutex::new(StockCatalog::from_stocks(&opening)),
+            opening,
+            control,
+            config,
+        })
+    }
+
+    pub fn run(self) -> Result<ExchangeReport, StockError> {
+        self.start()?.join()
+    }
+}
+
+// A running exchange. Joining waits for every broker to finish.
+pub struct ExchangeHandle {
+    // Kept alive for as long as the simulator should keep ticking.
+    _sched: ScheduledThreadPool,
+    broker_threads: Vec<JoinHandle<BrokerResult>>,
+    watchdog: JoinHandle<bool>,
+    shared_stock: Arc<Mutex<Vec<Stock>>>,
+    opening: Vec<Stock>,
+    // Every stock listed during the run, including ones since delisted.
+    catalog: Mutex<StockCatalog>,
+    control: SimulationControl,
+    config: SimulationConfig,
+}
+
+impl ExchangeHandle {
+    pub fn control(&self) -> &SimulationControl {
+        &self.control
+    }
+
+    pub fn snapshot(&self) -> MarketSnapshot {
+        MarketSnapshot::take(&self.shared_stock, &self.control)
+    }
+
+    // Broker name -> whether that broker has finished.
+    pub fn completion_status(&self) -> BTreeMap<String, bool> {
+        self.control.completion_status()
+    }
+
+    // Shocks every stock in `sector` by `delta_bps` basis points on the next tick.
+    pub fn inject_news(&self, sector: StockType, delta_bps: i32) {
+        self.control.inject_news(sector, delta_bps);
+    }
+
+    // Lists a stock from the next tick on. Returns false if the ticker is
+    // already listed.
+    pub fn add_stock_live(&self, name: &str, price: i32, sector: StockType) -> bool {
+        let mut stocks = self.shared_stock.lock().unwrap();
+        if stocks.iter().any(|s| &*s.name == name) {
+            return false;
+        }
+        let stock = Stock::new(name, price).with_sector(sector);
+        self.catalog.lock().unwrap().insert(&stock);
+        stocks.push(stock);
+        true
+    }
+
+    // Stops ticking the stock. Returns it as of its last tick, if it was listed.
+    // The last stock listed stays, since a run without ticks never ends.
+    pub fn remove_stock_live(&self, name: &str) -> Option<Stock> {
+        let mut stocks = self.shared_stock.lock().unwrap();
+        let index = stocks.iter().position(|s| &*s.name == name).filter(|_| stocks.len() > 1)?;
+        Some(stocks.remove(index))
+    }
+
+    // Waits for every broker to finish. A broker that panicked fails the run
+    // once the others are done.
+    pub fn join(self) -> Result<ExchangeReport, StockError> {
+        let broker_results: Vec<_> = self.broker_threads.into_iter().map(join_thread).collect();
+
+        self.control.request_shutdown();
+        join_thread(self.watchdog)?;
+        let broker_results = broker_results.into_iter().collect::<Result<Vec<_>, _>>()?;
+
+        let duration = self.control.elapsed();
+        let closing = self.shared_stock.lock().unwrap().clone();
+        let mut report = ExchangeReport::new(broker_results, self.opening, closing, duration);
+        report.ticks = self.control.ticks();
+        report.price_history = self.control.price_history();
+        report.ohlc = self.control.ohlc();
+        report.candles = self.control.candles();
+        report.acks = self.control.acks().try_iter().collect();
+        report.tape = self.control.tape().try_iter().collect();
+        report.tape.sort_by_key(|entry| entry.ts);
+        report.catalog = self.catalog.into_inner().unwrap();
+        report.fx_rates = self.config.fx_rates.clone();
+        let verbosity = self.config.verbosity;
+        if let Err(discrepancies) = report.reconcile() {
+            if verbosity > Verbosity::Silent {
+                for discrepancy in discrepancies {
+                    println!("RECONCILIATION: {}", discrepancy);
+                }
+            }
+        }
+        if let Some((path, format)) = &self.config.report_file {
+            match report.write_to_file(path, *format) {
+                Ok(()) if verbosity >= Verbosity::Orders => println!("Report written to {}", path.display()),
+                Err(e) if verbosity > Verbosity::Silent => println!("{}", e),
+                _ => {}
+            }
+        }
+        Ok(report)
+    }
+}
@@ -0,0 +1,146 @@
+use std::io::Write;
+use std::sync::Arc;
+
+use arrow_array::{
+    ArrayRef, BooleanArray, Int32Array, Int64Array, ListArray, RecordBatch, StringArray, StructArray, UInt32Array,
+    UInt64Array,
+};
+use arrow_buffer::OffsetBuffer;
+use arrow_schema::{DataType, Field, Fields, Schema, SchemaRef};
+use parquet::arrow::ArrowWriter;
+
+use crate::error::StockError;
+use crate::stock::{Order, PriceTick};
+
+// Durations are stored as whole microseconds since the simulation started.
+fn micros(ts: std::time::Duration) -> i64 {
+    ts.as_micros().min(i64::MAX as u128) as i64
+}
+
+fn fill_fields() -> Fields {
+    Fields::from(vec![
+        Field::new("quantity", DataType::Int32, false),
+        Field::new("price", DataType::Int32, false),
+        Field::new("ts_micros", DataType::Int64, false),
+        Field::new("commission", DataType::Int64, false),
+        Field::new("liquidity", DataType::Utf8, false),
+        Field::new("fee", DataType::Int64, false),
+    ])
+}
+
+// One column per `Order` field. The reason is written as its display text.
+pub fn order_schema() -> SchemaRef {
+    Arc::new(Schema::new(vec![
+        Field::new("id", DataType::UInt64, false),
+        Field::new("client_name", DataType::Utf8, false),
+        Field::new("stock_name", DataType::Utf8, false),
+        Field::new("order_type", DataType::Utf8, false),
+        Field::new("quantity", DataType::Int32, false),
+        Field::new("price", DataType::Int32, false),
+        Field::new("prev_price", DataType::Int32, false),
+        Field::new("arrival_price", DataType::Int32, false),
+        Field::new("reason", DataType::Utf8, false),
+        Field::new("order_category", DataType::Utf8, false),
+        Field::new("clamped_from", DataType::Int32, true),
+        Field::new("ts_micros", DataType::Int64, false),
+        Field::new("filled_qty", DataType::Int32, false),
+        Field::new_list("fills", Field::new("item", DataType::Struct(fill_fields()), false), false),
+        Field::new_list("closes", Field::new("item", DataType::UInt64, false), false),
+        Field::new("fifo_pnl", DataType::Int64, false),
+        Field::new("paper", DataType::Boolean, false),
+    ]))
+}
+
+// One column per `PriceTick` field, with the stock's fields inlined.
+pub fn tick_schema() -> SchemaRef {
+    Arc::new(Schema::new(vec![
+        Field::new("name", DataType::Utf8, false),
+        Field::new("v", DataType::Int32, false),
+        Field::new("prev_v", DataType::Int32, false),
+        Field::new("display_name", DataType::Utf8, true),
+        Field::new("exchange", DataType::Utf8, true),
+        Field::new("fair_value", DataType::Int32, true),
+        Field::new("sector", DataType::Utf8, true),
+        Field::new("day", DataType::UInt32, false),
+        Field::new("seq", DataType::UInt64, false),
+    ]))
+}
+
+fn list_of(item: Field, lengths: impl IntoIterator<Item = usize>, values: ArrayRef) -> ListArray {
+    let offsets = OffsetBuffer::from_lengths(lengths);
+    ListArray::new(Arc::new(item), offsets, values, None)
+}
+
+pub fn orders_batch(orders: &[Order]) -> Result<RecordBatch, StockError> {
+    let fills: Vec<_> = orders.iter().flat_map(|o| &o.fills).collect();
+    let fill_columns: Vec<ArrayRef> = vec![
+        Arc::new(Int32Array::from_iter_values(fills.iter().map(|f| f.quantity))),
+        Arc::new(Int32Array::from_iter_values(fills.iter().map(|f| f.price))),
+        Arc::new(Int64Array::from_iter_values(fills.iter().map(|f| micros(f.ts)))),
+        Arc::new(Int64Array::from_iter_values(fills.iter().map(|f| f.commission))),
+        Arc::new(StringArray::from_iter_values(fills.iter().map(|f| format!("{:?}", f.liquidity)))),
+        Arc::new(Int64Array::from_iter_values(fills.iter().map(|f| f.fee))),
+    ];
+    let fill_values = StructArray::try_new(fill_fields(), fill_columns, None)?;
+    let closes = UInt64Array::from_iter_values(orders.iter().flat_map(|o| o.closes.iter().copied()));
+
+    let columns: Vec<ArrayRef> = vec![
+        Arc::new(UInt64Array::from_iter_values(orders.iter().map(|o| o.id))),
+        Arc::new(StringArray::from_iter_values(orders.iter().map(|o| &o.client_name))),
+        Arc::new(StringArray::from_iter_values(orders.iter().map(|o| &*o.stock_name))),
+        Arc::new(StringArray::from_iter_values(orders.iter().map(|o| &o.order_type))),
+        Arc::new(Int32Array::from_iter_values(orders.iter().map(|o| o.quantity))),
+        Arc::new(Int32Array::from_iter_values(orders.iter().map(|o| o.price))),
+        Arc::new(Int32Array::from_iter_values(orders.iter().map(|o| o.prev_price))),
+        Arc::new(Int32Array::from_iter_values(orders.iter().map(|o| o.arrival_price))),
+        Arc::new(StringArray::from_iter_values(orders.iter().map(|o| o.reason.to_string()))),
+        Arc::new(StringArray::from_iter_values(orders.iter().map(|o| &o.order_category))),
+        Arc::new(Int32Array::from_iter(orders.iter().map(|o| o.clamped_from))),
+        Arc::new(Int64Array::from_iter_values(orders.iter().map(|o| micros(o.ts)))),
+        Arc::new(Int32Array::from_iter_values(orders.iter().map(|o| o.filled_qty))),
+        Arc::new(list_of(
+            Field::new("item", DataType::Struct(fill_fields()), false),
+            orders.iter().map(|o| o.fills.len()),
+            Arc::new(fill_values),
+        )),
+        Arc::new(list_of(
+            Field::new("item", DataType::UInt64, false),
+            orders.iter().map(|o| o.closes.len()),
+            Arc::new(closes),
+        )),
+        Arc::new(Int64Array::from_iter_values(orders.iter().map(|o| o.fifo_pnl))),
+        Arc::new(BooleanArray::from_iter(orders.iter().map(|o| Some(o.paper)))),
+    ];
+    Ok(RecordBatch::try_new(order_schema(), columns)?)
+}
+
+pub fn ticks_batch(ticks: &[PriceTick]) -> Result<RecordBatch, StockError> {
+    let stocks = ticks.iter().map(|t| &t.stock);
+    let columns: Vec<ArrayRef> = vec![
+        Arc::new(StringArray::from_iter_values(stocks.clone().map(|s| &*s.name))),
+        Arc::new(Int32Array::from_iter_values(stocks.clone().map(|s| s.v))),
+        Arc::new(Int32Array::from_iter_values(stocks.clone().map(|s| s.prev_v))),
+        Arc::new(StringArray::from_iter(stocks.clone().map(|s| s.display_name.as_deref()))),
+        Arc::new(StringArray::from_iter(stocks.clone().map(|s| s.exchange.as_deref()))),
+        Arc::new(Int32Array::from_iter(stocks.clone().map(|s| s.fair_value))),
+        Arc::new(StringArray::from_iter(stocks.map(|s| s.sector.as_ref().map(|t| format!("{:?}", t))))),
+        Arc::new(UInt32Array::from_iter_values(ticks.iter().map(|t| t.day))),
+        Arc::new(UInt64Array::from_iter_values(ticks.iter().map(|t| t.seq))),
+    ];
+    Ok(RecordBatch::try_new(tick_schema(), columns)?)
+}
+
+fn write_batch<W: Write + Send>(batch: &RecordBatch, w: W) -> Result<(), StockError> {
+    let mut writer = ArrowWriter::try_new(w, batch.schema(), None)?;
+    writer.write(batch)?;
+    writer.close()?;
+    Ok(())
+}
+
+pub fn write_orders_parquet<W: Write + Send>(orders: &[Order], w: W) -> Result<(), StockError> {
+    write_batch(&orders_batch(orders)?, w)
+}
+
+pub fn write_ticks_parquet<W: Write + Send>(ticks: &[PriceTick], w: W) -> Result<(), StockError> {
+    write_batch(&ticks_batch(ticks)?, w)
+}
@@ -1,3 +1,6 @@
-pub mod stock;
-
-
+pub mod error;
+pub mod exchange;
+#[cfg(feature = "parquet")]
+pub mod export;
+pub mod report;
+pub mod stock;
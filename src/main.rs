@@ -1,5 +1,5 @@
 
-mod stock;
+use ngwaijie_tp066893::stock;
 
 
 
@@ -12,8 +12,6 @@ fn main() {
 
 
 
-use std::time::{Duration, Instant};
-use stock::run_simulation;
 
 //fn main() {
     //let duration = Duration::new(60, 0); 
@@ -0,0 +1,476 @@
+use std::cmp::Ordering;
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+use serde::Serialize;
+use thiserror::Error;
+
+use crate::error::StockError;
+use crate::exchange::ExchangeError;
+use crate::stock::{realized_vol, relative_change, BrokerMode, BrokerResult, Candle, Money, Ohlc, OrderAck, PositionMode, Stock, StockCatalog, TapeEntry, TradeStats};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Format {
+    Json,
+    Csv,
+}
+
+#[derive(Debug, Error)]
+pub enum ReportError {
+    #[error("failed to write report to {}: {source}", path.display())]
+    Io { path: PathBuf, source: io::Error },
+    #[error("failed to serialize report: {0}")]
+    Serialize(#[source] serde_json::Error),
+}
+
+// Names an account across the whole exchange. Every pooled broker has an
+// account named POOL_ACCOUNT, so those are qualified with the broker's name.
+fn account_key(broker: &BrokerResult, account: &str) -> String {
+    match broker.mode {
+        BrokerMode::Pooled => format!("{}/{}", broker.name, account),
+        BrokerMode::Individual => account.to_string(),
+    }
+}
+
+const CSV_HEADER: &str = "record,broker,client,stock,order_type,quantity,price,value,detail,ts_ms";
+
+fn csv_field(value: &str) -> String {
+    if value.contains(',') || value.contains('"') || value.contains('\n') {
+        format!("\"{}\"", value.replace('"', "\"\""))
+    } else {
+        value.to_string()
+    }
+}
+
+fn csv_row(fields: &[String]) -> String {
+    fields.iter().map(|f| csv_field(f)).collect::<Vec<_>>().join(",")
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize)]
+pub struct ClientPnl {
+    pub realized: Money,
+    // Open positions marked to the closing price.
+    pub unrealized: Money,
+}
+
+// An account whose cash and positions do not add up to its PnL: `net_worth`
+// is cash plus positions at closing prices less starting cash, `pnl` is
+// realized plus unrealized PnL less commissions and fees.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Error)]
+#[error("{broker}/{account} does not reconcile: net worth changed by {net_worth} but PnL is {pnl}")]
+pub struct Discrepancy {
+    pub broker: String,
+    pub account: String,
+    pub net_worth: Money,
+    pub pnl: Money,
+}
+
+#[derive(Debug, Serialize)]
+pub struct ExchangeReport {
+    pub brokers: Vec<BrokerResult>,
+    // Stock prices when the run started and when it ended.
+    pub opening: Vec<Stock>,
+    pub closing: Vec<Stock>,
+    pub duration: Duration,
+    pub ticks: u64,
+    pub catalog: StockCatalog,
+    pub ohlc: BTreeMap<String, Ohlc>,
+    // Empty unless `SimulationConfig::candle_interval` is set.
+    pub candles: BTreeMap<String, Vec<Candle>>,
+    // One per placed order.
+    pub acks: Vec<OrderAck>,
+    // Every trade of the run, oldest first.
+    pub tape: Vec<TapeEntry>,
+    // Copied from `SimulationConfig::fx_rates`.
+    pub fx_rates: HashMap<String, f64>,
+    // Each stock's price on every tick of the run.
+    #[serde(skip)]
+    pub price_history: BTreeMap<String, Vec<i32>>,
+}
+
+// Combines broker results from separate runs into one report. A client may
+// only appear in one of them, or its earnings would be counted twice. Each
+// stock opens at the first broker's opening price for it and closes at the
+// last broker's closing price.
+pub fn merge_broker_results(brokers: Vec<BrokerResult>) -> Result<ExchangeReport, StockError> {
+    let mut seen = HashSet::new();
+    for broker in &brokers {
+        let mut clients: HashSet<&str> = broker.client_volume.keys().map(String::as_str).collect();
+        clients.extend(broker.orders.iter().map(|o| o.client_name.as_str()));
+        if broker.mode == BrokerMode::Individual {
+            clients.extend(broker.accounts.keys().map(String::as_str));
+        }
+        for client in clients {
+            if !seen.insert(client) {
+                return Err(ExchangeError::DuplicateClient(client.to_string()).into());
+            }
+        }
+    }
+    let mut opening = BTreeMap::new();
+    let mut closing = BTreeMap::new();
+    for broker in &brokers {
+        for stock in &broker.opening {
+            opening.entry(stock.name.clone()).or_insert_with(|| stock.clone());
+        }
+        for stock in &broker.closing {
+            closing.insert(stock.name.clone(), stock.clone());
+        }
+    }
+    Ok(ExchangeReport::new(brokers, opening.into_values().collect(), closing.into_values().collect(), Duration::ZERO))
+}
+
+impl ExchangeReport {
+    pub fn new(brokers: Vec<BrokerResult>, opening: Vec<Stock>, closing: Vec<Stock>, duration: Duration) -> Self {
+        let catalog = StockCatalog::from_stocks(&opening);
+        ExchangeReport {
+            brokers,
+            opening,
+            closing,
+            duration,
+            ticks: 0,
+            catalog,
+            ohlc: BTreeMap::new(),
+            candles: BTreeMap::new(),
+            acks: Vec::new(),
+            tape: Vec::new(),
+            fx_rates: HashMap::new(),
+            price_history: BTreeMap::new(),
+        }
+    }
+
+    // Sample standard deviation of the stock's last `window` tick-to-tick
+    // returns. None until the stock has ticked `window + 1` times.
+    pub fn realized_vol(&self, name: &str, window: usize) -> Option<f64> {
+        let prices = self.price_history.get(name)?;
+        if window < 2 || prices.len() < window + 1 {
+            return None;
+        }
+        realized_vol(&prices[prices.len() - window - 1..])
+    }
+
+    // Mean FIFO PnL of the broker's sells over its sample standard deviation.
+    // None with fewer than two sells or when every sell made the same PnL,
+    // and for gross-mode brokers, whose sells close no lots.
+    pub fn broker_sharpe(&self, name: &str) -> Option<f64> {
+        let broker = self.brokers.iter().find(|b| b.name == name)?;
+        if broker.position_mode == PositionMode::Gross {
+            return None;
+        }
+        let pnls: Vec<f64> = broker
+            .orders
+            .iter()
+            .filter(|o| o.order_type == "selling" && o.filled_qty > 0)
+            .map(|o| o.fifo_pnl as f64)
+            .collect();
+        if pnls.len() < 2 {
+            return None;
+        }
+        let mean = pnls.iter().sum::<f64>() / pnls.len() as f64;
+        let variance = pnls.iter().map(|p| (p - mean).powi(2)).sum::<f64>() / (pnls.len() - 1) as f64;
+        if variance == 0.0 {
+            return None;
+        }
+        Some(mean / variance.sqrt())
+    }
+
+    // None unless the stock was listed at both the open and the close. A
+    // zero opening price counts as no change.
+    pub fn percent_change(&self, name: &str) -> Option<f64> {
+        let open = self.opening.iter().find(|s| &*s.name == name)?;
+        let close = self.closing.iter().find(|s| &*s.name == name)?;
+        Some(relative_change(open.v, close.v) * 100.0)
+    }
+
+    fn ranked_changes(&self) -> Vec<(String, f64)> {
+        let mut changes: Vec<(String, f64)> = self
+            .closing
+            .iter()
+            .filter_map(|s| self.percent_change(&s.name).map(|pct| (s.name.to_string(), pct)))
+            .collect();
+        changes.sort_by(|a, b| b.1.partial_cmp(&a.1).unwrap_or(Ordering::Equal).then_with(|| a.0.cmp(&b.0)));
+        changes
+    }
+
+    // Stocks with the largest percentage rise over the run, best first.
+    pub fn top_gainers(&self, n: usize) -> Vec<(String, f64)> {
+        self.ranked_changes().into_iter().take(n).collect()
+    }
+
+    // Stocks with the largest percentage fall over the run, worst first.
+    pub fn top_losers(&self, n: usize) -> Vec<(String, f64)> {
+        let mut changes = self.ranked_changes();
+        changes.sort_by(|a, b| a.1.partial_cmp(&b.1).unwrap_or(Ordering::Equal).then_with(|| a.0.cmp(&b.0)));
+        changes.into_iter().take(n).collect()
+    }
+
+    // Filled buy volume minus filled sell volume for the stock, across all
+    // brokers. Saturates at the ends of the i32 range.
+    pub fn order_flow_imbalance(&self, name: &str) -> i32 {
+        let stats = self.trade_stats(name);
+        (stats.bought - stats.sold).clamp(i32::MIN as i64, i32::MAX as i64) as i32
+    }
+
+    // The stock's fills summed across all brokers. Unaffected by order retention.
+    pub fn trade_stats(&self, name: &str) -> TradeStats {
+        let mut total = TradeStats::default();
+        for stats in self.brokers.iter().filter_map(|b| b.trades.get(name)) {
+            total.bought += stats.bought;
+            total.sold += stats.sold;
+            total.notional += stats.notional;
+        }
+        total
+    }
+
+    pub fn volume(&self, name: &str) -> i64 {
+        self.trade_stats(name).volume()
+    }
+
+    pub fn vwap(&self, name: &str) -> Option<f64> {
+        self.trade_stats(name).vwap()
+    }
+
+    // The stock's open, high, low and close over the whole run. None if it
+    // never ticked.
+    pub fn ohlc(&self, name: &str) -> Option<Ohlc> {
+        self.ohlc.get(name).copied()
+    }
+
+    pub fn candles(&self, name: &str) -> &[Candle] {
+        self.candles.get(name).map_or(&[], Vec::as_slice)
+    }
+
+    // The stock's fair value at the end of the run.
+    pub fn fair_value(&self, name: &str) -> Option<i32> {
+        self.closing.iter().find(|s| &*s.name == name)?.fair_value
+    }
+
+    pub fn closing_prices(&self) -> HashMap<String, i32> {
+        self.closing.iter().map(|s| (s.name.to_string(), s.v)).collect()
+    }
+
+    // Realized and unrealized PnL per account across all brokers.
+    pub fn client_pnl(&self) -> BTreeMap<String, ClientPnl> {
+        let prices = self.closing_prices();
+        self.brokers
+            .iter()
+            .flat_map(|b| b.accounts.iter().map(move |(name, account)| (account_key(b, name), account)))
+            .map(|(key, account)| {
+                let pnl = ClientPnl { realized: account.realized_pnl, unrealized: account.unrealized_pnl(&prices) };
+                (key, pnl)
+            })
+            .collect()
+    }
+
+    // Checks every account's cash and positions against its PnL, at closing
+    // prices. Returns each account that does not balance.
+    pub fn reconcile(&self) -> Result<(), Vec<Discrepancy>> {
+        let prices = self.closing_prices();
+        let discrepancies: Vec<Discrepancy> = self
+            .brokers
+            .iter()
+            .flat_map(|b| b.accounts.iter().map(move |(name, account)| (b, name, account)))
+            .filter_map(|(broker, name, account)| {
+                let net_worth = account.cash + account.market_value(&prices) - account.funded;
+                let pnl = account.realized_pnl + account.unrealized_pnl(&prices) - account.commissions - account.fees;
+                (net_worth != pnl).then(|| Discrepancy {
+                    broker: broker.name.clone(),
+                    account: name.clone(),
+                    net_worth,
+                    pnl,
+                })
+            })
+            .collect();
+        if discrepancies.is_empty() {
+            Ok(())
+        } else {
+            Err(discrepancies)
+        }
+    }
+
+    // Realized PnL per stock, summed over every account.
+    pub fn pnl_by_stock(&self) -> HashMap<String, Money> {
+        let mut totals = HashMap::new();
+        for account in self.brokers.iter().flat_map(|b| b.accounts.values()) {
+            for (stock, &pnl) in &account.realized_by_stock {
+                let total: &mut Money = totals.entry(stock.clone()).or_insert(0);
+                *total = total.saturating_add(pnl);
+            }
+        }
+        totals
+    }
+
+    // Mean slippage against arrival price over each client's filled orders.
+    pub fn client_slippage(&self) -> BTreeMap<String, f64> {
+        let mut totals: BTreeMap<String, (f64, usize)> = BTreeMap::new();
+        for order in self.brokers.iter().flat_map(|b| &b.orders) {
+            if let Some(slippage) = order.slippage() {
+                let total = totals.entry(order.client_name.clone()).or_default();
+                total.0 += slippage;
+                total.1 += 1;
+            }
+        }
+        totals.into_iter().map(|(client, (sum, count))| (client, sum / count as f64)).collect()
+    }
+
+    // Earnings per account across all brokers, keyed as in `leaderboard`.
+    pub fn earnings(&self) -> HashMap<String, Money> {
+        self.brokers
+            .iter()
+            .flat_map(|b| b.accounts.iter().map(move |(name, account)| (account_key(b, name), account.earnings)))
+            .collect()
+    }
+
+    // Earnings converted into `currency`. None if no rate is configured for it.
+    pub fn earnings_in(&self, currency: &str) -> Option<HashMap<String, f64>> {
+        let rate = *self.fx_rates.get(currency)?;
+        Some(self.earnings().into_iter().map(|(client, earnings)| (client, earnings as f64 * rate)).collect())
+    }
+
+    // Every account's earnings across all brokers, highest first and by name
+    // on ties. Pooled brokers appear as "<broker>/Pool".
+    pub fn leaderboard(&self) -> Vec<(String, Money)> {
+        let mut board: Vec<(String, Money)> = self.earnings().into_iter().collect();
+        board.sort_by(|a, b| b.1.cmp(&a.1).then_with(|| a.0.cmp(&b.0)));
+        board
+    }
+
+    // Realized earnings across all brokers, per zero-based trading day.
+    pub fn daily_earnings(&self) -> BTreeMap<u32, Money> {
+        let mut totals = BTreeMap::new();
+        for broker in &self.brokers {
+            for (day, earnings) in broker.daily_earnings() {
+                let total: &mut Money = totals.entry(day).or_insert(0);
+                *total = total.saturating_add(earnings);
+            }
+        }
+        totals
+    }
+
+    pub fn to_json(&self) -> Result<String, StockError> {
+        Ok(serde_json::to_string_pretty(self).map_err(ReportError::Serialize)?)
+    }
+
+    // One row per client earnings total, open position and placed order.
+    pub fn to_csv(&self) -> String {
+        let mut lines = vec![CSV_HEADER.to_string()];
+        for broker in &self.brokers {
+            let mut accounts: Vec<_> = broker.accounts.iter().collect();
+            accounts.sort_by(|a, b| a.0.cmp(b.0));
+            for (client, account) in &accounts {
+                lines.push(csv_row(&[
+                    "earnings".to_string(),
+                    broker.name.clone(),
+                    client.to_string(),
+                    String::new(),
+                    String::new(),
+                    String::new(),
+                    String::new(),
+                    account.earnings.to_string(),
+                    String::new(),
+                    String::new(),
+                ]));
+            }
+            for (client, account) in &accounts {
+                let mut positions: Vec<_> = account.positions.iter().filter(|(_, q)| **q != 0).collect();
+                positions.sort();
+                for (stock, quantity) in positions {
+                    lines.push(csv_row(&[
+                        "position".to_string(),
+                        broker.name.clone(),
+                        client.to_string(),
+                        stock.clone(),
+                        String::new(),
+                        quantity.to_string(),
+                        account.average_price(stock).map(|p| p.to_string()).unwrap_or_default(),
+                        account.cost.get(stock).copied().unwrap_or(0).to_string(),
+                        String::new(),
+                        String::new(),
+                    ]));
+                }
+            }
+            for order in &broker.orders {
+                lines.push(csv_row(&[
+                    "order".to_string(),
+                    broker.name.clone(),
+                    order.client_name.clone(),
+                    order.stock_name.to_string(),
+                    order.order_type.clone(),
+                    order.quantity.to_string(),
+                    order.price.to_string(),
+                    (order.quantity as Money * order.price as Money).to_string(),
+                    order.reason.to_string(),
+                    order.ts.as_millis().to_string(),
+                ]));
+            }
+        }
+        lines.push(String::new());
+        lines.join("\n")
+    }
+
+    pub fn write_to_file<P: AsRef<Path>>(&self, path: P, format: Format) -> Result<(), StockError> {
+        let path = path.as_ref();
+        let contents = match format {
+            Format::Json => self.to_json()?,
+            Format::Csv => self.to_csv(),
+        };
+        Ok(fs::write(path, contents).map_err(|source| ReportError::Io { path: path.to_path_buf(), source })?)
+    }
+
+    pub fn print(&self) {
+        println!("Simulation ended. It took: {:?}", self.duration);
+        println!("Ticks generated: {}", self.ticks);
+
+        // Final report
+        println!("Final report:");
+        for result in &self.brokers {
+            match result.mode {
+                BrokerMode::Individual => println!("{} earnings:", result.name),
+                BrokerMode::Pooled => println!("{} pooled earnings:", result.name),
+            }
+            for (client, earnings) in result.earnings() {
+                println!("{} earned ${}", client, earnings);
+            }
+            for (client, account) in &result.accounts {
+                if account.commissions > 0 {
+                    println!("{} paid ${} in commission", client, account.commissions);
+                }
+                if account.fees != 0 {
+                    println!("{} paid ${} in exchange fees net of rebates", client, account.fees);
+                }
+            }
+        }
+
+        println!("Realized and unrealized PnL:");
+        for (client, pnl) in self.client_pnl() {
+            println!("{} realized ${}, unrealized ${}", client, pnl.realized, pnl.unrealized);
+        }
+
+        let slippage = self.client_slippage();
+        if slippage.values().any(|&s| s != 0.0) {
+            println!("Average slippage vs arrival price:");
+            for (client, slippage) in slippage {
+                println!("{} {:+.2}", client, slippage);
+            }
+        }
+
+        let daily = self.daily_earnings();
+        if daily.len() > 1 {
+            println!("Earnings per day:");
+            for (day, earnings) in daily {
+                println!("Day {} earned ${}", day + 1, earnings);
+            }
+        }
+
+        println!("Top gainers:");
+        for (name, pct) in self.top_gainers(3) {
+            println!("{} {:+.2}%", name, pct);
+        }
+        println!("Top losers:");
+        for (name, pct) in self.top_losers(3) {
+            println!("{} {:+.2}%", name, pct);
+        }
+    }
+}
@@ -1,279 +1,3482 @@
-use std::sync::{Arc, Mutex};
-use std::thread::{self, JoinHandle};
-use std::time::{Duration, Instant};
-use std::collections::HashMap;
-use crossbeam_channel::unbounded;
-use rand::Rng;
-use scheduled_thread_pool::ScheduledThreadPool;
-
-#[derive(Debug, Clone)]
-pub struct Stock {
-    pub name: String,
-    pub v: i32,
-    pub prev_v: i32,
-}
-
-#[derive(Debug)]
-pub struct Order {
-    pub stock_name: String,
-    pub order_type: String,
-    pub quantity: i32,
-    pub price: i32,
-    pub prev_price: i32,
-    pub reason: String,
-    pub order_category: String,
-}
-
-impl Order {
-    fn new(stock_name: String, order_type: String, quantity: i32, price: i32, prev_price: i32, reason: String, order_category: String) -> Self {
-        Order {
-            stock_name,
-            order_type,
-            quantity,
-            price,
-            prev_price,
-            reason,
-            order_category,
-        }
-    }
-}
-
-#[derive(Debug, Clone, PartialEq, Eq, Hash)]
-pub enum StockType {
-    Tech,
-    Food,
-    Healthcare,
-}
-
-impl Stock {
-    pub fn stock_type(&self) -> StockType {
-        match self.name.as_str() {
-            "AAPL" | "AMZN" | "GOOGL" | "MSFT" | "TSLA" | "FB" | "CRM" | "INTC" | "NVDA" |"WORK" | "FSLY" | "CRWD" |
-            "DOCU" => StockType::Tech,
-            "KO" | "PEP" | "MCD" | "SBUX" | "GIS" | "HSY" | "KR" | "CPB" | "WMT" |"TGT" | "COST" | "PG" | "UN" | "SYY" |
-            "FLO" | "WBA" => StockType::Food,
-            "MDLZ" | "MRK" | "AMGN" | "UNH" | "HCA" | "ANTM" |"DHR" | "ABT" |"TMO" |"REGN" | "ILMN" | "MDT" | "ZBH" | "VRTX" |
-            "IDXX" |"DGX"  => StockType::Healthcare,
-            _ => unreachable!(),
-        }
-    }
-}
-
-pub fn simulate_stock_changes(sched: &ScheduledThreadPool, shared_stock: Arc<Mutex<Vec<Stock>>>, stock_sel: crossbeam_channel::Sender<Stock>) {
-    sched.execute_at_fixed_rate(
-        Duration::from_micros(100),
-        Duration::from_secs(1),
-        move || {
-            let mut rng = rand::thread_rng();
-            let mut stocks = shared_stock.lock().unwrap();
-
-            for stock in stocks.iter_mut() {
-                stock.prev_v = stock.v;
-                stock.v += rng.gen_range(-40..=60);
-                println!("STOCK UPDATE: name: {}, v:{}", stock.name, stock.v);
-
-                if stock_sel.send(stock.clone()).is_err() {
-                    break;
-                }
-            }
-        },
-    );
-}
-
-pub fn process_broker_actions(
-    name: String,
-    broker_counts: Arc<HashMap<String, Mutex<i32>>>,
-    sel_r: crossbeam_channel::Receiver<Stock>,
-    client_preferences: HashMap<String, (StockType, String, i32, i32)>,
-    transaction_limit: i32,
-) -> JoinHandle<HashMap<String, i32>> {
-    thread::spawn(move || {
-        let mut client_transactions: HashMap<String, i32> = client_preferences.keys().map
-        (|k| (k.clone(), 0)).collect();
-        let mut client_earnings: HashMap<String, i32> = HashMap::new();
-
-        while client_transactions.values().any(|&v| v < transaction_limit) {
-            let stock = match sel_r.recv() {
-                Ok(stock) => stock,
-                Err(_) => break,
-            };
-
-            let price_change = stock.v - stock.prev_v;
-            for (client_name, (stock_type, order_category, 
-                min_change_buy, min_change_sell)) in &client_preferences {
-                if stock.stock_type() != *stock_type || (*order_category != "Market" && 
-                (price_change > -*min_change_buy && price_change < *min_change_sell)) {
-                    continue;
-                }
-
-                let mut process_order = false;
-                let mut order_type = "buying"; 
-                let mut reason = String::new();
-                if (*order_category == "Market" || price_change <= -*min_change_buy) && stock.v < stock.prev_v {
-                    process_order = true;
-                    reason = format!("Executed a buy due to price decrease to {}", stock.v);
-                } else if (*order_category == "Market" || price_change >= *min_change_sell) && stock.v > stock.prev_v {
-                    process_order = true;
-                    order_type = "selling";
-                    reason = format!("Executed a sell due to price increase to {}", stock.v);
-                }
-
-                if process_order {
-                    let quantity = rand::thread_rng().gen_range(10..=100);
-
-                    let order = Order::new(
-                        stock.name.clone(),
-                        order_type.to_string(),
-                        quantity,
-                        stock.v,
-                        stock.prev_v,
-                        reason,
-                        order_category.to_string(),
-                    );
-
-                    if order_type == "selling" {
-                        let earnings = quantity * (stock.v - stock.prev_v);
-                        let client_earning = client_earnings.entry(client_name.clone()).or_insert(0);
-                        *client_earning += earnings;
-                    }
-
-                    println!("{} for client {} placed a {} stock: {:?}", name, client_name, order_type, order);
-
-                    let count = client_transactions.entry(client_name.clone()).or_insert(0);
-                    *count += 1;
-
-                    if *count >= transaction_limit {
-                        continue;
-                    }
-                }
-            }
-        }
-
-        println!("{} has completed the transactions for all clients.", name);
-        client_earnings
-    })
-}
-
-
-
-
-
-pub fn run_simulation() {
-    println!("Stock updates from Bursa Malaysia...");
-    let start = Instant::now();
-    let sched = ScheduledThreadPool::new(5);
-    let (sel_s, sel_r) = unbounded::<Stock>();
-    let shared_stock = Arc::new(Mutex::new(vec![
-        Stock { name: "AMZN".to_string(), v: 200, prev_v: 200 },
-        Stock { name: "GOOGL".to_string(), v: 120, prev_v: 120 },
-        Stock { name: "MSFT".to_string(), v: 130, prev_v: 130 },
-        Stock { name: "TSLA".to_string(), v: 300, prev_v: 300 },
-        Stock { name: "FB".to_string(), v: 156, prev_v: 156 },
-        Stock { name: "CRM".to_string(), v: 90, prev_v: 90 },
-        Stock { name: "INTC".to_string(), v: 245, prev_v: 245 },
-        Stock { name: "NVDA".to_string(), v: 187, prev_v: 187 },
-        Stock { name: "WORK".to_string(), v: 65, prev_v: 65 },
-        Stock { name: "FSLY".to_string(), v: 110, prev_v: 110 },
-        Stock { name: "CRWD".to_string(), v: 125, prev_v: 125 },
-        Stock { name: "DOCU".to_string(), v: 240, prev_v: 240 },
-        Stock { name: "NOW".to_string(), v: 180, prev_v: 180 },
-        Stock { name: "PLTR".to_string(), v: 95, prev_v: 95 },
-        Stock { name: "KO".to_string(), v: 310, prev_v: 310 },
-        Stock { name: "PEP".to_string(), v: 400, prev_v: 400 },
-        Stock { name: "MCD".to_string(), v: 170, prev_v: 170 },
-        Stock { name: "SBUX".to_string(), v: 200, prev_v: 200 },
-        Stock { name: "GIS".to_string(), v: 67, prev_v: 67 },
-        Stock { name: "HSY".to_string(), v: 276, prev_v: 276 },
-        Stock { name: "KR".to_string(), v: 22, prev_v: 22 },
-        Stock { name: "CPB".to_string(), v: 120, prev_v: 120 },
-        Stock { name: "PER".to_string(), v: 400, prev_v: 400 },
-        Stock { name: "WMT".to_string(), v: 150, prev_v: 150 },
-        Stock { name: "TGT".to_string(), v: 90, prev_v: 90 },
-        Stock { name: "COST".to_string(), v: 280, prev_v: 280 },
-        Stock { name: "PG".to_string(), v: 200, prev_v: 200 },
-        Stock { name: "UN".to_string(), v: 170, prev_v: 170 },
-        Stock { name: "SYY".to_string(), v: 110, prev_v: 110 },
-        Stock { name: "FLO".to_string(), v: 30, prev_v: 30 },
-        Stock { name: "WBA".to_string(), v: 55, prev_v: 55 },
-        Stock { name: "MDLZ".to_string(), v: 330, prev_v: 330 },
-        Stock { name: "MRK".to_string(), v: 280, prev_v: 280 },
-        Stock { name: "AMGN".to_string(), v: 430, prev_v: 430 },
-        Stock { name: "UNH".to_string(), v: 120, prev_v: 120 },
-        Stock { name: "HCA".to_string(), v: 88, prev_v: 88 },
-        Stock { name: "ANTM".to_string(), v: 22, prev_v: 22 },
-        Stock { name: "DHR".to_string(), v: 120, prev_v: 120 },
-        Stock { name: "ABT".to_string(), v: 400, prev_v: 400 },
-        Stock { name: "TMO".to_string(), v: 150, prev_v: 150 },
-        Stock { name: "REGN".to_string(), v: 90, prev_v: 90 },
-        Stock { name: "ILMN".to_string(), v: 280, prev_v: 280 },
-        Stock { name: "MDT".to_string(), v: 200, prev_v: 200 },
-        Stock { name: "ZBH".to_string(), v: 170, prev_v: 170 },
-        Stock { name: "VRTX".to_string(), v: 110, prev_v: 110 },
-        Stock { name: "IDXX".to_string(), v: 30, prev_v: 30 },
-        Stock { name: "DGX".to_string(), v: 55, prev_v: 55 },
-        
-    ]));
-
-    let broker_count = Arc::new(HashMap::new());
-
-    simulate_stock_changes(&sched, shared_stock.clone(), sel_s);
-
-    let transaction_limit = 10; 
-
-    let client_preferences_broker1 = HashMap::from([
-        ("John".to_string(), (StockType::Tech, "Market".to_string(), 0, 0)),
-        ("Peter".to_string(), (StockType::Tech, "Market".to_string(), 0, 0)),
-    ]);
-
-    let client_preferences_broker2 = HashMap::from([
-        ("James".to_string(), (StockType::Food, "Limit".to_string(), 25, 40)),
-    ]);
-
-    let client_preferences_broker3 = HashMap::from([
-        ("Alex".to_string(), (StockType::Healthcare, "Limit".to_string(), 10, 30)),
-        ("Mike".to_string(), (StockType::Tech, "Market".to_string(), 0, 0)),
-    ]);
-
-    let broker1_thread = process_broker_actions(
-        "Broker 1".to_string(), broker_count.clone(), sel_r.clone(), client_preferences_broker1, transaction_limit
-    );
-    let broker2_thread = process_broker_actions(
-        "Broker 2".to_string(), broker_count.clone(), sel_r.clone(), client_preferences_broker2, transaction_limit
-    );
-    let broker3_thread = process_broker_actions(
-        "Broker 3".to_string(), broker_count, sel_r, client_preferences_broker3, transaction_limit
-    );
-
-    let earnings_broker1 = broker1_thread.join().unwrap();
-    let earnings_broker2 = broker2_thread.join().unwrap();
-    let earnings_broker3 = broker3_thread.join().unwrap();
-
-    let duration = Instant::now() - start;
-    println!("Simulation ended. It took: {:?}", duration);
-
-    // Final report
-    println!("Final report:");
-    println!("Broker 1 earnings:");
-    for (client, earnings) in earnings_broker1 {
-        println!("{} earned ${}", client, earnings);
-    }
-    println!("Broker 2 earnings:");
-    for (client, earnings) in earnings_broker2 {
-        println!("{} earned ${}", client, earnings);
-    }
-    println!("Broker 3 earnings:");
-    for (client, earnings) in earnings_broker3 {
-        println!("{} earned ${}", client, earnings);
-    }
-}
-
-extern crate bma_benchmark;
-use  bma_benchmark::{benchmark, staged_benchmark, staged_benchmark_print_for};
-use core::hint::black_box;
-
-pub fn benchmarkmarco() {
-    staged_benchmark!("simulation", 30, {
-        black_box(run_simulation());
-    });
-    staged_benchmark_print_for!("simulation")
-}
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant};
+use std::collections::{BTreeMap, BTreeSet, HashMap, HashSet, VecDeque};
+use std::fmt;
+use std::ops::{Range, RangeInclusive};
+use std::path::PathBuf;
+use crossbeam_channel::unbounded;
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
+use scheduled_thread_pool::ScheduledThreadPool;
+use serde::{Deserialize, Serialize};
+use thiserror::Error;
+
+use crate::error::StockError;
+use crate::exchange::StockExchange;
+use crate::report::{ExchangeReport, Format};
+
+// Tickers are shared rather than copied: every tick and order for a stock
+// points at the allocation made when the stock was listed.
+pub type Ticker = Arc<str>;
+
+// Cash, earnings, costs and commissions. Prices and quantities are `i32`, so
+// any single quantity * price fits with room to spare; totals stay exact up
+// to about ±9.2e18.
+pub type Money = i64;
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Stock {
+    pub name: Ticker,
+    pub v: i32,
+    pub prev_v: i32,
+    // Descriptive only; classification and matching are keyed on `name`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub display_name: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub exchange: Option<String>,
+    // What the stock is "really" worth. Drifts slowly while the simulation
+    // runs; value-investing clients trade on the gap to it.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub fair_value: Option<i32>,
+    // Overrides the sector looked up from the ticker, e.g. for stocks listed mid-run.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub sector: Option<StockType>,
+}
+
+// One stock's price update as sent from the simulator to the brokers.
+#[derive(Debug, Clone)]
+pub struct PriceTick {
+    pub stock: Stock,
+    // Zero-based trading day and the simulator cycle that produced the tick.
+    pub day: u32,
+    pub seq: u64,
+}
+
+// Stocks rank by current price, with the ticker as tiebreak.
+impl PartialEq for Stock {
+    fn eq(&self, other: &Self) -> bool {
+        self.v == other.v && self.name == other.name
+    }
+}
+
+impl Eq for Stock {}
+
+impl PartialOrd for Stock {
+    fn partial_cmp(&self, other: &Self) -> Option<std::cmp::Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for Stock {
+    fn cmp(&self, other: &Self) -> std::cmp::Ordering {
+        self.v.cmp(&other.v).then_with(|| self.name.cmp(&other.name))
+    }
+}
+
+#[derive(Debug, Serialize)]
+pub struct Order {
+    // Unique across the exchange, in the order orders were placed.
+    pub id: u64,
+    pub client_name: String,
+    pub stock_name: Ticker,
+    pub order_type: String,
+    pub quantity: i32,
+    pub price: i32,
+    pub prev_price: i32,
+    // The tick price when the signal fired, kept apart from the fills as the
+    // benchmark for slippage.
+    pub arrival_price: i32,
+    pub reason: OrderReason,
+    pub order_category: String,
+    // Set when a sell was reduced to the quantity actually held.
+    pub clamped_from: Option<i32>,
+    // Time since the simulation started when the order was placed.
+    pub ts: Duration,
+    // `quantity` is the total ordered; limit orders may fill in several pieces.
+    pub filled_qty: i32,
+    pub fills: Vec<Fill>,
+    // For sells, the buy orders whose shares were sold, matched first in,
+    // first out, and the PnL against those buys' prices.
+    pub closes: Vec<u64>,
+    pub fifo_pnl: Money,
+    // Placed by a paper-trading client.
+    pub paper: bool,
+}
+
+// How much of an order filled when it was placed.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+pub enum AckStatus {
+    Filled,
+    PartiallyFilled,
+    // A limit order that filled nothing yet and rests for later ticks.
+    Resting,
+    // Nothing filled and nothing rests, e.g. a copied sell with no shares held.
+    Rejected,
+}
+
+// Sent once for every placed order, as soon as it has been placed.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct OrderAck {
+    pub id: u64,
+    pub broker: String,
+    pub status: AckStatus,
+}
+
+// One executed order on the consolidated tape: the shares it filled at
+// their average price, timed at its last fill. An order that fills in pieces
+// shows up once, when it stops filling.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct TapeEntry {
+    // Time since the simulation started.
+    pub ts: Duration,
+    pub stock: Ticker,
+    pub price: i32,
+    pub qty: i32,
+    // "buying" or "selling", as on the order.
+    pub side: String,
+}
+
+// Shares still held from one buy order.
+#[derive(Debug, Clone, Serialize)]
+pub struct Lot {
+    pub order_id: u64,
+    pub quantity: i32,
+    pub price: i32,
+}
+
+// Makers' orders had been resting on the book when they filled; takers'
+// filled on arrival.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+pub enum LiquiditySide {
+    Maker,
+    Taker,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct Fill {
+    pub quantity: i32,
+    pub price: i32,
+    pub ts: Duration,
+    pub commission: Money,
+    pub liquidity: LiquiditySide,
+    // The taker fee, or the maker rebate as a negative fee.
+    pub fee: Money,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub enum SkipReason {
+    InsufficientHoldings { held: i32, requested: i32 },
+    StopLossCooldown { remaining: Duration },
+    ExposureLimit { exposure: Money, limit: Money },
+    BelowMinNotional { notional: Money, min: Money },
+    BelowLotSize { quantity: i32, lot_size: i32 },
+    InsufficientCash { needed: Money, available: Money },
+    SectorLimit { sector: StockType, limit: i32 },
+    DailyLossLimit { loss: Money, limit: Money },
+    FatFinger { price: i32, last: i32, max_bps: i32 },
+    Throttled { max_orders: usize, interval: Duration },
+}
+
+impl fmt::Display for SkipReason {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            SkipReason::InsufficientHoldings { held, requested } => {
+                write!(f, "Skipped a sell of {} shares with only {} held", requested, held)
+            }
+            SkipReason::StopLossCooldown { remaining } => {
+                write!(f, "Skipped a buy during stop-loss cooldown, {:?} remaining", remaining)
+            }
+            SkipReason::ExposureLimit { exposure, limit } => {
+                write!(f, "Skipped a buy with broker exposure at {} of a {} limit", exposure, limit)
+            }
+            SkipReason::BelowMinNotional { notional, min } => {
+                write!(f, "Skipped an order worth {} below the minimum notional of {}", notional, min)
+            }
+            SkipReason::BelowLotSize { quantity, lot_size } => {
+                write!(f, "Skipped an order of {} shares below the lot size of {}", quantity, lot_size)
+            }
+            SkipReason::InsufficientCash { needed, available } => {
+                write!(f, "Skipped a buy costing {} with only {} cash available", needed, available)
+            }
+            SkipReason::SectorLimit { sector, limit } => {
+                write!(f, "Skipped an order with {:?} trades at the sector limit of {}", sector, limit)
+            }
+            SkipReason::DailyLossLimit { loss, limit } => {
+                write!(f, "Skipped a buy after losing {} today, over the daily limit of {}", loss, limit)
+            }
+            SkipReason::FatFinger { price, last, max_bps } => {
+                write!(f, "Rejected an order at {}, more than {} bps from the last traded price of {}", price, max_bps, last)
+            }
+            SkipReason::Throttled { max_orders, interval } => {
+                write!(f, "Skipped an order over the stock's throttle of {} orders per {:?}", max_orders, interval)
+            }
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct SkippedOrder {
+    pub client_name: String,
+    pub stock_name: Ticker,
+    pub order_type: String,
+    pub quantity: i32,
+    pub reason: SkipReason,
+}
+
+// Side of the signal recorded for a flat tick.
+pub const NO_CHANGE: &str = "no change";
+
+// Every buy or sell signal a client acted on or had to pass up.
+#[derive(Debug, Clone, Serialize)]
+pub struct Signal {
+    pub client: String,
+    pub stock: Ticker,
+    pub side: String,
+    // False for orders skipped before placing and for ones the ack rejected.
+    pub executed: bool,
+    pub skip_reason: Option<String>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub enum OrderReason {
+    PriceDecrease { to: i32 },
+    PriceIncrease { to: i32 },
+    StopLoss,
+    TrailingStop { high: i32 },
+    TakeProfit,
+    Copied { leader_order: u64 },
+    BelowFairValue { fair_value: i32 },
+    AboveFairValue { fair_value: i32 },
+    // The pair's price ratio, in basis points, when it diverged or reverted.
+    PairDiverged { ratio_bps: i64 },
+    PairReverted { ratio_bps: i64 },
+    // The number of consecutive ticks the stock had moved in one direction.
+    RisingStreak { ticks: u32 },
+    FallingStreak { ticks: u32 },
+    // Equity had fallen this far from its peak.
+    Drawdown { drawdown: Money },
+}
+
+impl fmt::Display for OrderReason {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            OrderReason::PriceDecrease { to } => write!(f, "Executed a buy due to price decrease to {}", to),
+            OrderReason::PriceIncrease { to } => write!(f, "Executed a sell due to price increase to {}", to),
+            OrderReason::StopLoss => write!(f, "Executed a sell due to stop-loss"),
+            OrderReason::TrailingStop { high } => write!(f, "Executed a sell due to trailing stop from a high of {}", high),
+            OrderReason::TakeProfit => write!(f, "Executed a sell due to take-profit"),
+            OrderReason::Copied { leader_order } => write!(f, "Executed a copy of leader order {}", leader_order),
+            OrderReason::BelowFairValue { fair_value } => {
+                write!(f, "Executed a buy due to price below fair value of {}", fair_value)
+            }
+            OrderReason::AboveFairValue { fair_value } => {
+                write!(f, "Executed a sell due to price above fair value of {}", fair_value)
+            }
+            OrderReason::PairDiverged { ratio_bps } => {
+                write!(f, "Executed a pair trade on the ratio diverging to {} bps", ratio_bps)
+            }
+            OrderReason::PairReverted { ratio_bps } => {
+                write!(f, "Unwound a pair trade on the ratio reverting to {} bps", ratio_bps)
+            }
+            OrderReason::RisingStreak { ticks } => write!(f, "Executed after {} consecutive rising ticks", ticks),
+            OrderReason::FallingStreak { ticks } => write!(f, "Executed after {} consecutive falling ticks", ticks),
+            OrderReason::Drawdown { drawdown } => write!(f, "Executed a sell to liquidate after a drawdown of ${}", drawdown),
+        }
+    }
+}
+
+impl Order {
+    #[allow(clippy::too_many_arguments)]
+    fn new(id: u64, client_name: String, stock_name: Ticker, order_type: String, quantity: i32, price: i32, prev_price: i32, reason: OrderReason, order_category: String, ts: Duration) -> Self {
+        Order {
+            id,
+            client_name,
+            stock_name,
+            order_type,
+            quantity,
+            price,
+            prev_price,
+            arrival_price: price,
+            reason,
+            order_category,
+            clamped_from: None,
+            ts,
+            filled_qty: 0,
+            fills: Vec::new(),
+            closes: Vec::new(),
+            fifo_pnl: 0,
+            paper: false,
+        }
+    }
+
+    pub fn remaining(&self) -> i32 {
+        self.quantity - self.filled_qty
+    }
+
+    pub fn average_fill_price(&self) -> Option<f64> {
+        if self.filled_qty == 0 {
+            return None;
+        }
+        let notional: i64 = self.fills.iter().map(|f| f.quantity as i64 * f.price as i64).sum();
+        Some(notional as f64 / self.filled_qty as f64)
+    }
+
+    // Average fill price less the arrival price. Unfilled orders have none.
+    pub fn slippage(&self) -> Option<f64> {
+        Some(self.average_fill_price()? - self.arrival_price as f64)
+    }
+
+    // Limit orders placed on a price signal rest until filled; stop exits and
+    // market orders fill straight away.
+    pub fn is_resting(&self) -> bool {
+        self.order_category == "Limit"
+            && matches!(self.reason, OrderReason::PriceDecrease { .. } | OrderReason::PriceIncrease { .. })
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub enum StockType {
+    Tech,
+    Food,
+    Healthcare,
+}
+
+// Relative change from `from` to `to`, as a fraction. Moves off a zero price
+// have no meaningful size and count as no change.
+pub fn relative_change(from: i32, to: i32) -> f64 {
+    if from == 0 {
+        return 0.0;
+    }
+    (to as f64 - from as f64) / from as f64
+}
+
+// Sample standard deviation of the tick-to-tick returns over `prices`. None
+// with fewer than two returns.
+pub fn realized_vol(prices: &[i32]) -> Option<f64> {
+    if prices.len() < 3 {
+        return None;
+    }
+    let returns: Vec<f64> = prices.windows(2).map(|pair| relative_change(pair[0], pair[1])).collect();
+    let mean = returns.iter().sum::<f64>() / returns.len() as f64;
+    let variance = returns.iter().map(|r| (r - mean).powi(2)).sum::<f64>() / (returns.len() - 1) as f64;
+    Some(variance.sqrt())
+}
+
+impl Stock {
+    pub fn new(name: &str, v: i32) -> Self {
+        Stock { name: Ticker::from(name), v, prev_v: v, display_name: None, exchange: None, fair_value: None, sector: None }
+    }
+
+    pub fn with_sector(mut self, sector: StockType) -> Self {
+        self.sector = Some(sector);
+        self
+    }
+
+    // Sets the reference price the stock's first simulated tick is measured
+    // against, so that tick can be made to signal a buy (above `v`) or a
+    // sell (below `v`) whatever the random move.
+    pub fn with_prev_v(mut self, prev_v: i32) -> Self {
+        self.prev_v = prev_v;
+        self
+    }
+
+    pub fn with_fair_value(mut self, fair_value: i32) -> Self {
+        self.fair_value = Some(fair_value);
+        self
+    }
+
+    // Movement since the reference price `prev_v`.
+    pub fn change(&self) -> i32 {
+        self.v - self.prev_v
+    }
+
+    // Percentage move since the previous tick; 0 if that price was zero.
+    pub fn percent_change(&self) -> f64 {
+        relative_change(self.prev_v, self.v) * 100.0
+    }
+
+    pub fn with_metadata(mut self, display_name: &str, exchange: &str) -> Self {
+        self.display_name = Some(display_name.to_string());
+        self.exchange = Some(exchange.to_string());
+        self
+    }
+
+    // The same as `try_stock_type`. It used to panic on unknown tickers; use
+    // `classify` for an error naming the ticker instead.
+    pub fn stock_type(&self) -> Option<StockType> {
+        self.try_stock_type()
+    }
+
+    pub fn classify(&self) -> Result<StockType, StockError> {
+        self.try_stock_type().ok_or_else(|| StockError::Unclassified(self.name.to_string()))
+    }
+
+    pub fn try_stock_type(&self) -> Option<StockType> {
+        if let Some(sector) = &self.sector {
+            return Some(sector.clone());
+        }
+        let stock_type = match &*self.name {
+            "AAPL" | "AMZN" | "GOOGL" | "MSFT" | "TSLA" | "FB" | "CRM" | "INTC" | "NVDA" |"WORK" | "FSLY" | "CRWD" |
+            "DOCU" => StockType::Tech,
+            "KO" | "PEP" | "MCD" | "SBUX" | "GIS" | "HSY" | "KR" | "CPB" | "WMT" |"TGT" | "COST" | "PG" | "UN" | "SYY" |
+            "FLO" | "WBA" => StockType::Food,
+            "MDLZ" | "MRK" | "AMGN" | "UNH" | "HCA" | "ANTM" |"DHR" | "ABT" |"TMO" |"REGN" | "ILMN" | "MDT" | "ZBH" | "VRTX" |
+            "IDXX" |"DGX"  => StockType::Healthcare,
+            _ => return None,
+        };
+        Some(stock_type)
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct CatalogEntry {
+    pub name: String,
+    pub stock_type: Option<StockType>,
+    pub display_name: Option<String>,
+    pub exchange: Option<String>,
+}
+
+// The listed stocks and their descriptive metadata, keyed by ticker.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct StockCatalog {
+    pub entries: BTreeMap<String, CatalogEntry>,
+}
+
+impl StockCatalog {
+    pub fn from_stocks(stocks: &[Stock]) -> Self {
+        let mut catalog = StockCatalog::default();
+        for stock in stocks {
+            catalog.insert(stock);
+        }
+        catalog
+    }
+
+    pub fn insert(&mut self, stock: &Stock) {
+        let entry = CatalogEntry {
+            name: stock.name.to_string(),
+            stock_type: stock.try_stock_type(),
+            display_name: stock.display_name.clone(),
+            exchange: stock.exchange.clone(),
+        };
+        self.entries.insert(stock.name.to_string(), entry);
+    }
+
+    pub fn get(&self, name: &str) -> Option<&CatalogEntry> {
+        self.entries.get(name)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+pub enum BrokerMode {
+    Individual,
+    Pooled,
+}
+
+pub const POOL_ACCOUNT: &str = "Pool";
+
+// How a sell of shares the client holds is booked.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize)]
+pub enum PositionMode {
+    // The sell closes bought shares: the position shrinks and the PnL is
+    // realized.
+    #[default]
+    Netting,
+    // The sell opens a short leg next to the long one and both stay open,
+    // each marked to market. Holdings still count the net of the two.
+    Gross,
+}
+
+#[derive(Debug, Clone)]
+pub struct BrokerConfig {
+    pub mode: BrokerMode,
+    // No new buys once the notional of all open positions across the
+    // broker's clients, at their latest prices, would exceed this.
+    pub max_broker_exposure: Option<Money>,
+    // No new buys for the rest of the trading day once the broker's realized
+    // losses that day exceed this.
+    pub max_daily_loss: Option<Money>,
+    // Shares per tick and stock that limit orders can fill against. Orders
+    // larger than this rest and fill in pieces over later ticks.
+    pub liquidity_per_tick: Option<i32>,
+    // Seeds the broker's order quantities. The exchange derives one from its
+    // own seed when this is left unset.
+    pub seed: Option<u64>,
+    // Signal orders worth less than this (quantity * price) are skipped.
+    pub min_order_notional: Money,
+    // Signal order quantities are rounded down to a multiple of this.
+    pub lot_size: i32,
+    // No commission is charged while this is empty. Otherwise each fill pays
+    // the rate of the highest tier the client's volume before it has reached.
+    pub commission_tiers: Vec<CommissionTier>,
+    // Clamp each charged commission into this band. Fills that no tier
+    // applies to stay free.
+    pub min_commission: Option<Money>,
+    pub max_commission: Option<Money>,
+    // Total orders the broker places across all clients, paper orders aside.
+    // Clients with a higher `priority` are offered each tick first.
+    pub order_cap: Option<usize>,
+    // Orders beyond the latest this many are dropped, oldest first and
+    // resting orders aside, as are older skipped orders and signals. The
+    // exchange fills this in from `SimulationConfig::retention` when left
+    // unset.
+    pub order_retention: Option<usize>,
+    // Turns the broker into a follower that copies another broker's orders
+    // for each of its own clients instead of trading on price signals.
+    pub copy: Option<CopyTrading>,
+    // How long the broker waits after starting before it takes its first
+    // tick. The exchange draws this from `SimulationConfig::broker_start_jitter`
+    // when left unset.
+    pub start_delay: Option<Duration>,
+    // Rejects orders priced more than this many basis points away from the
+    // price the stock last filled at in this broker, or from its price
+    // before the tick if it has not filled yet.
+    pub fat_finger_bps: Option<i32>,
+    // Best-effort hint to run the broker's thread on this CPU core. Ignored
+    // without the `affinity` feature, off Linux, or if the core is unavailable.
+    pub cpu_affinity: Option<usize>,
+    // Shifts clients' price-change thresholds by a random amount of up to
+    // this many basis points of the stock price, either way, on every
+    // signal, so moves close to a threshold only sometimes trade. Drawn
+    // from the broker's quantity source, so seeded runs stay reproducible.
+    pub decision_noise_bps: i32,
+    pub position_mode: PositionMode,
+    // Maker/taker pricing on top of commission, in basis points of the fill
+    // value: fills of resting limit orders earn the rebate, fills on arrival
+    // pay the fee.
+    pub maker_rebate_bps: i32,
+    pub taker_fee_bps: i32,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct CopyTrading {
+    pub leader: String,
+    // Copied quantities are the leader's multiplied by this, rounded.
+    pub scale: f64,
+}
+
+// An order placed by a leader broker, as seen by its followers.
+#[derive(Debug, Clone)]
+pub struct CopiedOrder {
+    pub order_id: u64,
+    pub stock: Stock,
+    pub order_type: String,
+    pub quantity: i32,
+}
+
+impl BrokerConfig {
+    pub fn new(mode: BrokerMode) -> Self {
+        BrokerConfig {
+            mode,
+            max_broker_exposure: None,
+            max_daily_loss: None,
+            liquidity_per_tick: None,
+            seed: None,
+            min_order_notional: 0,
+            lot_size: 1,
+            commission_tiers: Vec::new(),
+            min_commission: None,
+            max_commission: None,
+            order_cap: None,
+            order_retention: None,
+            copy: None,
+            start_delay: None,
+            fat_finger_bps: None,
+            cpu_affinity: None,
+            decision_noise_bps: 0,
+            position_mode: PositionMode::Netting,
+            maker_rebate_bps: 0,
+            taker_fee_bps: 0,
+        }
+    }
+
+    pub fn commission(&self, volume: i32, value: Money) -> Money {
+        self.commission_tiers
+            .iter()
+            .filter(|tier| volume >= tier.min_volume)
+            .max_by_key(|tier| tier.min_volume)
+            .map_or(0, |tier| {
+                let commission = value * tier.rate_bps as Money / 10_000;
+                let commission = self.min_commission.map_or(commission, |min| commission.max(min));
+                self.max_commission.map_or(commission, |max| commission.min(max))
+            })
+    }
+}
+
+// Commission in basis points of the trade value, charged once a client's
+// cumulative traded volume reaches `min_volume` shares.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct CommissionTier {
+    pub min_volume: i32,
+    pub rate_bps: i32,
+}
+
+impl Default for BrokerConfig {
+    fn default() -> Self {
+        BrokerConfig::new(BrokerMode::Individual)
+    }
+}
+
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct Account {
+    pub cash: Money,
+    pub positions: HashMap<String, i32>,
+    // Total cost of the shares currently held, per stock.
+    pub cost: HashMap<String, Money>,
+    // Highest price seen since each open position was entered.
+    pub high_water: HashMap<String, i32>,
+    pub earnings: Money,
+    // Open buy lots per stock, oldest first.
+    pub lots: HashMap<String, VecDeque<Lot>>,
+    // Realized earnings per zero-based trading day.
+    pub daily_earnings: BTreeMap<u32, Money>,
+    // Sale proceeds less the average cost of the shares sold, in total and
+    // per stock.
+    pub realized_pnl: Money,
+    pub realized_by_stock: HashMap<String, Money>,
+    // Realized PnL per zero-based trading day.
+    pub daily_realized_pnl: BTreeMap<u32, Money>,
+    // Set once a client with starting cash funds the account; buys are then
+    // limited to the cash on hand.
+    pub cash_limited: bool,
+    pub commissions: Money,
+    // Taker fees paid less maker rebates earned.
+    pub fees: Money,
+    // Starting cash paid into the account.
+    pub funded: Money,
+    // Gross mode only: shares sold against the long leg in `positions`, and
+    // what they were sold for.
+    pub short_positions: HashMap<String, i32>,
+    pub short_proceeds: HashMap<String, Money>,
+}
+
+impl Account {
+    // Net shares held, the short leg taken off in gross mode.
+    pub fn held(&self, stock_name: &str) -> i32 {
+        self.positions.get(stock_name).copied().unwrap_or(0) - self.short_positions.get(stock_name).copied().unwrap_or(0)
+    }
+
+    // Average cost of the long leg.
+    pub fn average_price(&self, stock_name: &str) -> Option<i32> {
+        let long = self.positions.get(stock_name).copied().unwrap_or(0);
+        if long <= 0 || self.held(stock_name) <= 0 {
+            return None;
+        }
+        self.cost.get(stock_name).map(|cost| (cost / long as Money) as i32)
+    }
+
+    // Open positions valued at `prices`.
+    pub fn market_value(&self, prices: &HashMap<String, i32>) -> Money {
+        let price = |name: &String| prices.get(name).copied().unwrap_or(0) as Money;
+        let long: Money = self.positions.iter().map(|(name, &held)| held as Money * price(name)).sum();
+        let short: Money = self.short_positions.iter().map(|(name, &sold)| sold as Money * price(name)).sum();
+        long - short
+    }
+
+    // Market value of open positions at `prices` less what they cost. A
+    // short leg gains what it was sold for above its value at `prices`.
+    pub fn unrealized_pnl(&self, prices: &HashMap<String, i32>) -> Money {
+        let price = |name: &String| prices.get(name).copied().unwrap_or(0) as Money;
+        let long: Money = self
+            .positions
+            .iter()
+            .filter(|(_, &held)| held > 0)
+            .map(|(name, &held)| held as Money * price(name) - self.cost.get(name).copied().unwrap_or(0))
+            .sum();
+        let short: Money = self
+            .short_positions
+            .iter()
+            .map(|(name, &sold)| self.short_proceeds.get(name).copied().unwrap_or(0) - sold as Money * price(name))
+            .sum();
+        long + short
+    }
+
+    // Raises the position's high-water mark to `price` if higher and returns it.
+    pub fn mark_high(&mut self, stock_name: &str, price: i32) -> i32 {
+        let high = self.high_water.entry(stock_name.to_string()).or_insert(price);
+        *high = (*high).max(price);
+        *high
+    }
+
+    fn buy(&mut self, order_id: u64, stock_name: &str, quantity: i32, price: i32) {
+        self.lots.entry(stock_name.to_string()).or_default().push_back(Lot { order_id, quantity, price });
+        if self.held(stock_name) <= 0 {
+            self.high_water.insert(stock_name.to_string(), price);
+        }
+        self.cash -= quantity as Money * price as Money;
+        *self.positions.entry(stock_name.to_string()).or_insert(0) += quantity;
+        *self.cost.entry(stock_name.to_string()).or_insert(0) += quantity as Money * price as Money;
+    }
+
+    // Takes `quantity` shares from the oldest lots. Returns the buy orders
+    // they came from and the PnL of selling them at `price`.
+    fn close_lots(&mut self, stock_name: &str, quantity: i32, price: i32) -> (Vec<u64>, Money) {
+        let mut closed = Vec::new();
+        let mut pnl = 0;
+        let mut left = quantity;
+        let lots = self.lots.entry(stock_name.to_string()).or_default();
+        while left > 0 {
+            let Some(lot) = lots.front_mut() else { break };
+            let taken = left.min(lot.quantity);
+            pnl += taken as Money * (price as Money - lot.price as Money);
+            closed.push(lot.order_id);
+            lot.quantity -= taken;
+            left -= taken;
+            if lot.quantity == 0 {
+                lots.pop_front();
+            }
+        }
+        (closed, pnl)
+    }
+
+    fn sell(&mut self, stock_name: &str, quantity: i32, price: i32, earnings: Money, day: u32, mode: PositionMode) {
+        self.cash += quantity as Money * price as Money;
+        let held = self.held(stock_name);
+        if mode == PositionMode::Gross {
+            *self.short_positions.entry(stock_name.to_string()).or_insert(0) += quantity;
+            *self.short_proceeds.entry(stock_name.to_string()).or_insert(0) += quantity as Money * price as Money;
+        } else if held > 0 {
+            let sold = quantity.min(held);
+            let cost = self.cost.entry(stock_name.to_string()).or_insert(0);
+            let sold_cost = *cost * sold as Money / held as Money;
+            *cost -= sold_cost;
+            let pnl = sold as Money * price as Money - sold_cost;
+            self.realized_pnl += pnl;
+            *self.realized_by_stock.entry(stock_name.to_string()).or_insert(0) += pnl;
+            *self.daily_realized_pnl.entry(day).or_insert(0) += pnl;
+        }
+        if mode == PositionMode::Netting {
+            *self.positions.entry(stock_name.to_string()).or_insert(0) -= quantity;
+        }
+        if self.held(stock_name) <= 0 {
+            self.high_water.remove(stock_name);
+        }
+        // Saturate rather than wrap if a run ever reaches the edge of the range.
+        self.earnings = self.earnings.saturating_add(earnings);
+        let daily = self.daily_earnings.entry(day).or_insert(0);
+        *daily = daily.saturating_add(earnings);
+    }
+}
+
+#[derive(Debug, Serialize)]
+pub struct BrokerResult {
+    pub name: String,
+    pub mode: BrokerMode,
+    pub position_mode: PositionMode,
+    // Keyed by client name, or by POOL_ACCOUNT for a pooled broker.
+    pub accounts: HashMap<String, Account>,
+    pub orders: Vec<Order>,
+    pub skipped: Vec<SkippedOrder>,
+    pub signals: Vec<Signal>,
+    // Shares each client has traded, for commission tiers.
+    pub client_volume: BTreeMap<String, i32>,
+    // Keyed by stock. Paper fills are left out.
+    pub trades: BTreeMap<String, TradeStats>,
+    // The stocks the broker saw, before their first tick and as of their
+    // last, by name. Kept so results merged later can still be valued.
+    #[serde(skip)]
+    pub opening: Vec<Stock>,
+    #[serde(skip)]
+    pub closing: Vec<Stock>,
+}
+
+impl BrokerResult {
+    pub fn earnings(&self) -> HashMap<String, Money> {
+        self.accounts.iter().map(|(k, a)| (k.clone(), a.earnings)).collect()
+    }
+
+    pub fn daily_earnings(&self) -> BTreeMap<u32, Money> {
+        let mut totals = BTreeMap::new();
+        for account in self.accounts.values() {
+            for (day, &earnings) in &account.daily_earnings {
+                let total: &mut Money = totals.entry(*day).or_insert(0);
+                *total = total.saturating_add(earnings);
+            }
+        }
+        totals
+    }
+}
+
+// A stock's open, high, low and close over a span of ticks. The open is the
+// price before the span's first tick.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+pub struct Ohlc {
+    pub open: i32,
+    pub high: i32,
+    pub low: i32,
+    pub close: i32,
+}
+
+impl Ohlc {
+    pub fn new(open: i32) -> Self {
+        Ohlc { open, high: open, low: open, close: open }
+    }
+
+    pub fn update(&mut self, price: i32) {
+        self.high = self.high.max(price);
+        self.low = self.low.min(price);
+        self.close = price;
+    }
+}
+
+// How often a stock's ticks are cut into a new candle.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CandleInterval {
+    // Every this many ticks of the stock.
+    Ticks(u64),
+    // Every this much simulated time, scaled by `SimulationConfig::speed`.
+    Time(Duration),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+pub struct Candle {
+    // Time since the simulation started at the candle's first tick.
+    pub started: Duration,
+    #[serde(flatten)]
+    pub ohlc: Ohlc,
+    // Shares filled across all brokers while the candle was the latest.
+    pub volume: i64,
+    pub ticks: u64,
+}
+
+// Running totals of a broker's fills in one stock.
+#[derive(Debug, Clone, Copy, Default, Serialize)]
+pub struct TradeStats {
+    pub bought: i64,
+    pub sold: i64,
+    // Sum of quantity * price over all fills.
+    pub notional: i64,
+}
+
+impl TradeStats {
+    pub fn volume(&self) -> i64 {
+        self.bought + self.sold
+    }
+
+    pub fn vwap(&self) -> Option<f64> {
+        if self.volume() == 0 {
+            return None;
+        }
+        Some(self.notional as f64 / self.volume() as f64)
+    }
+}
+
+// Where the simulator draws price deltas and brokers draw order quantities
+// from. Tests can script the sequence instead of using a real RNG.
+pub trait RandomSource: Send {
+    fn delta(&mut self, range: Range<i32>) -> i32;
+}
+
+// An empty range has nothing to draw from and gives its start.
+impl RandomSource for StdRng {
+    fn delta(&mut self, range: Range<i32>) -> i32 {
+        if range.is_empty() {
+            return range.start;
+        }
+        self.gen_range(range)
+    }
+}
+
+// Returns the given values in order, starting over once they run out. With
+// no values it gives the start of each range asked for.
+#[derive(Debug, Clone)]
+pub struct ScriptedSource {
+    values: Vec<i32>,
+    next: usize,
+}
+
+impl ScriptedSource {
+    pub fn new(values: Vec<i32>) -> Self {
+        ScriptedSource { values, next: 0 }
+    }
+}
+
+impl RandomSource for ScriptedSource {
+    fn delta(&mut self, range: Range<i32>) -> i32 {
+        if self.values.is_empty() {
+            return range.start;
+        }
+        let value = self.values[self.next % self.values.len()];
+        self.next += 1;
+        value
+    }
+}
+
+// A range ending at `i32::MAX` cannot include it, so draws stop one short.
+fn inclusive(range: &RangeInclusive<i32>) -> Range<i32> {
+    *range.start()..range.end().saturating_add(1)
+}
+
+pub fn seeded_rng(seed: Option<u64>) -> StdRng {
+    match seed {
+        Some(seed) => StdRng::seed_from_u64(seed),
+        None => StdRng::from_entropy(),
+    }
+}
+
+// Draws from N(0, 1) with the Box-Muller transform.
+fn standard_normal(rng: &mut dyn RandomSource) -> f64 {
+    let u1 = rng.delta(1..1_000_000) as f64 / 1_000_000.0;
+    let u2 = rng.delta(0..1_000_000) as f64 / 1_000_000.0;
+    (-2.0 * u1.ln()).sqrt() * (2.0 * std::f64::consts::PI * u2).cos()
+}
+
+// How the simulator moves a stock's price on each tick.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub enum PriceModel {
+    // Uniform integer deltas, autocorrelated by `SimulationConfig::autocorrelation`.
+    #[default]
+    RandomWalk,
+    // Geometric Brownian motion discretized per tick: log-returns are normal
+    // with mean `mu - sigma^2 / 2` and standard deviation `sigma`.
+    Gbm { mu: f64, sigma: f64 },
+}
+
+// Per-stock settings for the simulator, keyed by ticker in `SimulationConfig`.
+#[derive(Debug, Clone, Default)]
+pub struct StockSettings {
+    // Furthest the price may move above or below the day's open.
+    pub daily_limit_up: Option<i32>,
+    pub daily_limit_down: Option<i32>,
+    pub model: PriceModel,
+    // Generated prices are rounded to the nearest multiple of this that lies
+    // within the daily limits.
+    pub tick_size: Option<i32>,
+    // Caps orders in the stock across all brokers.
+    pub throttle: Option<OrderThrottle>,
+    // How strongly the stock follows the market-wide shock of each cycle:
+    // it moves by `market_beta` times the shock on top of its own move.
+    pub market_beta: f64,
+}
+
+// At most `max_orders` orders within any sliding window of `interval`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+pub struct OrderThrottle {
+    pub max_orders: usize,
+    pub interval: Duration,
+}
+
+// A throttle and when each order still inside its window was placed.
+#[derive(Debug)]
+struct ThrottleWindow {
+    throttle: OrderThrottle,
+    placed: VecDeque<Duration>,
+}
+
+impl StockSettings {
+    pub fn clamp_to_daily_limits(&self, v: i32, open: i32) -> i32 {
+        let mut v = v;
+        if let Some(up) = self.daily_limit_up {
+            v = v.min(open.saturating_add(up));
+        }
+        if let Some(down) = self.daily_limit_down {
+            v = v.max(open.saturating_sub(down));
+        }
+        v
+    }
+
+    // Halfway prices round up. Ticks beyond the `i32` range are never chosen.
+    pub fn round_to_tick(&self, v: i32) -> i32 {
+        match self.tick_size {
+            Some(tick) if tick > 1 => {
+                let tick = tick as i64;
+                let rounded = (v as i64 + tick / 2).div_euclid(tick) * tick;
+                let rounded = if rounded > i32::MAX as i64 {
+                    rounded - tick
+                } else if rounded < i32::MIN as i64 {
+                    rounded + tick
+                } else {
+                    rounded
+                };
+                rounded as i32
+            }
+            _ => v,
+        }
+    }
+
+    // Clamps to the daily limits, then rounds to a tick. A limit that falls
+    // between ticks is met by the tick inside it, towards the open.
+    pub fn settle(&self, v: i32, open: i32) -> i32 {
+        let clamped = self.clamp_to_daily_limits(v, open);
+        let rounded = self.round_to_tick(clamped);
+        let tick = self.tick_size.unwrap_or(1).max(1);
+        if rounded > clamped && self.daily_limit_up.is_some_and(|up| rounded > open.saturating_add(up)) {
+            rounded - tick
+        } else if rounded < clamped && self.daily_limit_down.is_some_and(|down| rounded < open.saturating_sub(down)) {
+            rounded + tick
+        } else {
+            rounded
+        }
+    }
+}
+
+// Which events get printed while the simulation runs. Each level also prints
+// everything the levels before it do.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Verbosity {
+    Silent,
+    // Placed orders and the simulation's lifecycle: new days, news, brokers finishing.
+    #[default]
+    Orders,
+    // Every price tick.
+    Ticks,
+    // Skipped orders and partial fills.
+    All,
+}
+
+#[derive(Debug, Clone)]
+pub struct SimulationConfig {
+    pub transaction_limit: i32,
+    pub watchdog_timeout: Duration,
+    pub watchdog_shutdown: bool,
+    // With `ticks_per_day` set the run lasts `days` trading days, and each
+    // stock jumps by a random `overnight_delta` between consecutive days.
+    pub days: u32,
+    pub ticks_per_day: Option<u64>,
+    pub overnight_delta: RangeInclusive<i32>,
+    // Zero-based day indices the market is shut, e.g. weekends and holidays.
+    // They produce no ticks and do not count toward `days`; the gap on
+    // reopening is scaled by the number of nights since the last close.
+    pub closed_days: BTreeSet<u32>,
+    // Common move, in basis points, drawn once per cycle and applied to each
+    // stock scaled by its `market_beta`. Nothing is drawn while every beta is zero.
+    pub market_shock_bps: RangeInclusive<i32>,
+    // Stop after this many price ticks regardless of transaction progress.
+    pub max_ticks: Option<u64>,
+    // Persist the final report here once the run completes.
+    pub report_file: Option<(PathBuf, Format)>,
+    pub stock_settings: HashMap<String, StockSettings>,
+    // Makes a run reproducible. Unseeded runs draw from OS entropy.
+    pub seed: Option<u64>,
+    // Weight of a stock's previous delta in its next one (`rho`); positive
+    // values give momentum, negative values mean reversion.
+    pub autocorrelation: f64,
+    pub verbosity: Verbosity,
+    // Send each broker one message per cycle holding all of its ticks,
+    // instead of one message per stock.
+    pub batch_ticks: bool,
+    // Measure the first tick of each day against the day's open, after the
+    // overnight gap, rather than against the previous day's close.
+    pub reset_reference_at_open: bool,
+    // Runs time faster (or slower) than real time. The tick period, the
+    // watchdog timeout and client cooldowns are all divided by it.
+    pub speed: f64,
+    // Bounds memory on long runs: only the latest `retention` prices and
+    // candles per stock, orders per broker (resting orders aside), acks and
+    // tape entries are kept. Trade statistics are running totals and stay
+    // exact.
+    pub retention: Option<usize>,
+    // Per-tick change in the fair value of stocks that have one.
+    pub fair_value_drift: RangeInclusive<i32>,
+    // Caps the trades placed in a sector, counted across all brokers.
+    pub sector_transaction_limits: HashMap<StockType, i32>,
+    // Collects per-stock candles into the report when set.
+    pub candle_interval: Option<CandleInterval>,
+    // Intraday volatility as (offset into the trading day, multiplier) pairs.
+    // Each cycle counts as one simulated second, and random moves are scaled
+    // by the latest entry reached, or left as they are before the first.
+    pub volatility_curve: Vec<(Duration, f64)>,
+    // Staggers broker startup: each broker waits a random time below this
+    // before taking ticks.
+    pub broker_start_jitter: Duration,
+    // Bounds each broker's tick channel to this many messages, so a stalled
+    // broker cannot grow it without limit. Unbounded when unset.
+    pub tick_buffer: Option<usize>,
+    pub send_retry: SendRetry,
+    // Units of each secondary currency per unit of the base currency, for
+    // reporting earnings converted.
+    pub fx_rates: HashMap<String, f64>,
+}
+
+impl Default for SimulationConfig {
+    fn default() -> Self {
+        SimulationConfig {
+            transaction_limit: 10,
+            watchdog_timeout: Duration::from_secs(10),
+            watchdog_shutdown: true,
+            days: 1,
+            ticks_per_day: None,
+            overnight_delta: -20..=20,
+            closed_days: BTreeSet::new(),
+            market_shock_bps: -50..=50,
+            max_ticks: None,
+            report_file: None,
+            stock_settings: HashMap::new(),
+            seed: None,
+            autocorrelation: 0.0,
+            verbosity: Verbosity::default(),
+            batch_ticks: false,
+            reset_reference_at_open: true,
+            speed: 1.0,
+            retention: None,
+            fair_value_drift: -1..=1,
+            sector_transaction_limits: HashMap::new(),
+            candle_interval: None,
+            volatility_curve: Vec::new(),
+            broker_start_jitter: Duration::ZERO,
+            tick_buffer: None,
+            send_retry: SendRetry::default(),
+            fx_rates: HashMap::new(),
+        }
+    }
+}
+
+impl SimulationConfig {
+    pub fn scaled(&self, duration: Duration) -> Duration {
+        duration.div_f64(self.speed)
+    }
+
+    // Fails if `duration` cannot be divided by the speed without overflowing.
+    pub(crate) fn check_scaled(&self, what: &str, duration: Duration) -> Result<(), StockError> {
+        match Duration::try_from_secs_f64(duration.as_secs_f64() / self.speed) {
+            Ok(_) => Ok(()),
+            Err(_) => Err(StockError::Overflow(what.to_string())),
+        }
+    }
+
+    pub fn validate(&self) -> Result<(), StockError> {
+        if !self.speed.is_finite() || self.speed <= 0.0 {
+            return Err(StockError::InvalidConfig(format!("speed must be positive, got {}", self.speed)));
+        }
+        for (what, range) in [("overnight_delta", &self.overnight_delta), ("fair_value_drift", &self.fair_value_drift)] {
+            if range.is_empty() {
+                return Err(StockError::InvalidConfig(format!("{} {:?} is empty", what, range)));
+            }
+        }
+        if self.tick_buffer == Some(0) {
+            return Err(StockError::InvalidConfig("tick_buffer must hold at least one message".to_string()));
+        }
+        self.check_scaled("the tick period", Duration::from_secs(1))?;
+        self.check_scaled("watchdog_timeout", self.watchdog_timeout)?;
+        self.check_scaled("broker_start_jitter", self.broker_start_jitter)?;
+        if let Some(CandleInterval::Time(interval)) = self.candle_interval {
+            self.check_scaled("candle_interval", interval)?;
+        }
+        for (name, settings) in &self.stock_settings {
+            if let Some(throttle) = settings.throttle {
+                self.check_scaled(&format!("the throttle interval of {}", name), throttle.interval)?;
+            }
+        }
+        Ok(())
+    }
+
+    pub fn volatility_at(&self, time_of_day: Duration) -> f64 {
+        self.volatility_curve
+            .iter()
+            .filter(|(offset, _)| *offset <= time_of_day)
+            .max_by_key(|(offset, _)| *offset)
+            .map_or(1.0, |(_, multiplier)| *multiplier)
+    }
+}
+
+// Shared between the simulator, the brokers and the watchdog. Every generated
+// tick and placed order refreshes the heartbeat.
+#[derive(Debug, Clone)]
+pub struct SimulationControl {
+    // When the run started: at creation, then again when an exchange starts.
+    started: Arc<Mutex<Instant>>,
+    shutdown: Arc<AtomicBool>,
+    heartbeat: Arc<Mutex<Instant>>,
+    ticks: Arc<AtomicU64>,
+    // Sector shocks in basis points, applied on the next tick.
+    news: Arc<Mutex<Vec<(StockType, i32)>>>,
+    // Broker name -> whether it has finished.
+    completion: Arc<Mutex<BTreeMap<String, bool>>>,
+    // Every price each stock has ticked at, oldest first.
+    history: Arc<Mutex<HashMap<Ticker, VecDeque<i32>>>>,
+    // Whole-run bars, exact even when `history` is trimmed.
+    ohlc: Arc<Mutex<HashMap<Ticker, Ohlc>>>,
+    candles: Arc<Mutex<HashMap<Ticker, Vec<Candle>>>>,
+    order_ids: Arc<AtomicU64>,
+    // Leader broker name -> its followers' feeds.
+    followers: Arc<Mutex<HashMap<String, Vec<crossbeam_channel::Sender<CopiedOrder>>>>>,
+    ack_sender: crossbeam_channel::Sender<OrderAck>,
+    ack_receiver: crossbeam_channel::Receiver<OrderAck>,
+    tape_sender: crossbeam_channel::Sender<TapeEntry>,
+    tape_receiver: crossbeam_channel::Receiver<TapeEntry>,
+    // Exchange-wide caps on trades per sector, and the trades counted against them.
+    sector_limits: Arc<Mutex<HashMap<StockType, i32>>>,
+    sector_trades: Arc<Mutex<HashMap<StockType, i32>>>,
+    // Per-stock throttles and when each recent order in the stock was placed.
+    throttles: Arc<Mutex<HashMap<String, ThrottleWindow>>>,
+    // How many candles per stock, acks and tape entries are kept.
+    retention: Arc<Mutex<Option<usize>>>,
+}
+
+impl SimulationControl {
+    pub fn new() -> Self {
+        let (ack_sender, ack_receiver) = unbounded();
+        let (tape_sender, tape_receiver) = unbounded();
+        SimulationControl {
+            started: Arc::new(Mutex::new(Instant::now())),
+            shutdown: Arc::new(AtomicBool::new(false)),
+            heartbeat: Arc::new(Mutex::new(Instant::now())),
+            ticks: Arc::new(AtomicU64::new(0)),
+            news: Arc::new(Mutex::new(Vec::new())),
+            completion: Arc::new(Mutex::new(BTreeMap::new())),
+            history: Arc::new(Mutex::new(HashMap::new())),
+            ohlc: Arc::new(Mutex::new(HashMap::new())),
+            candles: Arc::new(Mutex::new(HashMap::new())),
+            order_ids: Arc::new(AtomicU64::new(0)),
+            followers: Arc::new(Mutex::new(HashMap::new())),
+            ack_sender,
+            ack_receiver,
+            tape_sender,
+            tape_receiver,
+            sector_limits: Arc::new(Mutex::new(HashMap::new())),
+            sector_trades: Arc::new(Mutex::new(HashMap::new())),
+            throttles: Arc::new(Mutex::new(HashMap::new())),
+            retention: Arc::new(Mutex::new(None)),
+        }
+    }
+
+    pub fn elapsed(&self) -> Duration {
+        self.started.lock().unwrap().elapsed()
+    }
+
+    // Starts the clock and the heartbeat over, for every clone alike.
+    pub fn restart(&self) {
+        let now = Instant::now();
+        *self.started.lock().unwrap() = now;
+        *self.heartbeat.lock().unwrap() = now;
+    }
+
+    pub fn beat(&self) {
+        *self.heartbeat.lock().unwrap() = Instant::now();
+    }
+
+    pub fn idle_for(&self) -> Duration {
+        self.heartbeat.lock().unwrap().elapsed()
+    }
+
+    pub fn record_tick(&self) {
+        self.ticks.fetch_add(1, Ordering::SeqCst);
+    }
+
+    pub fn ticks(&self) -> u64 {
+        self.ticks.load(Ordering::SeqCst)
+    }
+
+    // Order IDs start at 1.
+    pub fn next_order_id(&self) -> u64 {
+        self.order_ids.fetch_add(1, Ordering::SeqCst) + 1
+    }
+
+    pub fn set_sector_limits(&self, limits: HashMap<StockType, i32>) {
+        *self.sector_limits.lock().unwrap() = limits;
+    }
+
+    // Counts a trade against its sector's limit. Fails with the limit once
+    // it has been reached.
+    fn reserve_sector_trade(&self, sector: &StockType) -> Result<(), i32> {
+        let Some(&limit) = self.sector_limits.lock().unwrap().get(sector) else { return Ok(()) };
+        let mut trades = self.sector_trades.lock().unwrap();
+        let count = trades.entry(sector.clone()).or_insert(0);
+        if *count >= limit {
+            return Err(limit);
+        }
+        *count += 1;
+        Ok(())
+    }
+
+    pub fn set_retention(&self, retention: Option<usize>) {
+        *self.retention.lock().unwrap() = retention;
+    }
+
+    fn retention(&self) -> Option<usize> {
+        *self.retention.lock().unwrap()
+    }
+
+    // Drops the oldest messages beyond the retention limit, which the control
+    // can do since it holds a receiver of its own.
+    fn trim<T>(&self, receiver: &crossbeam_channel::Receiver<T>) {
+        if let Some(retention) = self.retention() {
+            while receiver.len() > retention && receiver.try_recv().is_ok() {}
+        }
+    }
+
+    pub fn set_throttles(&self, throttles: HashMap<String, OrderThrottle>) {
+        *self.throttles.lock().unwrap() =
+            throttles.into_iter().map(|(name, throttle)| (name, ThrottleWindow { throttle, placed: VecDeque::new() })).collect();
+    }
+
+    // Counts an order in the stock against its throttle. Fails with the
+    // throttle while the window is full.
+    fn reserve_throttled_order(&self, stock_name: &str) -> Result<(), OrderThrottle> {
+        let mut throttles = self.throttles.lock().unwrap();
+        let Some(window) = throttles.get_mut(stock_name) else { return Ok(()) };
+        let now = self.elapsed();
+        while window.placed.front().is_some_and(|&at| now.saturating_sub(at) >= window.throttle.interval) {
+            window.placed.pop_front();
+        }
+        if window.placed.len() >= window.throttle.max_orders {
+            return Err(window.throttle);
+        }
+        window.placed.push_back(now);
+        Ok(())
+    }
+
+    pub fn sector_exhausted(&self, sector: &StockType) -> bool {
+        let Some(&limit) = self.sector_limits.lock().unwrap().get(sector) else { return false };
+        self.sector_trades.lock().unwrap().get(sector).copied().unwrap_or(0) >= limit
+    }
+
+    pub fn sector_trades(&self) -> HashMap<StockType, i32> {
+        self.sector_trades.lock().unwrap().clone()
+    }
+
+    // Keeps at most `retention` prices per stock when set.
+    pub fn record_price(&self, stock: &Stock, retention: Option<usize>) {
+        self.ohlc.lock().unwrap().entry(stock.name.clone()).or_insert_with(|| Ohlc::new(stock.prev_v)).update(stock.v);
+        let mut history = self.history.lock().unwrap();
+        let prices = history.entry(stock.name.clone()).or_default();
+        prices.push_back(stock.v);
+        if let Some(retention) = retention {
+            while prices.len() > retention {
+                prices.pop_front();
+            }
+        }
+    }
+
+    pub fn price_history(&self) -> BTreeMap<String, Vec<i32>> {
+        self.history
+            .lock()
+            .unwrap()
+            .iter()
+            .map(|(name, prices)| (name.to_string(), prices.iter().copied().collect()))
+            .collect()
+    }
+
+    // Extends the stock's latest candle with the tick, or opens a new one once
+    // the interval is up.
+    pub fn record_candle(&self, stock: &Stock, interval: CandleInterval) {
+        let now = self.elapsed();
+        let mut candles = self.candles.lock().unwrap();
+        let candles = candles.entry(stock.name.clone()).or_default();
+        let full = candles.last().is_none_or(|candle| match interval {
+            CandleInterval::Ticks(ticks) => candle.ticks >= ticks.max(1),
+            CandleInterval::Time(duration) => now >= candle.started + duration,
+        });
+        if full {
+            candles.push(Candle { started: now, ohlc: Ohlc::new(stock.prev_v), volume: 0, ticks: 0 });
+        }
+        let candle = candles.last_mut().unwrap();
+        candle.ohlc.update(stock.v);
+        candle.ticks += 1;
+        if let Some(retention) = self.retention() {
+            let excess = candles.len().saturating_sub(retention.max(1));
+            candles.drain(..excess);
+        }
+    }
+
+    // Counts filled shares toward the stock's latest candle, if candles are kept.
+    pub fn record_volume(&self, stock_name: &str, quantity: i32) {
+        if let Some(candle) = self.candles.lock().unwrap().get_mut(stock_name).and_then(|c| c.last_mut()) {
+            candle.volume += quantity as i64;
+        }
+    }
+
+    pub fn candles(&self) -> BTreeMap<String, Vec<Candle>> {
+        self.candles.lock().unwrap().iter().map(|(name, candles)| (name.to_string(), candles.clone())).collect()
+    }
+
+    pub fn ohlc(&self) -> BTreeMap<String, Ohlc> {
+        self.ohlc.lock().unwrap().iter().map(|(name, bar)| (name.to_string(), *bar)).collect()
+    }
+
+    pub fn inject_news(&self, sector: StockType, delta_bps: i32) {
+        self.news.lock().unwrap().push((sector, delta_bps));
+    }
+
+    fn take_news(&self) -> Vec<(StockType, i32)> {
+        std::mem::take(&mut *self.news.lock().unwrap())
+    }
+
+    pub fn register_broker(&self, name: &str) {
+        self.completion.lock().unwrap().insert(name.to_string(), false);
+    }
+
+    pub fn mark_complete(&self, name: &str) {
+        self.completion.lock().unwrap().insert(name.to_string(), true);
+    }
+
+    pub fn is_complete(&self, name: &str) -> bool {
+        self.completion.lock().unwrap().get(name).copied().unwrap_or(false)
+    }
+
+    // Receives every order the leader places from now on.
+    pub fn follow(&self, leader: &str) -> crossbeam_channel::Receiver<CopiedOrder> {
+        let (sender, receiver) = unbounded();
+        self.followers.lock().unwrap().entry(leader.to_string()).or_default().push(sender);
+        receiver
+    }
+
+    fn publish_order(&self, leader: &str, order: CopiedOrder) {
+        if let Some(followers) = self.followers.lock().unwrap().get_mut(leader) {
+            followers.retain(|sender| sender.send(order.clone()).is_ok());
+        }
+    }
+
+    fn acknowledge(&self, ack: OrderAck) {
+        // The control holds a receiver itself, so this cannot disconnect.
+        let _ = self.ack_sender.send(ack);
+        self.trim(&self.ack_receiver);
+    }
+
+    // Every order acknowledgment, in the order the orders were placed per
+    // broker. Receiving takes acks off the channel.
+    pub fn acks(&self) -> crossbeam_channel::Receiver<OrderAck> {
+        self.ack_receiver.clone()
+    }
+
+    fn print_to_tape(&self, entry: TapeEntry) {
+        // The control holds a receiver itself, so this cannot disconnect.
+        let _ = self.tape_sender.send(entry);
+        self.trim(&self.tape_receiver);
+    }
+
+    // Every order executed across all brokers as it finishes filling, paper
+    // orders aside. Receiving takes them off the tape, as with `acks`.
+    pub fn tape(&self) -> crossbeam_channel::Receiver<TapeEntry> {
+        self.tape_receiver.clone()
+    }
+
+    pub fn completion_status(&self) -> BTreeMap<String, bool> {
+        self.completion.lock().unwrap().clone()
+    }
+
+    pub fn request_shutdown(&self) {
+        self.shutdown.store(true, Ordering::SeqCst);
+    }
+
+    pub fn is_shutdown(&self) -> bool {
+        self.shutdown.load(Ordering::SeqCst)
+    }
+}
+
+impl Default for SimulationControl {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+// The run Ctrl-C stops. Only one handler can be installed per process, so
+// each run swaps itself in here rather than installing its own.
+#[cfg(feature = "ctrlc")]
+static INTERRUPT_TARGET: Mutex<Option<SimulationControl>> = Mutex::new(None);
+
+// Requests a clean shutdown of the latest run passed here on Ctrl-C. The
+// handler is installed on the first call; later calls point it at their run.
+#[cfg(feature = "ctrlc")]
+pub fn install_interrupt_handler(control: &SimulationControl) {
+    static INSTALL: std::sync::Once = std::sync::Once::new();
+    *INTERRUPT_TARGET.lock().unwrap() = Some(control.clone());
+    INSTALL.call_once(|| {
+        let installed = ctrlc::set_handler(|| {
+            if let Some(control) = INTERRUPT_TARGET.lock().unwrap().as_ref() {
+                println!("Interrupted, shutting down...");
+                control.request_shutdown();
+            }
+        });
+        if let Err(e) = installed {
+            println!("Ctrl-C handling unavailable: {}", e);
+        }
+    });
+}
+
+// Returns true if the watchdog fired, false if the simulation shut down first.
+pub fn spawn_watchdog(
+    control: SimulationControl,
+    timeout: Duration,
+    trigger_shutdown: bool,
+    verbosity: Verbosity,
+) -> JoinHandle<bool> {
+    thread::Builder::new().name("watchdog".to_string()).spawn(move || {
+        let poll = (timeout / 4).min(Duration::from_millis(100));
+        loop {
+            if control.is_shutdown() {
+                return false;
+            }
+            if control.idle_for() >= timeout {
+                if verbosity >= Verbosity::Orders {
+                    println!("WATCHDOG: no ticks or orders for {:?}, the simulation appears to be stalled", timeout);
+                }
+                if trigger_shutdown {
+                    control.request_shutdown();
+                }
+                return true;
+            }
+            thread::sleep(poll);
+        }
+    }).expect("failed to spawn watchdog thread")
+}
+
+struct Subscription {
+    name: String,
+    sectors: HashSet<StockType>,
+    sender: crossbeam_channel::Sender<Vec<PriceTick>>,
+}
+
+// How often, and how patiently, the simulator retries a send to a broker
+// whose tick channel is full. The wait doubles after each attempt.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SendRetry {
+    pub attempts: u32,
+    pub backoff: Duration,
+}
+
+impl Default for SendRetry {
+    fn default() -> Self {
+        SendRetry { attempts: 3, backoff: Duration::from_millis(1) }
+    }
+}
+
+// Fans ticks out to brokers, forwarding each tick only to the brokers whose
+// clients trade that stock's sector.
+#[derive(Default)]
+pub struct TickDispatcher {
+    subscribers: Vec<Subscription>,
+    verbosity: Verbosity,
+    // Capacity of each broker's channel; unbounded when unset.
+    buffer: Option<usize>,
+    retry: SendRetry,
+}
+
+impl TickDispatcher {
+    pub fn new() -> Self {
+        TickDispatcher { subscribers: Vec::new(), verbosity: Verbosity::default(), buffer: None, retry: SendRetry::default() }
+    }
+
+    // Channels subscribed from now on hold at most `capacity` messages.
+    pub fn with_buffer(capacity: Option<usize>) -> Self {
+        TickDispatcher { buffer: capacity, ..TickDispatcher::new() }
+    }
+
+    pub fn subscribe(&mut self, name: &str, sectors: HashSet<StockType>) -> crossbeam_channel::Receiver<Vec<PriceTick>> {
+        let (sender, receiver) = match self.buffer {
+            Some(capacity) => crossbeam_channel::bounded::<Vec<PriceTick>>(capacity),
+            None => unbounded::<Vec<PriceTick>>(),
+        };
+        self.subscribers.push(Subscription { name: name.to_string(), sectors, sender });
+        receiver
+    }
+
+    // Sends each subscriber the ticks in its sectors as one message. Returns
+    // false once every subscriber has hung up.
+    fn dispatch(&mut self, ticks: &[PriceTick]) -> bool {
+        self.subscribers.retain(|sub| {
+            let batch: Vec<PriceTick> = ticks
+                .iter()
+                .filter(|tick| tick.stock.try_stock_type().is_some_and(|t| sub.sectors.contains(&t)))
+                .cloned()
+                .collect();
+            if batch.is_empty() {
+                return true;
+            }
+            let connected = Self::send(sub, batch, &self.retry, self.verbosity);
+            if !connected && self.verbosity >= Verbosity::Orders {
+                println!("{} stopped receiving ticks.", sub.name);
+            }
+            connected
+        });
+        !self.subscribers.is_empty()
+    }
+
+    // A full channel is transient: the send is retried with backoff and the
+    // batch dropped if the broker still has not caught up. A disconnected
+    // channel is terminal. Returns false only for the latter.
+    fn send(sub: &Subscription, mut batch: Vec<PriceTick>, retry: &SendRetry, verbosity: Verbosity) -> bool {
+        let mut backoff = retry.backoff;
+        let mut attempt = 0;
+        loop {
+            match sub.sender.try_send(batch) {
+                Ok(()) => return true,
+                Err(crossbeam_channel::TrySendError::Disconnected(_)) => return false,
+                Err(crossbeam_channel::TrySendError::Full(rejected)) => {
+                    if attempt >= retry.attempts {
+                        if verbosity >= Verbosity::All {
+                            println!("{} is not keeping up; dropped {} ticks.", sub.name, rejected.len());
+                        }
+                        return true;
+                    }
+                    batch = rejected;
+                    attempt += 1;
+                    thread::sleep(backoff);
+                    backoff *= 2;
+                }
+            }
+        }
+    }
+
+    // Hangs up on every subscriber, so their receivers disconnect once drained.
+    fn close(&mut self) {
+        self.subscribers.clear();
+    }
+}
+
+pub fn broker_sectors(client_preferences: &HashMap<String, ClientPreference>) -> HashSet<StockType> {
+    client_preferences.values().flat_map(|prefs| prefs.sectors()).collect()
+}
+
+// Generates one cycle of prices at a time and hands each tick to the dispatcher.
+pub struct MarketSimulator {
+    config: SimulationConfig,
+    dispatcher: TickDispatcher,
+    control: SimulationControl,
+    rng: Box<dyn RandomSource>,
+    seq: u64,
+    day: u32,
+    // Each stock's price at the open of the current day.
+    day_open: HashMap<Ticker, i32>,
+    // Each stock's delta on its previous tick, for autocorrelated prices.
+    prev_delta: HashMap<Ticker, i32>,
+    // Unrounded prices of GBM-driven stocks, so small moves are not lost to
+    // rounding between ticks.
+    gbm_price: HashMap<Ticker, f64>,
+    // Previous day's closing prices, used as the first tick's reference when
+    // the reference is not reset at the open.
+    prior_close: HashMap<Ticker, i32>,
+    // Stocks ticked at least once.
+    started: HashSet<Ticker>,
+    // Trading days opened so far, closed days aside.
+    open_days: u32,
+    // Recorded ticks to play back instead of generating prices.
+    replay: Option<VecDeque<PriceTick>>,
+}
+
+impl MarketSimulator {
+    pub fn new(
+        config: SimulationConfig,
+        mut dispatcher: TickDispatcher,
+        control: SimulationControl,
+        rng: Box<dyn RandomSource>,
+    ) -> Self {
+        dispatcher.verbosity = config.verbosity;
+        dispatcher.retry = config.send_retry;
+        let day = (0..).find(|day| !config.closed_days.contains(day)).unwrap_or(0);
+        MarketSimulator {
+            config,
+            dispatcher,
+            control,
+            rng,
+            seq: 0,
+            day,
+            day_open: HashMap::new(),
+            prev_delta: HashMap::new(),
+            gbm_price: HashMap::new(),
+            prior_close: HashMap::new(),
+            started: HashSet::new(),
+            open_days: 1,
+            replay: None,
+        }
+    }
+
+    // Plays `ticks` back, one `seq` per cycle, instead of generating prices,
+    // and requests shutdown once they run out. Ticks for stocks that are not
+    // listed are passed over.
+    pub fn with_replay(mut self, mut ticks: Vec<PriceTick>) -> Self {
+        ticks.sort_by_key(|tick| tick.seq);
+        self.replay = Some(ticks.into());
+        self
+    }
+
+    // Disconnects every broker from the tick feed. Later cycles still move
+    // prices but reach no one.
+    pub fn close(&mut self) {
+        self.dispatcher.close();
+    }
+
+    pub fn cycle(&mut self, stocks: &mut [Stock]) {
+        let ticks = self.generate(stocks);
+        self.deliver(ticks);
+    }
+
+    // Moves every stock on by one cycle and returns the ticks, without
+    // sending them anywhere.
+    pub fn generate(&mut self, stocks: &mut [Stock]) -> Vec<PriceTick> {
+        let control = self.control.clone();
+        let verbosity = self.config.verbosity;
+        control.beat();
+        if self.replay.is_some() {
+            return self.replay_cycle(stocks);
+        }
+
+        if let Some(ticks_per_day) = self.config.ticks_per_day {
+            if self.seq > 0 && self.seq.is_multiple_of(ticks_per_day.max(1)) {
+                let mut nights = 1;
+                self.day += 1;
+                while self.config.closed_days.contains(&self.day) {
+                    self.day += 1;
+                    nights += 1;
+                }
+                self.open_days += 1;
+                let days = self.config.days.max(1);
+                if self.open_days > days {
+                    if verbosity >= Verbosity::Orders {
+                        println!("Trading ended after {} days.", days);
+                    }
+                    control.request_shutdown();
+                    return Vec::new();
+                }
+                self.day_open.clear();
+                for stock in stocks.iter_mut() {
+                    if !self.config.reset_reference_at_open {
+                        self.prior_close.insert(stock.name.clone(), stock.v);
+                    }
+                    stock.v += self.rng.delta(inclusive(&self.config.overnight_delta)) * nights;
+                    if verbosity >= Verbosity::Ticks {
+                        println!("OVERNIGHT GAP: name: {}, v:{}", stock.name, stock.v);
+                    }
+                }
+                if verbosity >= Verbosity::Orders {
+                    println!("Day {} opens.", self.day + 1);
+                }
+            }
+        }
+
+        let news = control.take_news();
+        if verbosity >= Verbosity::Orders {
+            for (sector, delta_bps) in &news {
+                println!("NEWS: {:?} shocked by {} bps", sector, delta_bps);
+            }
+        }
+        let market_shock = if self.config.stock_settings.values().any(|s| s.market_beta != 0.0) {
+            self.rng.delta(inclusive(&self.config.market_shock_bps))
+        } else {
+            0
+        };
+
+        let mut ticks = Vec::new();
+        for stock in stocks.iter_mut() {
+            // The run is stopped once these ticks are delivered, in `deliver`.
+            if self.max_ticks_reached() {
+                break;
+            }
+            let listed = (!self.started.contains(&stock.name)).then_some(stock.v);
+            self.step(stock, &news, market_shock);
+            // A custom `prev_v` only measures the first tick; the stock never
+            // traded there, so its OHLC and first candle open at the listed price.
+            match listed {
+                Some(v) => self.record(&Stock { prev_v: v, ..stock.clone() }),
+                None => self.record(stock),
+            }
+            ticks.push(PriceTick { stock: stock.clone(), day: self.day, seq: self.seq });
+        }
+        self.seq += 1;
+        ticks
+    }
+
+    fn replay_cycle(&mut self, stocks: &mut [Stock]) -> Vec<PriceTick> {
+        let replay = self.replay.as_mut().expect("only called while replaying");
+        let Some(seq) = replay.front().map(|tick| tick.seq) else {
+            if self.config.verbosity >= Verbosity::Orders {
+                println!("Replay ended after {} ticks.", self.control.ticks());
+            }
+            self.control.request_shutdown();
+            return Vec::new();
+        };
+        let mut recorded = Vec::new();
+        while replay.front().is_some_and(|tick| tick.seq == seq) {
+            recorded.extend(replay.pop_front());
+        }
+
+        let mut ticks = Vec::new();
+        for tick in recorded {
+            let Some(stock) = stocks.iter_mut().find(|s| s.name == tick.stock.name) else { continue };
+            stock.v = tick.stock.v;
+            stock.prev_v = tick.stock.prev_v;
+            self.day = tick.day;
+            self.record(stock);
+            ticks.push(PriceTick { stock: stock.clone(), day: tick.day, seq: self.seq });
+        }
+        self.seq += 1;
+        ticks
+    }
+
+    fn record(&self, stock: &Stock) {
+        if self.config.verbosity >= Verbosity::Ticks {
+            println!("STOCK UPDATE: name: {}, v:{}", stock.name, stock.v);
+        }
+        self.control.record_tick();
+        self.control.record_price(stock, self.config.retention);
+        if let Some(interval) = self.config.candle_interval {
+            let interval = match interval {
+                CandleInterval::Time(duration) => CandleInterval::Time(self.config.scaled(duration)),
+                ticks => ticks,
+            };
+            self.control.record_candle(stock, interval);
+        }
+    }
+
+    // Sends a cycle's ticks to the brokers, as one message or one per tick.
+    // Past `max_ticks` the run stops, but only after the last ticks are sent
+    // so that brokers still trade on them.
+    pub fn deliver(&mut self, ticks: Vec<PriceTick>) {
+        if self.config.batch_ticks {
+            if !ticks.is_empty() {
+                self.dispatcher.dispatch(&ticks);
+            }
+        } else {
+            for tick in ticks {
+                if !self.dispatcher.dispatch(std::slice::from_ref(&tick)) {
+                    break;
+                }
+            }
+        }
+        if self.max_ticks_reached() && !self.control.is_shutdown() {
+            if self.config.verbosity >= Verbosity::Orders {
+                println!("Reached the limit of {} ticks.", self.control.ticks());
+            }
+            self.control.request_shutdown();
+        }
+    }
+
+    fn max_ticks_reached(&self) -> bool {
+        self.config.max_ticks.is_some_and(|max| self.control.ticks() >= max)
+    }
+
+    // Simulated time since the current trading day opened.
+    fn time_of_day(&self) -> Duration {
+        let cycles = match self.config.ticks_per_day {
+            Some(ticks_per_day) => self.seq % ticks_per_day.max(1),
+            None => self.seq,
+        };
+        Duration::from_secs(cycles)
+    }
+
+    fn step(&mut self, stock: &mut Stock, news: &[(StockType, i32)], market_shock_bps: i32) {
+        let open = *self.day_open.entry(stock.name.clone()).or_insert(stock.v);
+        // A stock listed with its own `prev_v` keeps it for its first tick.
+        if self.started.insert(stock.name.clone()) && stock.prev_v != stock.v {
+            self.prior_close.entry(stock.name.clone()).or_insert(stock.prev_v);
+        }
+        stock.prev_v = self.prior_close.remove(&stock.name).unwrap_or(stock.v);
+        let volatility = self.config.volatility_at(self.time_of_day());
+
+        let model = self.config.stock_settings.get(&*stock.name).map(|s| s.model).unwrap_or_default();
+        match model {
+            PriceModel::RandomWalk => {
+                let noise = (self.rng.delta(-40..61) as f64 * volatility).round() as i32;
+                let prev_delta = self.prev_delta.get(&stock.name).copied().unwrap_or(0);
+                let delta = (self.config.autocorrelation * prev_delta as f64).round() as i32 + noise;
+                self.prev_delta.insert(stock.name.clone(), delta);
+                stock.v += delta;
+            }
+            PriceModel::Gbm { mu, sigma } => {
+                // Resync with the integer price after news, gaps or clamping moved it.
+                let price = self
+                    .gbm_price
+                    .get(&stock.name)
+                    .copied()
+                    .filter(|p| p.round() as i32 == stock.v)
+                    .unwrap_or(stock.v as f64);
+                let z = standard_normal(self.rng.as_mut());
+                let sigma = sigma * volatility;
+                let next = price * ((mu - sigma * sigma / 2.0) + sigma * z).exp();
+                self.gbm_price.insert(stock.name.clone(), next);
+                stock.v = next.round() as i32;
+            }
+        }
+
+        let stock_type = stock.try_stock_type();
+        for (sector, delta_bps) in news {
+            if stock_type.as_ref() == Some(sector) {
+                let shock = stock.prev_v as i64 * *delta_bps as i64 / 10_000;
+                stock.v = stock.v.saturating_add(shock.clamp(i32::MIN as i64, i32::MAX as i64) as i32);
+            }
+        }
+        if let Some(settings) = self.config.stock_settings.get(&*stock.name) {
+            let shock = (stock.prev_v as f64 * settings.market_beta * market_shock_bps as f64 / 10_000.0).round() as i32;
+            stock.v = stock.v.saturating_add(shock);
+            stock.v = settings.settle(stock.v, open);
+        }
+        if let Some(fair_value) = stock.fair_value.as_mut() {
+            *fair_value += self.rng.delta(inclusive(&self.config.fair_value_drift));
+        }
+    }
+}
+
+// Runs a job periodically: in real time on a thread pool, or on demand
+// against a `VirtualClock`.
+pub trait Scheduler {
+    fn execute_at_fixed_rate(&self, initial_delay: Duration, period: Duration, job: Box<dyn FnMut() + Send>);
+}
+
+impl Scheduler for ScheduledThreadPool {
+    fn execute_at_fixed_rate(&self, initial_delay: Duration, period: Duration, job: Box<dyn FnMut() + Send>) {
+        ScheduledThreadPool::execute_at_fixed_rate(self, initial_delay, period, job);
+    }
+}
+
+struct VirtualJob {
+    due: Duration,
+    period: Duration,
+    run: Box<dyn FnMut() + Send>,
+}
+
+// Simulated time that only moves when advanced. Jobs due in the meantime run
+// on the advancing thread, in due order, without sleeping.
+#[derive(Default)]
+pub struct VirtualClock {
+    now: Mutex<Duration>,
+    jobs: Mutex<Vec<VirtualJob>>,
+}
+
+impl VirtualClock {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn now(&self) -> Duration {
+        *self.now.lock().unwrap()
+    }
+
+    pub fn advance(&self, by: Duration) {
+        let until = self.now() + by;
+        // Jobs run unlocked so that they may schedule more jobs.
+        let mut jobs = std::mem::take(&mut *self.jobs.lock().unwrap());
+        while let Some(job) = jobs.iter_mut().filter(|job| job.due <= until).min_by_key(|job| job.due) {
+            *self.now.lock().unwrap() = job.due;
+            (job.run)();
+            job.due += job.period;
+        }
+        *self.now.lock().unwrap() = until;
+        self.jobs.lock().unwrap().append(&mut jobs);
+    }
+}
+
+impl Scheduler for VirtualClock {
+    fn execute_at_fixed_rate(&self, initial_delay: Duration, period: Duration, job: Box<dyn FnMut() + Send>) {
+        // A zero period would never let virtual time move past the job.
+        let period = period.max(Duration::from_nanos(1));
+        self.jobs.lock().unwrap().push(VirtualJob { due: self.now() + initial_delay, period, run: job });
+    }
+}
+
+pub fn simulate_stock_changes(
+    sched: &dyn Scheduler,
+    shared_stock: Arc<Mutex<Vec<Stock>>>,
+    dispatcher: TickDispatcher,
+    control: SimulationControl,
+    config: &SimulationConfig,
+    rng: Box<dyn RandomSource>,
+) {
+    schedule_simulator(sched, shared_stock, MarketSimulator::new(config.clone(), dispatcher, control, rng));
+}
+
+// Shutdown runs in one direction: the stop flag is set, the simulator's next
+// cycle closes every broker's tick channel instead of ticking, and brokers
+// exit once their channel disconnects (or sooner, on seeing the flag between
+// polls). Nothing waits on a thread that has already stopped.
+pub fn schedule_simulator(sched: &dyn Scheduler, shared_stock: Arc<Mutex<Vec<Stock>>>, mut simulator: MarketSimulator) {
+    let control = simulator.control.clone();
+    sched.execute_at_fixed_rate(
+        Duration::from_micros(100),
+        simulator.config.scaled(Duration::from_secs(1)),
+        Box::new(move || {
+            if control.is_shutdown() {
+                simulator.close();
+                return;
+            }
+            let ticks = simulator.generate(&mut shared_stock.lock().unwrap());
+            // Sending can wait on a stalled broker, so the stocks are unlocked
+            // first to keep snapshots and live listings from waiting with it.
+            simulator.deliver(ticks);
+        }),
+    );
+}
+
+#[derive(Debug, Clone)]
+pub struct ClientPreference {
+    pub stock_type: StockType,
+    pub order_category: String,
+    pub min_change_buy: i32,
+    pub min_change_sell: i32,
+    // Sell the whole position once the price falls this far below the average entry price.
+    pub stop_loss: Option<i32>,
+    // No new buy of a stock is allowed for this long after its stop-loss fired.
+    pub stop_loss_cooldown: Duration,
+    // Sell the whole position once the price falls this many basis points
+    // below the highest price seen since entry.
+    pub trailing_stop_bps: Option<i32>,
+    // Let a client act on only one side of the price signal. Stop exits are
+    // not affected.
+    pub enable_buy: bool,
+    pub enable_sell: bool,
+    // Stop trading for the client, resting orders included, once realized
+    // earnings reach this amount.
+    pub profit_target: Option<Money>,
+    // Replaces `stock_type` with a sector that changes over time.
+    pub rotation: Option<SectorRotation>,
+    // Funds the client's account (or adds to a broker's pool) and stops buys
+    // that would spend more than the cash on hand. Unset means unlimited cash.
+    pub starting_cash: Option<Money>,
+    pub flat_tick: FlatTick,
+    pub threshold_inclusivity: ThresholdInclusivity,
+    // Buys and sells are sized separately, e.g. to accumulate with large
+    // buys and trim with small sells.
+    pub buy_qty_strategy: QuantityStrategy,
+    pub sell_qty_strategy: QuantityStrategy,
+    // The client only looks at ticks from the first offset up to the second,
+    // both measured from the start of the run.
+    pub active_window: Option<(Duration, Duration)>,
+    // Higher-priority clients act on a tick before the broker's other clients.
+    pub priority: u8,
+    // Trade on the gap to the stock's fair value instead of on price changes:
+    // buy at least this far below it, sell at least this far above it.
+    pub value_threshold: Option<i32>,
+    // Market clients otherwise trade on any move; this filters out the
+    // smallest ones, on both sides.
+    pub market_min_change: i32,
+    // Paper orders are recorded and filled against the client's account
+    // but have no market impact: they do not count toward order caps or
+    // sector limits, take no liquidity, add no candle volume or trade
+    // statistics and are not copied by followers.
+    pub paper: bool,
+    // Trades the price ratio of two stocks instead of single-stock moves.
+    pub pair: Option<PairTrade>,
+    // Shrinks signal orders in volatile stocks. Stop exits are not affected.
+    pub risk_budget: Option<RiskBudget>,
+    // When non-zero, buys once a stock has risen for this many consecutive
+    // ticks and sells once it has fallen for as many, instead of trading on
+    // single price changes.
+    pub momentum_lookback: u32,
+    // The opposite bet: buys after this many consecutive falling ticks and
+    // sells after as many rising ones. Ignored while `momentum_lookback` is set.
+    pub mean_reversion_lookback: u32,
+    // Once the client's equity (cash plus positions at the latest prices)
+    // falls this far below its peak, every position is sold and the client
+    // stops trading, its resting orders cancelled. In a pooled broker this
+    // is the pool's equity, and every client in the pool stops.
+    pub max_drawdown: Option<Money>,
+}
+
+// Orders are sized as usual while the stock's realized volatility over the
+// last `window` returns is at most `target_vol`, and scaled down by
+// `target_vol / vol` above it, to no less than one share. Until the stock
+// has that much history, orders are not scaled.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct RiskBudget {
+    pub target_vol: f64,
+    pub window: usize,
+}
+
+impl RiskBudget {
+    fn scale(&self, quantity: i32, prices: &[i32]) -> i32 {
+        if self.window < 2 || prices.len() < self.window + 1 {
+            return quantity;
+        }
+        match realized_vol(&prices[prices.len() - self.window - 1..]) {
+            Some(vol) if vol > self.target_vol => ((quantity as f64 * self.target_vol / vol) as i32).max(1),
+            _ => quantity,
+        }
+    }
+}
+
+// Pairs trading on `first / second`. Once the ratio is `threshold` below
+// `mean_ratio`, the client buys `first` and sells any `second` it holds;
+// once it is `threshold` above, the other way round. The position is unwound
+// when the ratio gets back to the mean. Both stocks must be in the client's
+// sector, and each leg goes through the same checks as any signal order.
+#[derive(Debug, Clone, PartialEq)]
+pub struct PairTrade {
+    pub first: String,
+    pub second: String,
+    pub mean_ratio: f64,
+    pub threshold: f64,
+}
+
+// An open pair trade: the leg bought and the shares sold of the other leg.
+#[derive(Debug, Clone)]
+struct PairPosition {
+    long: Ticker,
+    short_qty: i32,
+}
+
+// `n` clients for load testing, named "Client 1" to "Client n", all
+// trading `sector` with no price-change thresholds.
+pub fn generate_clients(n: usize, sector: StockType, category: &str) -> HashMap<String, ClientPreference> {
+    (1..=n)
+        .map(|i| (format!("Client {}", i), ClientPreference::new(sector.clone(), category, 0, 0)))
+        .collect()
+}
+
+// What a client does with a tick whose price did not move.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum FlatTick {
+    #[default]
+    Ignore,
+    // Record a "no change" signal so the tick shows up in the broker's signals.
+    Record,
+}
+
+// How a client sizes the orders placed on its price signals.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum QuantityStrategy {
+    // Drawn from the broker's quantity source.
+    Random(Range<i32>),
+    Fixed(i32),
+}
+
+impl Default for QuantityStrategy {
+    fn default() -> Self {
+        QuantityStrategy::Random(10..101)
+    }
+}
+
+impl QuantityStrategy {
+    fn quantity(&self, rng: &mut dyn RandomSource) -> i32 {
+        match self {
+            QuantityStrategy::Random(range) => rng.delta(range.clone()),
+            QuantityStrategy::Fixed(quantity) => *quantity,
+        }
+    }
+}
+
+// Whether a price change exactly equal to `min_change_buy` (downwards) or
+// `min_change_sell` (upwards) triggers an order. Applies to both sides alike.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum ThresholdInclusivity {
+    // A move of exactly the threshold trades.
+    #[default]
+    Inclusive,
+    // The move must be strictly larger than the threshold.
+    Exclusive,
+}
+
+impl ThresholdInclusivity {
+    // Whether a move of `distance` (always positive) reaches `threshold`.
+    pub fn reaches(self, distance: i32, threshold: i32) -> bool {
+        match self {
+            ThresholdInclusivity::Inclusive => distance >= threshold,
+            ThresholdInclusivity::Exclusive => distance > threshold,
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct SectorRotation {
+    // Simulator cycles spent on each sector before moving to the next.
+    pub every_ticks: u64,
+    pub sectors: Vec<StockType>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Error)]
+pub enum ClientError {
+    // `min_change_buy`, `min_change_sell` and `market_min_change` are distances
+    // and must not be negative.
+    #[error("price change threshold {0} is negative")]
+    NegativeThreshold(i32),
+    // A `QuantityStrategy::Random` range with nothing to draw from.
+    #[error("quantity range {0:?} is empty")]
+    EmptyQuantityRange(Range<i32>),
+}
+
+impl ClientPreference {
+    pub fn try_new(
+        stock_type: StockType,
+        order_category: &str,
+        min_change_buy: i32,
+        min_change_sell: i32,
+    ) -> Result<Self, StockError> {
+        let prefs = ClientPreference::new(stock_type, order_category, min_change_buy, min_change_sell);
+        prefs.validate()?;
+        Ok(prefs)
+    }
+
+    pub fn validate(&self) -> Result<(), StockError> {
+        Ok(self.check()?)
+    }
+
+    pub(crate) fn check(&self) -> Result<(), ClientError> {
+        for threshold in [self.min_change_buy, self.min_change_sell, self.market_min_change] {
+            if threshold < 0 {
+                return Err(ClientError::NegativeThreshold(threshold));
+            }
+        }
+        for strategy in [&self.buy_qty_strategy, &self.sell_qty_strategy] {
+            if let QuantityStrategy::Random(range) = strategy {
+                if range.is_empty() {
+                    return Err(ClientError::EmptyQuantityRange(range.clone()));
+                }
+            }
+        }
+        Ok(())
+    }
+
+    pub fn new(stock_type: StockType, order_category: &str, min_change_buy: i32, min_change_sell: i32) -> Self {
+        ClientPreference {
+            stock_type,
+            order_category: order_category.to_string(),
+            min_change_buy,
+            min_change_sell,
+            stop_loss: None,
+            stop_loss_cooldown: Duration::ZERO,
+            trailing_stop_bps: None,
+            enable_buy: true,
+            enable_sell: true,
+            profit_target: None,
+            rotation: None,
+            starting_cash: None,
+            flat_tick: FlatTick::Ignore,
+            threshold_inclusivity: ThresholdInclusivity::Inclusive,
+            buy_qty_strategy: QuantityStrategy::default(),
+            sell_qty_strategy: QuantityStrategy::default(),
+            active_window: None,
+            market_min_change: 0,
+            paper: false,
+            pair: None,
+            risk_budget: None,
+            momentum_lookback: 0,
+            mean_reversion_lookback: 0,
+            max_drawdown: None,
+            priority: 0,
+            value_threshold: None,
+        }
+    }
+
+    // Trades Tech, then Food, then Healthcare, moving on every `every_ticks` cycles.
+    pub fn sector_rotation(every_ticks: u64, order_category: &str, min_change_buy: i32, min_change_sell: i32) -> Self {
+        let sectors = vec![StockType::Tech, StockType::Food, StockType::Healthcare];
+        ClientPreference {
+            rotation: Some(SectorRotation { every_ticks, sectors }),
+            ..ClientPreference::new(StockType::Tech, order_category, min_change_buy, min_change_sell)
+        }
+    }
+
+    // Follows runs of `lookback` consecutive ticks in the same direction.
+    pub fn momentum(stock_type: StockType, order_category: &str, lookback: u32) -> Self {
+        ClientPreference { momentum_lookback: lookback, ..ClientPreference::new(stock_type, order_category, 0, 0) }
+    }
+
+    // Bets on a bounce after runs of `lookback` consecutive ticks in the same direction.
+    pub fn mean_reversion(stock_type: StockType, order_category: &str, lookback: u32) -> Self {
+        ClientPreference { mean_reversion_lookback: lookback, ..ClientPreference::new(stock_type, order_category, 0, 0) }
+    }
+
+    // Buys stocks trading `threshold` below fair value and sells them once
+    // they trade `threshold` above it.
+    pub fn value_investing(stock_type: StockType, order_category: &str, threshold: i32) -> Self {
+        ClientPreference { value_threshold: Some(threshold), ..ClientPreference::new(stock_type, order_category, 0, 0) }
+    }
+
+    pub fn is_active_at(&self, elapsed: Duration) -> bool {
+        self.active_window.is_none_or(|(from, until)| elapsed >= from && elapsed < until)
+    }
+
+    // The sector the client trades during simulator cycle `seq`.
+    pub fn sector_at(&self, seq: u64) -> StockType {
+        match &self.rotation {
+            Some(rotation) if !rotation.sectors.is_empty() => {
+                let index = (seq / rotation.every_ticks.max(1)) as usize % rotation.sectors.len();
+                rotation.sectors[index].clone()
+            }
+            _ => self.stock_type.clone(),
+        }
+    }
+
+    // Every sector the client may trade over the run.
+    pub fn sectors(&self) -> Vec<StockType> {
+        match &self.rotation {
+            Some(rotation) if !rotation.sectors.is_empty() => rotation.sectors.clone(),
+            _ => vec![self.stock_type.clone()],
+        }
+    }
+
+    pub fn is_market(&self) -> bool {
+        self.order_category == "Market"
+    }
+}
+
+struct BrokerState {
+    name: String,
+    config: BrokerConfig,
+    // Ordered so that clients are evaluated, and draw random numbers, in a
+    // stable order.
+    client_preferences: BTreeMap<String, ClientPreference>,
+    transaction_limit: i32,
+    client_transactions: HashMap<String, i32>,
+    accounts: HashMap<String, Account>,
+    orders: Vec<Order>,
+    skipped: Vec<SkippedOrder>,
+    signals: Vec<Signal>,
+    // (client, stock) -> when that client's stop-loss last fired on the stock.
+    stop_loss_fired: HashMap<(String, Ticker), Instant>,
+    // Realized earnings per client, kept separately from accounts so that
+    // pooled clients can still be tracked individually.
+    client_earnings: HashMap<String, Money>,
+    client_volume: HashMap<String, i32>,
+    trades: HashMap<Ticker, TradeStats>,
+    // Indices into `orders` of limit orders that are not yet fully filled.
+    resting: Vec<usize>,
+    // Liquidity left on the current tick, per stock.
+    liquidity: HashMap<Ticker, i32>,
+    // Orders placed by clients not in paper mode, for `order_cap`.
+    live_orders: usize,
+    // Each stock as of its latest tick, for strategies that look at several.
+    last_prices: HashMap<Ticker, Stock>,
+    // Each stock as it stood before its first tick.
+    first_prices: HashMap<Ticker, Stock>,
+    // Price of each stock's latest live fill, for the fat-finger check.
+    last_fills: HashMap<Ticker, i32>,
+    pair_positions: HashMap<String, PairPosition>,
+    // Recent prices per stock, as many as the longest risk-budget window needs.
+    recent_prices: HashMap<Ticker, VecDeque<i32>>,
+    vol_window: usize,
+    // Consecutive ticks each stock has moved in one direction: positive while
+    // rising, negative while falling, zero after a flat tick.
+    streaks: HashMap<Ticker, i32>,
+    // Highest equity seen per account with a client that has a drawdown limit.
+    peak_equity: HashMap<String, Money>,
+    // Clients that no longer trade, e.g. after reaching their profit target.
+    stopped_clients: HashSet<String>,
+    control: SimulationControl,
+    day: u32,
+    seq: u64,
+    rng: Box<dyn RandomSource>,
+    verbosity: Verbosity,
+}
+
+impl BrokerState {
+    fn new(
+        name: String,
+        config: BrokerConfig,
+        client_preferences: HashMap<String, ClientPreference>,
+        transaction_limit: i32,
+        control: SimulationControl,
+        rng: Box<dyn RandomSource>,
+        verbosity: Verbosity,
+    ) -> Self {
+        let client_transactions = client_preferences.keys().map(|k| (k.clone(), 0)).collect();
+        let vol_window = client_preferences.values().filter_map(|p| p.risk_budget.map(|r| r.window)).max().unwrap_or(0);
+        let mut state = BrokerState {
+            name,
+            config,
+            client_preferences: client_preferences.into_iter().collect(),
+            transaction_limit,
+            client_transactions,
+            accounts: HashMap::new(),
+            orders: Vec::new(),
+            skipped: Vec::new(),
+            signals: Vec::new(),
+            stop_loss_fired: HashMap::new(),
+            client_earnings: HashMap::new(),
+            client_volume: HashMap::new(),
+            trades: HashMap::new(),
+            resting: Vec::new(),
+            liquidity: HashMap::new(),
+            live_orders: 0,
+            last_prices: HashMap::new(),
+            first_prices: HashMap::new(),
+            last_fills: HashMap::new(),
+            pair_positions: HashMap::new(),
+            recent_prices: HashMap::new(),
+            vol_window,
+            streaks: HashMap::new(),
+            peak_equity: HashMap::new(),
+            stopped_clients: HashSet::new(),
+            control,
+            day: 0,
+            seq: 0,
+            rng,
+            verbosity,
+        };
+        let funding: Vec<(String, Money)> = state
+            .client_preferences
+            .iter()
+            .filter_map(|(client_name, prefs)| prefs.starting_cash.map(|cash| (state.account_name(client_name), cash)))
+            .collect();
+        for (account_name, cash) in funding {
+            let account = state.accounts.entry(account_name).or_default();
+            account.cash += cash;
+            account.funded += cash;
+            account.cash_limited = true;
+        }
+        state
+    }
+
+    fn is_done(&self) -> bool {
+        !self
+            .client_transactions
+            .iter()
+            .any(|(client, &v)| v < self.transaction_limit && !self.stopped_clients.contains(client) && !self.capped(client))
+    }
+
+    fn is_paper(&self, client_name: &str) -> bool {
+        self.client_preferences.get(client_name).is_some_and(|prefs| prefs.paper)
+    }
+
+    // Whether a shared cap keeps the client from placing further orders.
+    // Paper clients are never capped.
+    fn capped(&self, client_name: &str) -> bool {
+        !self.is_paper(client_name) && (self.cap_reached() || self.sectors_exhausted(client_name))
+    }
+
+    // True once every sector the client trades has hit its exchange-wide limit.
+    fn sectors_exhausted(&self, client_name: &str) -> bool {
+        self.client_preferences
+            .get(client_name)
+            .is_some_and(|prefs| prefs.sectors().iter().all(|sector| self.control.sector_exhausted(sector)))
+    }
+
+    // Final checks before an order is placed. Skips orders priced too far
+    // from the stock's last traded price, and live orders over the stock's
+    // throttle or whose sector has no trades left under the exchange-wide
+    // limit.
+    fn admit(&mut self, client_name: &str, prefs: &ClientPreference, stock: &Stock, order_type: &str, quantity: i32) -> bool {
+        if let Some(max_bps) = self.config.fat_finger_bps {
+            let last = self.last_fills.get(&stock.name).copied().unwrap_or(stock.prev_v);
+            let deviation = (stock.v as i64 - last as i64).abs() * 10_000;
+            if last != 0 && deviation > max_bps as i64 * (last as i64).abs() {
+                let reason = SkipReason::FatFinger { price: stock.v, last, max_bps };
+                self.skip(client_name, stock, order_type, quantity, reason);
+                return false;
+            }
+        }
+
+        if prefs.paper {
+            return true;
+        }
+        if let Err(OrderThrottle { max_orders, interval }) = self.control.reserve_throttled_order(&stock.name) {
+            self.skip(client_name, stock, order_type, quantity, SkipReason::Throttled { max_orders, interval });
+            return false;
+        }
+        // Unclassified stocks count against no sector's limit.
+        let Some(sector) = stock.try_stock_type() else { return true };
+        match self.control.reserve_sector_trade(&sector) {
+            Ok(()) => true,
+            Err(limit) => {
+                self.skip(client_name, stock, order_type, quantity, SkipReason::SectorLimit { sector, limit });
+                false
+            }
+        }
+    }
+
+    fn cap_reached(&self) -> bool {
+        self.config.order_cap.is_some_and(|cap| self.live_orders >= cap)
+    }
+
+    // Notional of all open positions across the broker's accounts, at the
+    // latest prices.
+    fn exposure(&self) -> Money {
+        self.accounts
+            .values()
+            .flat_map(|account| account.positions.keys().map(move |name| (account, name)))
+            .map(|(account, name)| {
+                let price = self.last_prices.get(name.as_str()).map_or(0, |stock| stock.v);
+                (account.held(name) as Money).abs() * price as Money
+            })
+            .sum()
+    }
+
+    // Realized losses across the broker's accounts on the current day.
+    fn daily_loss(&self) -> Money {
+        let pnl: Money = self.accounts.values().filter_map(|a| a.daily_realized_pnl.get(&self.day)).sum();
+        -pnl
+    }
+
+    fn account_name(&self, client_name: &str) -> String {
+        match self.config.mode {
+            BrokerMode::Individual => client_name.to_string(),
+            BrokerMode::Pooled => POOL_ACCOUNT.to_string(),
+        }
+    }
+
+    fn on_ticks(&mut self, ticks: &[PriceTick]) {
+        for tick in ticks {
+            if self.is_done() {
+                break;
+            }
+            self.on_tick(tick);
+        }
+    }
+
+    fn on_tick(&mut self, tick: &PriceTick) {
+        self.day = tick.day;
+        self.seq = tick.seq;
+        self.fill_resting(&tick.stock);
+        self.last_prices.insert(tick.stock.name.clone(), tick.stock.clone());
+        self.first_prices
+            .entry(tick.stock.name.clone())
+            .or_insert_with(|| Stock { v: tick.stock.prev_v, ..tick.stock.clone() });
+        if self.vol_window > 0 {
+            let prices = self.recent_prices.entry(tick.stock.name.clone()).or_default();
+            prices.push_back(tick.stock.v);
+            while prices.len() > self.vol_window + 1 {
+                prices.pop_front();
+            }
+        }
+        let streak = self.streaks.entry(tick.stock.name.clone()).or_insert(0);
+        *streak = match tick.stock.change().signum() {
+            1 => (*streak).max(0).saturating_add(1),
+            -1 => (*streak).min(0).saturating_sub(1),
+            _ => 0,
+        };
+        if self.config.copy.is_some() {
+            return;
+        }
+        let client_preferences = std::mem::take(&mut self.client_preferences);
+        let mut clients: Vec<_> = client_preferences.iter().collect();
+        clients.sort_by_key(|(_, prefs)| std::cmp::Reverse(prefs.priority));
+        for (client_name, prefs) in clients {
+            if self.check_drawdown(client_name, prefs) && self.config.mode == BrokerMode::Pooled {
+                // The pool's clients share its equity, so they all stop with it.
+                for client in client_preferences.keys() {
+                    self.stop_client(client);
+                }
+            }
+            self.evaluate_client(client_name, prefs, &tick.stock);
+        }
+        self.client_preferences = client_preferences;
+        self.prune_orders();
+    }
+
+    // Places a scaled copy of a leader's order for each of the broker's clients.
+    fn on_copied(&mut self, copied: &CopiedOrder) {
+        let Some(copy) = &self.config.copy else { return };
+        let quantity = (copied.quantity as f64 * copy.scale).round() as i32;
+        if quantity <= 0 {
+            return;
+        }
+        let client_preferences = std::mem::take(&mut self.client_preferences);
+        for (client_name, prefs) in &client_preferences {
+            if self.stopped_clients.contains(client_name) || (!prefs.paper && self.cap_reached()) {
+                continue;
+            }
+            if !self.admit(client_name, prefs, &copied.stock, &copied.order_type, quantity) {
+                continue;
+            }
+            let reason = OrderReason::Copied { leader_order: copied.order_id };
+            self.execute(client_name, prefs, &copied.stock, &copied.order_type, quantity, reason);
+        }
+        self.client_preferences = client_preferences;
+        self.prune_orders();
+    }
+
+    // Drops the oldest records beyond the retention limit. Orders still
+    // resting are kept however old, and `resting` follows them to their new
+    // indices.
+    fn prune_orders(&mut self) {
+        let Some(retention) = self.config.order_retention else { return };
+        let excess = self.orders.len().saturating_sub(retention);
+        if excess > 0 && self.orders.len() > self.resting.len() {
+            let resting: HashSet<usize> = self.resting.iter().copied().collect();
+            let mut moved = HashMap::new();
+            let mut dropped = 0;
+            for (index, order) in std::mem::take(&mut self.orders).into_iter().enumerate() {
+                let keep = resting.contains(&index);
+                if !keep && dropped < excess {
+                    dropped += 1;
+                    continue;
+                }
+                if keep {
+                    moved.insert(index, self.orders.len());
+                }
+                self.orders.push(order);
+            }
+            for index in &mut self.resting {
+                *index = moved[index];
+            }
+        }
+        let excess = self.skipped.len().saturating_sub(retention);
+        self.skipped.drain(..excess);
+        let excess = self.signals.len().saturating_sub(retention);
+        self.signals.drain(..excess);
+    }
+
+    // Stops the client trading and cancels what it has resting. Returns false
+    // if it had already stopped.
+    fn stop_client(&mut self, client_name: &str) -> bool {
+        if !self.stopped_clients.insert(client_name.to_string()) {
+            return false;
+        }
+        let (cancelled, resting): (Vec<usize>, Vec<usize>) =
+            std::mem::take(&mut self.resting).into_iter().partition(|&i| self.orders[i].client_name == client_name);
+        self.resting = resting;
+        for index in cancelled {
+            self.print_order(index);
+        }
+        true
+    }
+
+    // Liquidates the client and stops its trading once its equity has fallen
+    // more than `max_drawdown` below its peak, and returns true if it did.
+    // The sells bypass the broker's admission checks so the client is always
+    // left flat.
+    fn check_drawdown(&mut self, client_name: &str, prefs: &ClientPreference) -> bool {
+        let Some(limit) = prefs.max_drawdown else { return false };
+        if self.stopped_clients.contains(client_name) {
+            return false;
+        }
+        let prices: HashMap<String, i32> = self.last_prices.iter().map(|(name, s)| (name.to_string(), s.v)).collect();
+        let account_name = self.account_name(client_name);
+        let account = self.accounts.entry(account_name.clone()).or_default();
+        let equity = account.cash + account.market_value(&prices);
+        let peak = self.peak_equity.entry(account_name).or_insert(equity);
+        *peak = (*peak).max(equity);
+        let drawdown = *peak - equity;
+        if drawdown <= limit {
+            return false;
+        }
+        let held: Vec<(Stock, i32)> = account
+            .positions
+            .keys()
+            .map(|name| (name, account.held(name)))
+            .filter(|(_, held)| *held > 0)
+            .filter_map(|(name, held)| self.last_prices.get(name.as_str()).map(|stock| (stock.clone(), held)))
+            .collect();
+        self.stop_client(client_name);
+        if self.verbosity >= Verbosity::Orders {
+            println!("{} stopped trading for client {}: drawdown of ${} exceeds ${}.", self.name, client_name, drawdown, limit);
+        }
+        for (stock, quantity) in held {
+            self.execute(client_name, prefs, &stock, "selling", quantity, OrderReason::Drawdown { drawdown });
+        }
+        true
+    }
+
+    fn evaluate_client(&mut self, client_name: &str, prefs: &ClientPreference, stock: &Stock) {
+        if stock.try_stock_type() != Some(prefs.sector_at(self.seq))
+            || self.stopped_clients.contains(client_name)
+            || (!prefs.paper && self.cap_reached())
+            || !prefs.is_active_at(self.control.elapsed())
+        {
+            return;
+        }
+
+        if let Some(pair) = &prefs.pair {
+            self.evaluate_pair(client_name, prefs, pair, stock);
+            return;
+        }
+
+        if let Some(stop_loss) = prefs.stop_loss {
+            let account = self.accounts.entry(self.account_name(client_name)).or_default();
+            let held = account.held(&stock.name);
+            let triggered = account.average_price(&stock.name).is_some_and(|avg| stock.v <= avg - stop_loss);
+            if held > 0 && triggered {
+                if !self.admit(client_name, prefs, stock, "selling", held) {
+                    return;
+                }
+                self.execute(client_name, prefs, stock, "selling", held, OrderReason::StopLoss);
+                self.stop_loss_fired.insert((client_name.to_string(), stock.name.clone()), Instant::now());
+                return;
+            }
+        }
+
+        if let Some(bps) = prefs.trailing_stop_bps {
+            let account = self.accounts.entry(self.account_name(client_name)).or_default();
+            let held = account.held(&stock.name);
+            if held > 0 {
+                let high = account.mark_high(&stock.name, stock.v);
+                if stock.v as i64 * 10_000 <= high as i64 * (10_000 - bps) as i64 {
+                    if !self.admit(client_name, prefs, stock, "selling", held) {
+                        return;
+                    }
+                    self.execute(client_name, prefs, stock, "selling", held, OrderReason::TrailingStop { high });
+                    return;
+                }
+            }
+        }
+
+        let Some((order_type, reason)) = self.signal(client_name, prefs, stock) else { return };
+        if (order_type == "buying" && !prefs.enable_buy) || (order_type == "selling" && !prefs.enable_sell) {
+            return;
+        }
+
+        let qty_strategy = if order_type == "buying" { &prefs.buy_qty_strategy } else { &prefs.sell_qty_strategy };
+        let mut quantity = qty_strategy.quantity(self.rng.as_mut());
+        if let (Some(budget), Some(prices)) = (prefs.risk_budget, self.recent_prices.get_mut(&stock.name)) {
+            quantity = budget.scale(quantity, prices.make_contiguous());
+        }
+        self.place(client_name, prefs, stock, order_type, quantity, reason);
+    }
+
+    // Runs a signal order through the broker's checks, sizing it down to the
+    // shares held and the lot size, and places it if they all pass. Returns
+    // the quantity placed.
+    fn place(
+        &mut self,
+        client_name: &str,
+        prefs: &ClientPreference,
+        stock: &Stock,
+        order_type: &str,
+        mut quantity: i32,
+        reason: OrderReason,
+    ) -> Option<i32> {
+        if order_type == "buying" {
+            let key = (client_name.to_string(), stock.name.clone());
+            if let Some(fired) = self.stop_loss_fired.get(&key) {
+                let elapsed = fired.elapsed();
+                if elapsed < prefs.stop_loss_cooldown {
+                    let remaining = prefs.stop_loss_cooldown - elapsed;
+                    self.skip(client_name, stock, order_type, quantity, SkipReason::StopLossCooldown { remaining });
+                    return None;
+                }
+            }
+
+            if let Some(limit) = self.config.max_daily_loss {
+                let loss = self.daily_loss();
+                if loss > limit {
+                    self.skip(client_name, stock, order_type, quantity, SkipReason::DailyLossLimit { loss, limit });
+                    return None;
+                }
+            }
+
+            if let Some(limit) = self.config.max_broker_exposure {
+                let exposure = self.exposure();
+                if exposure + quantity as Money * stock.v as Money > limit {
+                    self.skip(client_name, stock, order_type, quantity, SkipReason::ExposureLimit { exposure, limit });
+                    return None;
+                }
+            }
+        }
+
+        let mut clamped_from = None;
+        if order_type == "selling" {
+            let held = self.accounts.get(&self.account_name(client_name)).map_or(0, |a| a.held(&stock.name));
+            if held <= 0 {
+                self.skip(client_name, stock, order_type, quantity, SkipReason::InsufficientHoldings { held, requested: quantity });
+                return None;
+            }
+            if quantity > held {
+                clamped_from = Some(quantity);
+                quantity = held;
+            }
+        }
+
+        let lot_size = self.config.lot_size.max(1);
+        if quantity % lot_size != 0 {
+            let rounded = quantity / lot_size * lot_size;
+            if rounded == 0 {
+                self.skip(client_name, stock, order_type, quantity, SkipReason::BelowLotSize { quantity, lot_size });
+                return None;
+            }
+            quantity = rounded;
+        }
+
+        let notional = quantity as Money * stock.v as Money;
+        if notional < self.config.min_order_notional {
+            let min = self.config.min_order_notional;
+            self.skip(client_name, stock, order_type, quantity, SkipReason::BelowMinNotional { notional, min });
+            return None;
+        }
+
+        if order_type == "buying" {
+            let account = self.accounts.entry(self.account_name(client_name)).or_default();
+            if account.cash_limited && notional > account.cash {
+                let available = account.cash;
+                self.skip(client_name, stock, order_type, quantity, SkipReason::InsufficientCash { needed: notional, available });
+                return None;
+            }
+        }
+
+        if !self.admit(client_name, prefs, stock, order_type, quantity) {
+            return None;
+        }
+        let order = self.execute(client_name, prefs, stock, order_type, quantity, reason);
+        order.clamped_from = clamped_from;
+        Some(quantity)
+    }
+
+    fn evaluate_pair(&mut self, client_name: &str, prefs: &ClientPreference, pair: &PairTrade, stock: &Stock) {
+        if *stock.name != pair.first && *stock.name != pair.second {
+            return;
+        }
+        let (Some(first), Some(second)) = (self.last_prices.get(pair.first.as_str()), self.last_prices.get(pair.second.as_str()))
+        else {
+            return;
+        };
+        if second.v == 0 {
+            return;
+        }
+        let (first, second) = (first.clone(), second.clone());
+        let ratio = first.v as f64 / second.v as f64;
+        let ratio_bps = (ratio * 10_000.0).round() as i64;
+
+        match self.pair_positions.get(client_name).cloned() {
+            None => {
+                let (long, short) = if ratio <= pair.mean_ratio - pair.threshold {
+                    (first, second)
+                } else if ratio >= pair.mean_ratio + pair.threshold {
+                    (second, first)
+                } else {
+                    return;
+                };
+                let reason = OrderReason::PairDiverged { ratio_bps };
+                let quantity = prefs.buy_qty_strategy.quantity(self.rng.as_mut());
+                if !prefs.enable_buy || self.place(client_name, prefs, &long, "buying", quantity, reason.clone()).is_none() {
+                    return;
+                }
+                let wanted = prefs.sell_qty_strategy.quantity(self.rng.as_mut());
+                let mut short_qty = 0;
+                if prefs.enable_sell {
+                    short_qty = self.place(client_name, prefs, &short, "selling", wanted, reason).unwrap_or(0);
+                }
+                let position = PairPosition { long: long.name.clone(), short_qty };
+                self.pair_positions.insert(client_name.to_string(), position);
+            }
+            Some(position) => {
+                let reverted = if *position.long == pair.first { ratio >= pair.mean_ratio } else { ratio <= pair.mean_ratio };
+                if !reverted {
+                    return;
+                }
+                self.pair_positions.remove(client_name);
+                let reason = OrderReason::PairReverted { ratio_bps };
+                let (long, short) = if *position.long == pair.first { (first, second) } else { (second, first) };
+                let held = self.accounts.get(&self.account_name(client_name)).map_or(0, |a| a.held(&long.name));
+                if held > 0 && prefs.enable_sell {
+                    self.place(client_name, prefs, &long, "selling", held, reason.clone());
+                }
+                if position.short_qty > 0 && prefs.enable_buy {
+                    self.place(client_name, prefs, &short, "buying", position.short_qty, reason);
+                }
+            }
+        }
+    }
+
+    // The side a client's strategy picks for this tick, if any.
+    fn signal(&mut self, client_name: &str, prefs: &ClientPreference, stock: &Stock) -> Option<(&'static str, OrderReason)> {
+        if let Some(threshold) = prefs.value_threshold {
+            let fair_value = stock.fair_value?;
+            return if stock.v <= fair_value - threshold {
+                Some(("buying", OrderReason::BelowFairValue { fair_value }))
+            } else if stock.v >= fair_value + threshold {
+                Some(("selling", OrderReason::AboveFairValue { fair_value }))
+            } else {
+                None
+            };
+        }
+
+        if prefs.momentum_lookback > 0 {
+            return self.streak_signal(stock, prefs.momentum_lookback, true);
+        }
+        if prefs.mean_reversion_lookback > 0 {
+            return self.streak_signal(stock, prefs.mean_reversion_lookback, false);
+        }
+
+        let price_change = stock.change();
+        if price_change == 0 {
+            if prefs.flat_tick == FlatTick::Record {
+                self.signals.push(Signal {
+                    client: client_name.to_string(),
+                    stock: stock.name.clone(),
+                    side: NO_CHANGE.to_string(),
+                    executed: false,
+                    skip_reason: None,
+                });
+            }
+            return None;
+        }
+        let noise = self.decision_noise(stock);
+        let noisy = |threshold: i32| threshold.saturating_add(noise).max(0);
+        let inclusivity = prefs.threshold_inclusivity;
+        if prefs.is_market() && !inclusivity.reaches(price_change.abs(), noisy(prefs.market_min_change)) {
+            return None;
+        }
+        if (prefs.is_market() || inclusivity.reaches(-price_change, noisy(prefs.min_change_buy))) && price_change < 0 {
+            Some(("buying", OrderReason::PriceDecrease { to: stock.v }))
+        } else if (prefs.is_market() || inclusivity.reaches(price_change, noisy(prefs.min_change_sell))) && price_change > 0 {
+            Some(("selling", OrderReason::PriceIncrease { to: stock.v }))
+        } else {
+            None
+        }
+    }
+
+    // A random threshold offset within `decision_noise_bps` of the price.
+    // Draws nothing without noise, so noiseless runs see the same quantities.
+    fn decision_noise(&mut self, stock: &Stock) -> i32 {
+        if self.config.decision_noise_bps <= 0 {
+            return 0;
+        }
+        let max = (stock.v as i64 * self.config.decision_noise_bps as i64 / 10_000).clamp(0, i32::MAX as i64 - 1) as i32;
+        self.rng.delta(-max..max + 1)
+    }
+
+    // Trades once the stock has moved `lookback` consecutive ticks one way:
+    // with the run when `follow` is set, against it otherwise.
+    fn streak_signal(&self, stock: &Stock, lookback: u32, follow: bool) -> Option<(&'static str, OrderReason)> {
+        let streak = self.streaks.get(&stock.name).copied().unwrap_or(0);
+        let ticks = streak.unsigned_abs();
+        if ticks < lookback {
+            return None;
+        }
+        let (reason, rising) = if streak > 0 {
+            (OrderReason::RisingStreak { ticks }, true)
+        } else {
+            (OrderReason::FallingStreak { ticks }, false)
+        };
+        Some((if rising == follow { "buying" } else { "selling" }, reason))
+    }
+
+    fn skip(&mut self, client_name: &str, stock: &Stock, order_type: &str, quantity: i32, reason: SkipReason) {
+        if self.verbosity >= Verbosity::All {
+            println!("{} for client {} skipped a {} stock: {}", self.name, client_name, order_type, reason);
+        }
+        self.signals.push(Signal {
+            client: client_name.to_string(),
+            stock: stock.name.clone(),
+            side: order_type.to_string(),
+            executed: false,
+            skip_reason: Some(reason.to_string()),
+        });
+        self.skipped.push(SkippedOrder {
+            client_name: client_name.to_string(),
+            stock_name: stock.name.clone(),
+            order_type: order_type.to_string(),
+            quantity,
+            reason,
+        });
+    }
+
+    fn execute(
+        &mut self,
+        client_name: &str,
+        prefs: &ClientPreference,
+        stock: &Stock,
+        order_type: &str,
+        quantity: i32,
+        reason: OrderReason,
+    ) -> &mut Order {
+        let mut order = Order::new(
+            self.control.next_order_id(),
+            client_name.to_string(),
+            stock.name.clone(),
+            order_type.to_string(),
+            quantity,
+            stock.v,
+            stock.prev_v,
+            reason,
+            prefs.order_category.clone(),
+            self.control.elapsed(),
+        );
+        order.paper = prefs.paper;
+
+        if self.verbosity >= Verbosity::Orders {
+            println!("{} for client {} placed a {} stock: {:?}", self.name, client_name, order_type, order);
+        }
+        self.control.beat();
+
+        *self.client_transactions.entry(client_name.to_string()).or_insert(0) += 1;
+
+        if !order.paper {
+            self.live_orders += 1;
+            self.control.publish_order(
+                &self.name,
+                CopiedOrder { order_id: order.id, stock: stock.clone(), order_type: order_type.to_string(), quantity },
+            );
+        }
+        self.orders.push(order);
+        let index = self.orders.len() - 1;
+        self.fill(index, stock, prefs.profit_target, LiquiditySide::Taker);
+        let order = &self.orders[index];
+        let status = if order.remaining() == 0 {
+            AckStatus::Filled
+        } else if order.filled_qty > 0 {
+            AckStatus::PartiallyFilled
+        } else if order.is_resting() {
+            AckStatus::Resting
+        } else {
+            AckStatus::Rejected
+        };
+        self.signals.push(Signal {
+            client: client_name.to_string(),
+            stock: stock.name.clone(),
+            side: order_type.to_string(),
+            executed: status != AckStatus::Rejected,
+            skip_reason: None,
+        });
+        self.control.acknowledge(OrderAck { id: order.id, broker: self.name.clone(), status });
+        if self.orders[index].is_resting() && self.orders[index].remaining() > 0 {
+            self.resting.push(index);
+        } else {
+            self.print_order(index);
+        }
+        &mut self.orders[index]
+    }
+
+    // Fills as much of the order as the tick allows. Only resting limit
+    // orders are constrained by the broker's per-tick liquidity.
+    fn fill(&mut self, index: usize, stock: &Stock, profit_target: Option<Money>, liquidity: LiquiditySide) {
+        let order = &self.orders[index];
+        let order_id = order.id;
+        let client_name = order.client_name.clone();
+        let account_name = self.account_name(&client_name);
+        let selling = order.order_type == "selling";
+
+        let paper = order.paper;
+        let mut quantity = order.remaining();
+        if order.is_resting() && !paper {
+            if let Some(liquidity) = self.config.liquidity_per_tick {
+                let available = self.liquidity.entry(stock.name.clone()).or_insert(liquidity);
+                quantity = quantity.min(*available);
+                *available -= quantity;
+            }
+        }
+        if selling {
+            let held = self.accounts.get(&account_name).map_or(0, |a| a.held(&stock.name));
+            quantity = quantity.min(held.max(0));
+        }
+        if quantity <= 0 {
+            return;
+        }
+
+        let account = self.accounts.entry(account_name).or_default();
+        let mut closed = (Vec::new(), 0);
+        if selling {
+            let earnings = quantity as Money * (stock.v as Money - stock.prev_v as Money);
+            if self.config.position_mode == PositionMode::Netting {
+                closed = account.close_lots(&stock.name, quantity, stock.v);
+            }
+            account.sell(&stock.name, quantity, stock.v, earnings, self.day, self.config.position_mode);
+            let earned = self.client_earnings.entry(client_name.clone()).or_insert(0);
+            *earned = earned.saturating_add(earnings);
+        } else {
+            account.buy(order_id, &stock.name, quantity, stock.v);
+        }
+
+        if !paper {
+            self.last_fills.insert(stock.name.clone(), stock.v);
+            self.control.record_volume(&stock.name, quantity);
+            let stats = self.trades.entry(stock.name.clone()).or_default();
+            if selling {
+                stats.sold += quantity as i64;
+            } else {
+                stats.bought += quantity as i64;
+            }
+            stats.notional += quantity as i64 * stock.v as i64;
+        }
+
+        let volume = self.client_volume.entry(client_name.clone()).or_insert(0);
+        let commission = self.config.commission(*volume, quantity as Money * stock.v as Money);
+        *volume += quantity;
+        account.cash -= commission;
+        account.commissions += commission;
+        let value = quantity as Money * stock.v as Money;
+        let fee = match liquidity {
+            LiquiditySide::Maker => -(value * self.config.maker_rebate_bps as Money / 10_000),
+            LiquiditySide::Taker => value * self.config.taker_fee_bps as Money / 10_000,
+        };
+        account.cash -= fee;
+        account.fees += fee;
+
+        let ts = self.control.elapsed();
+        let order = &mut self.orders[index];
+        order.fills.push(Fill { quantity, price: stock.v, ts, commission, liquidity, fee });
+        let (closes, fifo_pnl) = closed;
+        for id in closes {
+            if !order.closes.contains(&id) {
+                order.closes.push(id);
+            }
+        }
+        order.fifo_pnl += fifo_pnl;
+        order.filled_qty += quantity;
+        if order.remaining() > 0 && self.verbosity >= Verbosity::All {
+            println!(
+                "{} for client {} partially filled {} of {} {} at {}",
+                self.name, client_name, order.filled_qty, order.quantity, stock.name, stock.v
+            );
+        }
+
+        if let Some(target) = profit_target {
+            let earned = self.client_earnings.get(&client_name).copied().unwrap_or(0);
+            if earned >= target && self.stop_client(&client_name) && self.verbosity >= Verbosity::Orders {
+                println!("{} stopped trading for client {}: profit target of ${} reached.", self.name, client_name, target);
+            }
+        }
+    }
+
+    // Tries to fill resting limit orders on the ticked stock whose limit the
+    // new price satisfies.
+    fn fill_resting(&mut self, stock: &Stock) {
+        if let Some(liquidity) = self.config.liquidity_per_tick {
+            self.liquidity.insert(stock.name.clone(), liquidity);
+        }
+        let resting = std::mem::take(&mut self.resting);
+        for &index in &resting {
+            let order = &self.orders[index];
+            let marketable = if order.order_type == "selling" { stock.v >= order.price } else { stock.v <= order.price };
+            if order.stock_name == stock.name && marketable && !self.stopped_clients.contains(&order.client_name) {
+                let profit_target = self.client_preferences.get(&order.client_name).and_then(|p| p.profit_target);
+                self.fill(index, stock, profit_target, LiquiditySide::Maker);
+            }
+        }
+        let (done, resting): (Vec<usize>, Vec<usize>) = resting
+            .into_iter()
+            .partition(|&i| self.orders[i].remaining() == 0 || self.stopped_clients.contains(&self.orders[i].client_name));
+        self.resting = resting;
+        for index in done {
+            self.print_order(index);
+        }
+    }
+
+    // Puts the order on the tape once it has stopped filling, if it filled at all.
+    fn print_order(&self, index: usize) {
+        let order = &self.orders[index];
+        let (Some(price), Some(last)) = (order.average_fill_price(), order.fills.last()) else { return };
+        if !order.paper {
+            self.control.print_to_tape(TapeEntry {
+                ts: last.ts,
+                stock: order.stock_name.clone(),
+                price: price.round() as i32,
+                qty: order.filled_qty,
+                side: order.order_type.clone(),
+            });
+        }
+    }
+
+    fn into_result(self) -> BrokerResult {
+        // Orders still resting have stopped filling now.
+        for &index in &self.resting {
+            self.print_order(index);
+        }
+        BrokerResult {
+            name: self.name,
+            mode: self.config.mode,
+            position_mode: self.config.position_mode,
+            accounts: self.accounts,
+            orders: self.orders,
+            skipped: self.skipped,
+            signals: self.signals,
+            client_volume: self.client_volume.into_iter().collect(),
+            trades: self.trades.into_iter().map(|(name, stats)| (name.to_string(), stats)).collect(),
+            opening: sorted_stocks(self.first_prices),
+            closing: sorted_stocks(self.last_prices),
+        }
+    }
+}
+
+fn sorted_stocks(stocks: HashMap<Ticker, Stock>) -> Vec<Stock> {
+    let mut stocks: Vec<Stock> = stocks.into_values().collect();
+    stocks.sort_by(|a, b| a.name.cmp(&b.name));
+    stocks
+}
+
+// Pins the calling thread to `core`. Returns false where that is unsupported
+// or the core cannot be used.
+#[cfg(all(feature = "affinity", target_os = "linux"))]
+pub fn pin_current_thread(core: usize) -> bool {
+    if core >= libc::CPU_SETSIZE as usize {
+        return false;
+    }
+    // SAFETY: `set` is a plain bitmask initialized by CPU_ZERO before use, and
+    // pid 0 refers to the calling thread.
+    unsafe {
+        let mut set: libc::cpu_set_t = std::mem::zeroed();
+        libc::CPU_ZERO(&mut set);
+        libc::CPU_SET(core, &mut set);
+        libc::sched_setaffinity(0, std::mem::size_of::<libc::cpu_set_t>(), &set) == 0
+    }
+}
+
+#[cfg(not(all(feature = "affinity", target_os = "linux")))]
+pub fn pin_current_thread(_core: usize) -> bool {
+    false
+}
+
+#[allow(clippy::too_many_arguments)]
+pub fn process_broker_actions(
+    name: String,
+    sel_r: crossbeam_channel::Receiver<Vec<PriceTick>>,
+    client_preferences: HashMap<String, ClientPreference>,
+    transaction_limit: i32,
+    control: SimulationControl,
+    broker_config: BrokerConfig,
+    quantity_source: Box<dyn RandomSource>,
+    verbosity: Verbosity,
+) -> JoinHandle<BrokerResult> {
+    control.register_broker(&name);
+    let leader = broker_config.copy.as_ref().map(|copy| copy.leader.clone());
+    let copy_r = match &leader {
+        Some(leader) => control.follow(leader),
+        None => crossbeam_channel::never(),
+    };
+    thread::Builder::new().name(name.clone()).spawn(move || {
+        let mut state = BrokerState::new(
+            name,
+            broker_config,
+            client_preferences,
+            transaction_limit,
+            control,
+            quantity_source,
+            verbosity,
+        );
+
+        if let Some(core) = state.config.cpu_affinity {
+            if !pin_current_thread(core) && state.verbosity >= Verbosity::All {
+                println!("{} could not be pinned to core {}; running unpinned.", state.name, core);
+            }
+        }
+
+        if let Some(delay) = state.config.start_delay {
+            thread::sleep(delay);
+        }
+
+        while !state.is_done() {
+            if state.control.is_shutdown() {
+                if state.verbosity >= Verbosity::Orders {
+                    println!("{} is stopping on shutdown request.", state.name);
+                }
+                // Ticks sent before the stop still count.
+                for ticks in sel_r.try_iter() {
+                    state.on_ticks(&ticks);
+                }
+                break;
+            }
+            // A follower has nothing left to copy once its leader is done.
+            if leader.as_ref().is_some_and(|leader| state.control.is_complete(leader)) && copy_r.is_empty() {
+                break;
+            }
+            let ticks = crossbeam_channel::select! {
+                recv(sel_r) -> ticks => match ticks {
+                    Ok(ticks) => ticks,
+                    Err(_) => break,
+                },
+                recv(copy_r) -> copied => {
+                    if let Ok(copied) = copied {
+                        state.on_copied(&copied);
+                    }
+                    continue;
+                },
+                default(Duration::from_millis(100)) => continue,
+            };
+            state.on_ticks(&ticks);
+        }
+
+        if state.verbosity >= Verbosity::Orders {
+            println!("{} has completed the transactions for all clients.", state.name);
+        }
+        state.control.mark_complete(&state.name);
+        state.into_result()
+    }).expect("failed to spawn broker thread")
+}
+
+
+
+
+
+// Client name and order size used by `run_strategy`.
+pub const STRATEGY_CLIENT: &str = "Client";
+pub const STRATEGY_QUANTITY: i32 = 50;
+
+// Replays a single client's decisions over `ticks` on the calling thread, with
+// every order sized at STRATEGY_QUANTITY.
+pub fn run_strategy(prefs: ClientPreference, ticks: &[PriceTick]) -> Vec<Order> {
+    run_strategy_with_quantities(prefs, ticks, Box::new(ScriptedSource::new(vec![STRATEGY_QUANTITY])))
+}
+
+pub fn run_strategy_with_quantities(
+    prefs: ClientPreference,
+    ticks: &[PriceTick],
+    quantity_source: Box<dyn RandomSource>,
+) -> Vec<Order> {
+    let client_preferences = HashMap::from([(STRATEGY_CLIENT.to_string(), prefs)]);
+    run_broker("Strategy", client_preferences, BrokerConfig::default(), ticks, quantity_source).orders
+}
+
+// Replays a whole broker over `ticks` on the calling thread, with no
+// transaction limit. The broker stops early only once every client has
+// stopped or is capped.
+pub fn run_broker(
+    name: &str,
+    client_preferences: HashMap<String, ClientPreference>,
+    config: BrokerConfig,
+    ticks: &[PriceTick],
+    quantity_source: Box<dyn RandomSource>,
+) -> BrokerResult {
+    let mut state = BrokerState::new(
+        name.to_string(),
+        config,
+        client_preferences,
+        i32::MAX,
+        SimulationControl::new(),
+        quantity_source,
+        Verbosity::Silent,
+    );
+    for tick in ticks {
+        if state.is_done() {
+            break;
+        }
+        state.on_tick(tick);
+    }
+    state.into_result()
+}
+
+#[derive(Debug, Error)]
+pub enum ReplayError {
+    #[error("failed to read price series: {0}")]
+    Io(#[source] std::io::Error),
+    // `line` is one-based and counts the header, if any.
+    #[error("line {line}: expected ticker,timestamp,price but got {row:?}")]
+    InvalidRow { line: usize, row: String },
+}
+
+// Turns `ticker,timestamp,price` rows into ticks in timestamp order, for
+// backtesting with `run_strategy`, `run_broker` or `StockExchange::replay`.
+// Each tick's `prev_v` is the ticker's
+// previous price, or its own price on its first row. Ticks sharing a
+// timestamp share a `seq`. A leading header row is skipped.
+pub fn replay_prices_csv<R: std::io::Read>(r: R) -> Result<Vec<PriceTick>, StockError> {
+    use std::io::BufRead;
+
+    let mut rows = Vec::new();
+    for (i, line) in std::io::BufReader::new(r).lines().enumerate() {
+        let line = line.map_err(ReplayError::Io)?;
+        let row = line.trim();
+        let fields: Vec<&str> = row.split(',').map(str::trim).collect();
+        let header = i == 0 && fields.iter().map(|f| f.to_ascii_lowercase()).eq(["ticker", "timestamp", "price"]);
+        if row.is_empty() || header {
+            continue;
+        }
+        let invalid = || ReplayError::InvalidRow { line: i + 1, row: row.to_string() };
+        let [ticker, timestamp, price] = fields[..] else { return Err(invalid().into()) };
+        let timestamp: u64 = timestamp.parse().map_err(|_| invalid())?;
+        let price: i32 = price.parse().map_err(|_| invalid())?;
+        if ticker.is_empty() {
+            return Err(invalid().into());
+        }
+        rows.push((timestamp, ticker.to_string(), price));
+    }
+    rows.sort_by_key(|(timestamp, _, _)| *timestamp);
+
+    let mut last: HashMap<String, Stock> = HashMap::new();
+    let mut ticks = Vec::with_capacity(rows.len());
+    let mut seq = 0;
+    let mut prev_timestamp = None;
+    for (timestamp, ticker, price) in rows {
+        if prev_timestamp.is_some_and(|prev| prev != timestamp) {
+            seq += 1;
+        }
+        prev_timestamp = Some(timestamp);
+        let stock = match last.get(&ticker) {
+            Some(prev) => Stock { prev_v: prev.v, v: price, ..prev.clone() },
+            None => Stock::new(&ticker, price),
+        };
+        last.insert(ticker, stock.clone());
+        ticks.push(PriceTick { stock, day: 0, seq });
+    }
+    Ok(ticks)
+}
+
+pub fn run_simulation() {
+    let config = SimulationConfig::default();
+    let verbosity = config.verbosity;
+    if let Err(e) = run_simulation_with_config(config) {
+        if verbosity > Verbosity::Silent {
+            println!("{}", e);
+        }
+    }
+}
+
+pub fn run_simulation_with_config(config: SimulationConfig) -> Result<ExchangeReport, StockError> {
+    let verbosity = config.verbosity;
+    if verbosity >= Verbosity::Orders {
+        println!("Stock updates from Bursa Malaysia...");
+    }
+    let mut exchange = StockExchange::new(config);
+    #[cfg(feature = "ctrlc")]
+    install_interrupt_handler(&exchange.control());
+    for stock in [
+        Stock::new("AMZN", 200),
+        Stock::new("GOOGL", 120),
+        Stock::new("MSFT", 130),
+        Stock::new("TSLA", 300),
+        Stock::new("FB", 156),
+        Stock::new("CRM", 90),
+        Stock::new("INTC", 245),
+        Stock::new("NVDA", 187),
+        Stock::new("WORK", 65),
+        Stock::new("FSLY", 110),
+        Stock::new("CRWD", 125),
+        Stock::new("DOCU", 240),
+        Stock::new("NOW", 180),
+        Stock::new("PLTR", 95),
+        Stock::new("KO", 310),
+        Stock::new("PEP", 400),
+        Stock::new("MCD", 170),
+        Stock::new("SBUX", 200),
+        Stock::new("GIS", 67),
+        Stock::new("HSY", 276),
+        Stock::new("KR", 22),
+        Stock::new("CPB", 120),
+        Stock::new("PER", 400),
+        Stock::new("WMT", 150),
+        Stock::new("TGT", 90),
+        Stock::new("COST", 280),
+        Stock::new("PG", 200),
+        Stock::new("UN", 170),
+        Stock::new("SYY", 110),
+        Stock::new("FLO", 30),
+        Stock::new("WBA", 55),
+        Stock::new("MDLZ", 330),
+        Stock::new("MRK", 280),
+        Stock::new("AMGN", 430),
+        Stock::new("UNH", 120),
+        Stock::new("HCA", 88),
+        Stock::new("ANTM", 22),
+        Stock::new("DHR", 120),
+        Stock::new("ABT", 400),
+        Stock::new("TMO", 150),
+        Stock::new("REGN", 90),
+        Stock::new("ILMN", 280),
+        Stock::new("MDT", 200),
+        Stock::new("ZBH", 170),
+        Stock::new("VRTX", 110),
+        Stock::new("IDXX", 30),
+        Stock::new("DGX", 55),
+    ] {
+        exchange.add_stock(stock);
+    }
+
+    let client_preferences_broker1 = HashMap::from([
+        ("John".to_string(), ClientPreference::new(StockType::Tech, "Market", 0, 0)),
+        ("Peter".to_string(), ClientPreference::new(StockType::Tech, "Market", 0, 0)),
+    ]);
+
+    let client_preferences_broker2 = HashMap::from([
+        ("James".to_string(), ClientPreference::new(StockType::Food, "Limit", 25, 40)),
+    ]);
+
+    let client_preferences_broker3 = HashMap::from([
+        ("Alex".to_string(), ClientPreference::new(StockType::Healthcare, "Limit", 10, 30)),
+        ("Mike".to_string(), ClientPreference::new(StockType::Tech, "Market", 0, 0)),
+    ]);
+
+    exchange.add_broker("Broker 1", client_preferences_broker1, BrokerConfig::default());
+    exchange.add_broker("Broker 2", client_preferences_broker2, BrokerConfig::default());
+    exchange.add_broker("Broker 3", client_preferences_broker3, BrokerConfig::default());
+
+    let report = exchange.run()?;
+    if verbosity >= Verbosity::Orders {
+        report.print();
+    }
+    Ok(report)
+}
+
+extern crate bma_benchmark;
+use  bma_benchmark::{staged_benchmark, staged_benchmark_print_for};
+use core::hint::black_box;
+
+pub fn benchmarkmarco() {
+    staged_benchmark!("simulation", 30, {
+        run_simulation();
+        black_box(());
+    });
+    staged_benchmark!("simulation (batched ticks)", 30, {
+        let config = SimulationConfig { batch_ticks: true, ..SimulationConfig::default() };
+        let _ = black_box(run_simulation_with_config(config));
+    });
+    // Tick generation alone, which no longer allocates a ticker per tick.
+    let config = SimulationConfig { verbosity: Verbosity::Silent, ..SimulationConfig::default() };
+    let mut dispatcher = TickDispatcher::new();
+    let receiver = dispatcher.subscribe("benchmark", HashSet::from([StockType::Tech, StockType::Food, StockType::Healthcare]));
+    let mut simulator = MarketSimulator::new(config, dispatcher, SimulationControl::new(), Box::new(seeded_rng(None)));
+    let mut stocks = vec![Stock::new("AMZN", 200), Stock::new("KO", 310), Stock::new("MRK", 280)];
+    staged_benchmark!("market cycle", 100_000, {
+        simulator.cycle(&mut stocks);
+        black_box(receiver.try_iter().count());
+    });
+    staged_benchmark_print_for!("simulation");
+    staged_benchmark_print_for!("simulation (batched ticks)");
+    staged_benchmark_print_for!("market cycle")
+}
@@ -0,0 +1,462 @@
+mod common;
+
+use std::collections::HashMap;
+use std::time::Duration;
+
+use common::{broker, clients, fixed, path, tick};
+use ngwaijie_tp066893::report::ExchangeReport;
+use ngwaijie_tp066893::stock::{
+    realized_vol, run_broker, seeded_rng, BrokerConfig, BrokerMode, ClientPreference, CommissionTier, LiquiditySide,
+    OrderReason, PairTrade, PositionMode, QuantityStrategy, RiskBudget, SkipReason, StockType,
+};
+
+#[test]
+fn exposure_cap_stops_buys_across_clients() {
+    let config = BrokerConfig { max_broker_exposure: Some(10_000), ..BrokerConfig::default() };
+    let client = fixed(StockType::Tech, "Market", 50);
+    let result = broker(&[("Ann", client.clone()), ("Bob", client)], config, &path("AAPL", &[100, 99, 98, 40]));
+
+    let buys = |client: &str| {
+        result.orders.iter().filter(|o| o.client_name == client).map(|o| o.price).collect::<Vec<_>>()
+    };
+    // At 98 the 100 shares held are worth 9800, so neither may add 50 more.
+    // At 40 they are worth 4000 and both may buy again, whatever they cost.
+    assert_eq!(buys("Ann"), [99, 40]);
+    assert_eq!(buys("Bob"), [99, 40]);
+    let skipped: Vec<_> = result.skipped.iter().map(|s| (s.client_name.as_str(), &s.reason)).collect();
+    assert_eq!(
+        skipped,
+        [
+            ("Ann", &SkipReason::ExposureLimit { exposure: 9800, limit: 10_000 }),
+            ("Bob", &SkipReason::ExposureLimit { exposure: 9800, limit: 10_000 }),
+        ]
+    );
+}
+
+#[test]
+fn retention_keeps_resting_orders_and_exact_totals() {
+    let config = BrokerConfig { order_retention: Some(5), liquidity_per_tick: Some(10), ..BrokerConfig::default() };
+    let buy_only = |prefs| ClientPreference { enable_sell: false, ..prefs };
+    let clients = [("Ann", buy_only(fixed(StockType::Food, "Limit", 50))), ("Bob", buy_only(fixed(StockType::Tech, "Market", 1)))];
+
+    // Ann's buy at 90 fills 10 and rests while Bob places twenty orders.
+    let mut ticks = path("KO", &[100, 90, 91]);
+    ticks.extend(path("MSFT", &[100, 99].repeat(21)).into_iter().filter(|t| t.stock.v == 99));
+    ticks.extend(path("KO", &[91, 85, 86, 87, 88]));
+    let result = broker(&clients, config, &ticks);
+
+    assert_eq!(result.orders.len(), 5);
+    let first = result.orders.iter().find(|o| o.client_name == "Ann").unwrap();
+    assert_eq!((first.price, first.filled_qty), (90, 50));
+    assert_eq!(result.trades["MSFT"].bought, 21);
+    assert_eq!(result.trades["KO"].bought, 50);
+}
+
+#[test]
+fn daily_loss_counts_realized_losses() {
+    let config = BrokerConfig { max_daily_loss: Some(500), ..BrokerConfig::default() };
+    let ticks = path("AAPL", &[110, 100, 60, 61, 55]);
+    let result = broker(&[("Ann", fixed(StockType::Tech, "Market", 50))], config, &ticks);
+
+    // Selling half of 100 shares bought at an average of 80 loses 950, even
+    // though the price rose on the tick of the sale.
+    let orders: Vec<_> = result.orders.iter().map(|o| (o.order_type.as_str(), o.price)).collect();
+    assert_eq!(orders, [("buying", 100), ("buying", 60), ("selling", 61)]);
+    assert_eq!(result.accounts["Ann"].daily_realized_pnl[&0], -950);
+    assert_eq!(result.skipped[0].reason, SkipReason::DailyLossLimit { loss: 950, limit: 500 });
+}
+
+#[test]
+fn paper_orders_take_no_cap_and_leave_no_trades() {
+    let config = BrokerConfig { order_cap: Some(2), ..BrokerConfig::default() };
+    let paper = ClientPreference { paper: true, ..fixed(StockType::Tech, "Market", 30) };
+    let result = broker(&[("Ann", fixed(StockType::Tech, "Market", 10)), ("Pat", paper)], config, &path("AAPL", &[100, 99, 98, 97]));
+
+    let placed = |client: &str| result.orders.iter().filter(|o| o.client_name == client).count();
+    assert_eq!((placed("Ann"), placed("Pat")), (2, 3));
+    let stats = &result.trades["AAPL"];
+    assert_eq!((stats.bought, stats.sold, stats.notional), (20, 0, 990 + 980));
+}
+
+#[test]
+fn pair_legs_go_through_the_broker_checks() {
+    let pair = PairTrade { first: "AAPL".to_string(), second: "MSFT".to_string(), mean_ratio: 1.0, threshold: 0.1 };
+    let client = ClientPreference { pair: Some(pair), ..fixed(StockType::Tech, "Market", 150) };
+    // The ratio diverges to 0.8, then reverts to 1.
+    let ticks = [tick("AAPL", 100, 100), tick("MSFT", 100, 100), tick("AAPL", 100, 80), tick("AAPL", 80, 100)];
+
+    let config = BrokerConfig { lot_size: 100, ..BrokerConfig::default() };
+    let result = broker(&[("Ann", client.clone())], config, &ticks);
+    let orders: Vec<_> = result.orders.iter().map(|o| (o.order_type.as_str(), &*o.stock_name, o.quantity, &o.reason)).collect();
+    assert_eq!(
+        orders,
+        [
+            ("buying", "AAPL", 100, &OrderReason::PairDiverged { ratio_bps: 8000 }),
+            ("selling", "AAPL", 100, &OrderReason::PairReverted { ratio_bps: 10_000 }),
+        ]
+    );
+    assert_eq!(result.skipped[0].reason, SkipReason::InsufficientHoldings { held: 0, requested: 150 });
+
+    let config = BrokerConfig { min_order_notional: 20_000, ..BrokerConfig::default() };
+    let result = broker(&[("Ann", client)], config, &ticks);
+    assert!(result.orders.is_empty());
+    assert_eq!(result.skipped[0].reason, SkipReason::BelowMinNotional { notional: 12_000, min: 20_000 });
+}
+
+#[test]
+fn resting_orders_stop_filling_once_the_profit_target_is_reached() {
+    let config = BrokerConfig { liquidity_per_tick: Some(10), ..BrokerConfig::default() };
+    let client = ClientPreference { profit_target: Some(10), ..fixed(StockType::Tech, "Market", 20) };
+    let client = ClientPreference { order_category: "Limit".to_string(), ..client };
+    // The buy at 90 fills 10 and rests 10; selling those 10 at 91 earns the
+    // target. The rest of the buy would fill at 85. Bob keeps the run going.
+    let clients = [("Ann", client), ("Bob", fixed(StockType::Food, "Market", 10))];
+    let result = broker(&clients, config, &path("AAPL", &[100, 90, 91, 85]));
+
+    assert_eq!(result.orders.len(), 2);
+    assert_eq!((result.trades["AAPL"].bought, result.trades["AAPL"].sold), (10, 10));
+}
+
+#[test]
+fn a_pooled_drawdown_stops_every_client_in_the_pool() {
+    let config = BrokerConfig::new(BrokerMode::Pooled);
+    let guarded = ClientPreference { max_drawdown: Some(100), ..fixed(StockType::Tech, "Market", 10) };
+    let result = broker(&[("Ann", guarded), ("Bob", fixed(StockType::Tech, "Market", 10))], config, &path("AAPL", &[100, 99, 90, 80]));
+
+    // 20 shares bought at 99 lose 180 at 90.
+    let mut orders: Vec<_> = result.orders.iter().map(|o| (o.order_type.as_str(), o.quantity, o.price, &o.reason)).collect();
+    orders.sort_by_key(|o| o.2);
+    let bought = OrderReason::PriceDecrease { to: 99 };
+    assert_eq!(
+        orders,
+        [("selling", 20, 90, &OrderReason::Drawdown { drawdown: 180 }), ("buying", 10, 99, &bought), ("buying", 10, 99, &bought)]
+    );
+    assert_eq!(result.accounts["Pool"].held("AAPL"), 0);
+}
+
+#[test]
+fn drawdown_limits_past_i32_max_are_honoured() {
+    // 10 million shares bought at 500 are 4 billion down at 100.
+    let drawdowns = |max_drawdown| {
+        let prefs = ClientPreference { max_drawdown: Some(max_drawdown), ..fixed(StockType::Tech, "Market", 10_000_000) };
+        let result = broker(&[("Ann", prefs)], BrokerConfig::default(), &path("AAPL", &[1000, 500, 100]));
+        result.orders.iter().filter(|o| matches!(o.reason, OrderReason::Drawdown { .. })).count()
+    };
+
+    assert_eq!(drawdowns(3_000_000_000), 1);
+    assert_eq!(drawdowns(5_000_000_000), 0);
+}
+
+#[test]
+fn pooled_earnings_equal_the_sum_of_separate_accounts() {
+    let clients = [("Ann", fixed(StockType::Tech, "Market", 10)), ("Bob", fixed(StockType::Tech, "Market", 20))];
+    let ticks = path("AAPL", &[100, 95, 105, 98, 110]);
+    let separate = broker(&clients, BrokerConfig::default(), &ticks);
+    let pooled = broker(&clients, BrokerConfig::new(BrokerMode::Pooled), &ticks);
+
+    assert_eq!(pooled.orders.len(), separate.orders.len());
+    assert_eq!(pooled.accounts.keys().collect::<Vec<_>>(), ["Pool"]);
+    let pool = &pooled.accounts["Pool"];
+    assert_eq!(pool.earnings, separate.accounts.values().map(|a| a.earnings).sum::<i64>());
+    assert_eq!(pool.realized_pnl, separate.accounts.values().map(|a| a.realized_pnl).sum::<i64>());
+    assert_ne!(pool.earnings, 0);
+}
+
+#[test]
+fn a_sell_of_more_than_is_held_is_clamped() {
+    let client = ClientPreference { sell_qty_strategy: QuantityStrategy::Fixed(100), ..fixed(StockType::Tech, "Market", 30) };
+    let result = broker(&[("Ann", client)], BrokerConfig::default(), &path("AAPL", &[100, 95, 105]));
+
+    let sell = &result.orders[1];
+    assert_eq!((sell.order_type.as_str(), sell.quantity, sell.clamped_from), ("selling", 30, Some(100)));
+    assert_eq!(result.accounts["Ann"].held("AAPL"), 0);
+    assert_eq!(result.accounts["Ann"].earnings, 30 * 10);
+}
+
+#[test]
+fn buys_are_skipped_during_the_stop_loss_cooldown() {
+    let client = |cooldown| ClientPreference {
+        stop_loss: Some(5),
+        stop_loss_cooldown: cooldown,
+        ..fixed(StockType::Tech, "Market", 10)
+    };
+    // Bought at 95, stopped out at 89, then a buy signal at 85.
+    let ticks = path("AAPL", &[100, 95, 89, 85]);
+
+    let result = broker(&[("Ann", client(Duration::from_secs(60)))], BrokerConfig::default(), &ticks);
+    let reasons: Vec<_> = result.orders.iter().map(|o| &o.reason).collect();
+    assert_eq!(reasons, [&OrderReason::PriceDecrease { to: 95 }, &OrderReason::StopLoss]);
+    assert_eq!(result.skipped.len(), 1);
+    assert_eq!(result.skipped[0].order_type, "buying");
+    assert!(matches!(result.skipped[0].reason, SkipReason::StopLossCooldown { remaining } if remaining > Duration::from_secs(59)));
+
+    let result = broker(&[("Ann", client(Duration::ZERO))], BrokerConfig::default(), &ticks);
+    assert_eq!(result.orders.last().unwrap().reason, OrderReason::PriceDecrease { to: 85 });
+}
+
+#[test]
+fn a_trailing_stop_sells_below_the_high_since_entry() {
+    let client = ClientPreference {
+        trailing_stop_bps: Some(1000),
+        market_min_change: 5,
+        enable_sell: false,
+        ..fixed(StockType::Tech, "Market", 10)
+    };
+    // Bought at 94, the stop trails 10% under the high of 130 rather than
+    // under the entry, so it fires at 117.
+    let result = broker(&[("Ann", client)], BrokerConfig::default(), &path("AAPL", &[100, 94, 110, 130, 126, 122, 118, 117, 110]));
+
+    let orders: Vec<_> = result.orders.iter().map(|o| (o.order_type.as_str(), o.price, &o.reason)).collect();
+    assert_eq!(
+        orders,
+        [
+            ("buying", 94, &OrderReason::PriceDecrease { to: 94 }),
+            ("selling", 117, &OrderReason::TrailingStop { high: 130 }),
+            ("buying", 110, &OrderReason::PriceDecrease { to: 110 }),
+        ]
+    );
+}
+
+#[test]
+fn orders_below_the_minimum_notional_are_skipped() {
+    let config = BrokerConfig { min_order_notional: 1000, ..BrokerConfig::default() };
+    let clients = [("Ann", fixed(StockType::Food, "Market", 10)), ("Bob", fixed(StockType::Food, "Market", 50))];
+    let result = broker(&clients, config, &path("KR", &[25, 22]));
+
+    let placed: Vec<_> = result.orders.iter().map(|o| (o.client_name.as_str(), o.quantity)).collect();
+    assert_eq!(placed, [("Bob", 50)]);
+    assert_eq!(result.skipped.len(), 1);
+    assert_eq!(result.skipped[0].client_name, "Ann");
+    assert_eq!(result.skipped[0].reason, SkipReason::BelowMinNotional { notional: 220, min: 1000 });
+}
+
+#[test]
+fn quantities_round_down_to_the_lot_size() {
+    let config = BrokerConfig { lot_size: 100, ..BrokerConfig::default() };
+    let clients = [("Ann", fixed(StockType::Tech, "Market", 150)), ("Bob", fixed(StockType::Tech, "Market", 50))];
+    let result = broker(&clients, config, &path("AAPL", &[100, 99]));
+
+    let placed: Vec<_> = result.orders.iter().map(|o| (o.client_name.as_str(), o.quantity)).collect();
+    assert_eq!(placed, [("Ann", 100)]);
+    assert_eq!(result.skipped[0].client_name, "Bob");
+    assert_eq!(result.skipped[0].reason, SkipReason::BelowLotSize { quantity: 50, lot_size: 100 });
+}
+
+#[test]
+fn a_buy_skipped_for_cash_is_recorded_as_a_signal() {
+    let poor = ClientPreference { starting_cash: Some(1000), ..fixed(StockType::Tech, "Market", 50) };
+    let result = broker(&[("Ann", poor), ("Bob", fixed(StockType::Tech, "Market", 50))], BrokerConfig::default(), &path("AAPL", &[100, 99]));
+
+    let signals: Vec<_> = result
+        .signals
+        .iter()
+        .map(|s| (s.client.as_str(), &*s.stock, s.side.as_str(), s.executed, s.skip_reason.as_deref()))
+        .collect();
+    assert_eq!(
+        signals,
+        [
+            ("Ann", "AAPL", "buying", false, Some("Skipped a buy costing 4950 with only 1000 cash available")),
+            ("Bob", "AAPL", "buying", true, None),
+        ]
+    );
+    assert_eq!(result.skipped[0].reason, SkipReason::InsufficientCash { needed: 4950, available: 1000 });
+}
+
+#[test]
+fn commission_drops_once_volume_crosses_a_tier() {
+    let config = BrokerConfig {
+        commission_tiers: vec![CommissionTier { min_volume: 0, rate_bps: 100 }, CommissionTier { min_volume: 1000, rate_bps: 10 }],
+        ..BrokerConfig::default()
+    };
+    let result = broker(&[("Ann", fixed(StockType::Tech, "Market", 600))], config, &path("AAPL", &[100, 99, 98, 97]));
+
+    // The third buy is the first placed with more than 1000 shares traded.
+    let commissions: Vec<_> = result.orders.iter().map(|o| o.fills[0].commission).collect();
+    assert_eq!(commissions, [594, 588, 58]);
+    assert_eq!(result.client_volume["Ann"], 1800);
+    assert_eq!(result.accounts["Ann"].commissions, 594 + 588 + 58);
+}
+
+#[test]
+fn the_higher_priority_client_takes_the_only_order() {
+    let config = BrokerConfig { order_cap: Some(1), ..BrokerConfig::default() };
+    for (ann, zed, winner) in [(0, 5, "Zed"), (5, 0, "Ann")] {
+        let result = broker(
+            &[
+                ("Ann", ClientPreference { priority: ann, ..fixed(StockType::Tech, "Market", 10) }),
+                ("Zed", ClientPreference { priority: zed, ..fixed(StockType::Tech, "Market", 10) }),
+            ],
+            config.clone(),
+            &path("AAPL", &[100, 99, 98]),
+        );
+        let clients: Vec<_> = result.orders.iter().map(|o| o.client_name.as_str()).collect();
+        assert_eq!(clients, [winner]);
+    }
+}
+
+#[test]
+fn earnings_past_i32_max_do_not_wrap() {
+    let quantity = 1_000_000;
+    let ticks = path("AAPL", &[1000, 500, 3000, 500, 3000]);
+    let result = broker(&[("Ann", fixed(StockType::Tech, "Market", quantity))], BrokerConfig::default(), &ticks);
+
+    // Each sell earns 2.5 billion, and the two together pass i32::MAX.
+    let expected = 2 * quantity as i64 * 2500;
+    assert!(expected > i32::MAX as i64);
+    assert_eq!(result.accounts["Ann"].earnings, expected);
+    assert_eq!(result.earnings()["Ann"], expected);
+
+    // Across brokers a day's earnings saturate rather than wrap at i64::MAX.
+    let mut second = broker(&[("Bob", fixed(StockType::Tech, "Market", quantity))], BrokerConfig::default(), &ticks);
+    second.accounts.get_mut("Bob").unwrap().daily_earnings.insert(0, i64::MAX - 1);
+    let report = ExchangeReport::new(vec![result, second], Vec::new(), Vec::new(), Duration::ZERO);
+    assert_eq!(report.daily_earnings()[&0], i64::MAX);
+}
+
+#[test]
+fn commissions_are_clamped_into_the_band() {
+    let config = BrokerConfig {
+        commission_tiers: vec![CommissionTier { min_volume: 0, rate_bps: 10 }],
+        min_commission: Some(5),
+        max_commission: Some(1000),
+        ..BrokerConfig::default()
+    };
+    let prefs = [
+        ("Ann", fixed(StockType::Tech, "Market", 1)),
+        ("Bob", fixed(StockType::Tech, "Market", 10_000)),
+        ("Cat", fixed(StockType::Tech, "Market", 1_000_000)),
+    ];
+    let result = broker(&prefs, config, &path("AAPL", &[100, 99]));
+
+    // 10 bps of 99, 990,000 and 99,000,000.
+    let commission = |client: &str| result.orders.iter().find(|o| o.client_name == client).unwrap().fills[0].commission;
+    assert_eq!((commission("Ann"), commission("Bob"), commission("Cat")), (5, 990, 1000));
+}
+
+#[test]
+fn an_extreme_price_is_rejected_as_a_fat_finger() {
+    let config = BrokerConfig { fat_finger_bps: Some(1000), ..BrokerConfig::default() };
+    // A 10% fall trades; a fall of 50% from 90 to 45 is rejected.
+    let result = broker(&[("Ann", fixed(StockType::Tech, "Market", 10))], config, &path("AAPL", &[100, 90, 45]));
+
+    let prices: Vec<_> = result.orders.iter().map(|o| o.price).collect();
+    assert_eq!(prices, [90]);
+    assert_eq!(result.skipped.len(), 1);
+    assert_eq!(result.skipped[0].order_type, "buying");
+    assert_eq!(result.skipped[0].reason, SkipReason::FatFinger { price: 45, last: 90, max_bps: 1000 });
+    assert!(result.signals.iter().any(|s| !s.executed && s.skip_reason.is_some()));
+}
+
+#[test]
+fn the_fat_finger_check_measures_from_the_last_fill() {
+    let config = BrokerConfig { fat_finger_bps: Some(1500), ..BrokerConfig::default() };
+    let prefs = ClientPreference { market_min_change: 8, ..fixed(StockType::Tech, "Market", 10) };
+    // The buy at 90 fills, and the moves of 5 after it trade nothing. The
+    // fall from 100 to 80 is 20% in one tick but only 11% from that fill;
+    // the fall to 60 is 25% from the fill at 80.
+    let result = broker(&[("Ann", prefs)], config, &path("AAPL", &[100, 90, 95, 100, 80, 60]));
+
+    let prices: Vec<_> = result.orders.iter().map(|o| o.price).collect();
+    assert_eq!(prices, [90, 80]);
+    let skips: Vec<_> = result.skipped.iter().map(|s| s.reason.clone()).collect();
+    assert_eq!(skips, [SkipReason::FatFinger { price: 60, last: 80, max_bps: 1500 }]);
+}
+
+#[test]
+fn a_risk_budget_trades_smaller_in_the_more_volatile_stock() {
+    let prefs = ClientPreference {
+        risk_budget: Some(RiskBudget { target_vol: 0.05, window: 4 }),
+        ..fixed(StockType::Tech, "Market", 100)
+    };
+    // Both paths end on a fall, so the last order of each is a buy on the same signal.
+    let mut ticks = path("AAPL", &[100, 101, 100, 101, 100, 99]);
+    ticks.extend(path("MSFT", &[100, 120, 100, 120, 100, 80]));
+    let result = broker(&[("Ann", prefs)], BrokerConfig::default(), &ticks);
+
+    let last = |stock: &str| result.orders.iter().rfind(|o| &*o.stock_name == stock).unwrap();
+    let (calm, volatile) = (last("AAPL"), last("MSFT"));
+    assert_eq!((calm.order_type.as_str(), volatile.order_type.as_str()), ("buying", "buying"));
+
+    // AAPL moves about 1% a tick, under the target, and keeps its full size.
+    assert!(realized_vol(&[101, 100, 101, 100, 99]).unwrap() < 0.05);
+    assert_eq!(calm.quantity, 100);
+    let vol = realized_vol(&[120, 100, 120, 100, 80]).unwrap();
+    assert_eq!(volatile.quantity, (100.0 * 0.05 / vol) as i32);
+    assert!(volatile.quantity < calm.quantity);
+}
+
+#[test]
+fn decision_noise_makes_borderline_moves_trade_only_sometimes() {
+    // Moves of 6 against a minimum of 5 all trade without noise.
+    let prices: Vec<i32> = (0..201).map(|i| if i % 2 == 0 { 106 } else { 100 }).collect();
+    let ticks = path("AAPL", &prices);
+    let trades = |decision_noise_bps, seed| {
+        let prefs = ClientPreference { market_min_change: 5, ..fixed(StockType::Tech, "Market", 10) };
+        let config = BrokerConfig { decision_noise_bps, ..BrokerConfig::default() };
+        let result = run_broker("Broker", clients(&[("Ann", prefs)]), config, &ticks, Box::new(seeded_rng(Some(seed))));
+        result.orders.into_iter().map(|o| (o.order_type, o.price)).collect::<Vec<_>>()
+    };
+
+    let quiet = trades(0, 1);
+    assert_eq!(quiet.len(), 200);
+    assert_eq!(trades(0, 2), quiet);
+
+    // 1000 bps of the price shifts the threshold by up to 10 either way.
+    let noisy = trades(1000, 1);
+    assert_eq!(trades(1000, 1), noisy);
+    assert!(noisy.len() > 20 && noisy.len() < 160, "{} of 200 moves traded", noisy.len());
+    assert_ne!(trades(1000, 2), noisy);
+}
+
+#[test]
+fn netting_collapses_a_round_trip_while_gross_keeps_both_legs() {
+    let account = |position_mode| {
+        let config = BrokerConfig { position_mode, ..BrokerConfig::default() };
+        let result = broker(&[("Ann", fixed(StockType::Tech, "Market", 10))], config, &path("AAPL", &[100, 95, 105]));
+        result.accounts["Ann"].clone()
+    };
+    let prices = HashMap::from([("AAPL".to_string(), 105)]);
+
+    // Bought 10 at 95 and sold 10 at 105.
+    let netted = account(PositionMode::Netting);
+    assert_eq!(netted.positions["AAPL"], 0);
+    assert!(netted.short_positions.is_empty());
+    assert_eq!((netted.realized_pnl, netted.unrealized_pnl(&prices)), (100, 0));
+
+    let gross = account(PositionMode::Gross);
+    assert_eq!((gross.positions["AAPL"], gross.short_positions["AAPL"]), (10, 10));
+    assert_eq!((gross.realized_pnl, gross.unrealized_pnl(&prices)), (0, 100));
+
+    // Either way the net position is flat and the cash is the same.
+    for account in [&netted, &gross] {
+        assert_eq!(account.held("AAPL"), 0);
+        assert_eq!(account.market_value(&prices), 0);
+        assert_eq!(account.earnings, 100);
+    }
+}
+
+#[test]
+fn resting_fills_earn_the_maker_rebate_and_arrivals_pay_the_taker_fee() {
+    let config = BrokerConfig {
+        liquidity_per_tick: Some(10),
+        maker_rebate_bps: 10,
+        taker_fee_bps: 20,
+        ..BrokerConfig::default()
+    };
+    let limit = ClientPreference { min_change_buy: 50, min_change_sell: 50, ..fixed(StockType::Tech, "Limit", 20) };
+    let prefs = [("Ann", limit), ("Bob", fixed(StockType::Tech, "Market", 10))];
+    // Ann's buy at 900 fills 10 on arrival and rests for the rest, which
+    // fills at 895. Bob's market buys fill on arrival at 900 and 895.
+    let result = broker(&prefs, config, &path("AAPL", &[1000, 900, 895]));
+
+    let fills = |client: &str| {
+        let orders = result.orders.iter().filter(|o| o.client_name == client);
+        orders.flat_map(|o| &o.fills).map(|f| (f.price, f.liquidity, f.fee)).collect::<Vec<_>>()
+    };
+    // 20 bps of 9000 and 8950, and a rebate of 10 bps of 8950.
+    assert_eq!(fills("Ann"), [(900, LiquiditySide::Taker, 18), (895, LiquiditySide::Maker, -8)]);
+    assert_eq!(fills("Bob"), [(900, LiquiditySide::Taker, 18), (895, LiquiditySide::Taker, 17)]);
+    assert_eq!(result.accounts["Ann"].fees, 10);
+    assert_eq!(result.accounts["Bob"].fees, 35);
+    assert_eq!(result.accounts["Ann"].cash, -(9000 + 8950 + 10));
+}
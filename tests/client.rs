@@ -0,0 +1,238 @@
+mod common;
+
+use std::collections::HashSet;
+
+use common::{broker, clients, fixed, path, tick};
+use ngwaijie_tp066893::error::StockError;
+use ngwaijie_tp066893::stock::{
+    generate_clients, run_broker, run_strategy, BrokerConfig, ClientError, ClientPreference, FlatTick, OrderReason,
+    PriceTick, QuantityStrategy, ScriptedSource, StockType, ThresholdInclusivity, NO_CHANGE, STRATEGY_QUANTITY,
+};
+
+#[test]
+fn buys_and_sells_are_sized_separately() {
+    let prefs = ClientPreference { sell_qty_strategy: QuantityStrategy::Fixed(20), ..fixed(StockType::Tech, "Market", 100) };
+    let result = broker(&[("Ann", prefs)], BrokerConfig::default(), &path("AAPL", &[100, 99, 100]));
+    let orders: Vec<_> = result.orders.iter().map(|o| (o.order_type.as_str(), o.quantity)).collect();
+    assert_eq!(orders, [("buying", 100), ("selling", 20)]);
+}
+
+#[test]
+fn an_empty_random_range_is_rejected() {
+    let prefs = ClientPreference { buy_qty_strategy: QuantityStrategy::Random(50..50), ..fixed(StockType::Tech, "Market", 10) };
+    assert!(matches!(prefs.validate(), Err(StockError::Client(ClientError::EmptyQuantityRange(range))) if range == (50..50)));
+}
+
+#[test]
+fn an_empty_script_draws_the_start_of_each_range() {
+    let prefs = ClientPreference { buy_qty_strategy: QuantityStrategy::Random(25..50), ..fixed(StockType::Tech, "Market", 10) };
+    let clients = clients(&[("Ann", prefs)]);
+    let result = run_broker("Broker", clients, BrokerConfig::default(), &path("AAPL", &[100, 99]), Box::new(ScriptedSource::new(vec![])));
+    assert_eq!(result.orders[0].quantity, 25);
+}
+
+#[test]
+fn a_buy_only_client_never_sells() {
+    let prefs = ClientPreference { enable_sell: false, ..fixed(StockType::Tech, "Market", 10) };
+    let result = broker(&[("Ann", prefs)], BrokerConfig::default(), &path("AAPL", &[100, 90, 120, 80, 150]));
+    let orders: Vec<_> = result.orders.iter().map(|o| (o.order_type.as_str(), o.price)).collect();
+    assert_eq!(orders, [("buying", 90), ("buying", 80)]);
+    assert!(result.skipped.is_empty());
+    assert_eq!(result.accounts["Ann"].held("AAPL"), 20);
+
+    let prefs = ClientPreference { enable_buy: false, ..fixed(StockType::Tech, "Market", 10) };
+    assert!(broker(&[("Ann", prefs)], BrokerConfig::default(), &path("AAPL", &[100, 90, 120])).orders.is_empty());
+}
+
+#[test]
+fn a_client_stops_trading_at_its_profit_target() {
+    let prefs = ClientPreference { profit_target: Some(100), ..fixed(StockType::Tech, "Market", 10) };
+    // Bob trades another sector to keep the broker running.
+    let clients = [("Ann", prefs), ("Bob", fixed(StockType::Food, "Market", 10))];
+    let result = broker(&clients, BrokerConfig::default(), &path("AAPL", &[100, 95, 110, 90, 120]));
+
+    // The sell at 110 earns 150, past the target.
+    let orders: Vec<_> = result.orders.iter().map(|o| (o.order_type.as_str(), o.price)).collect();
+    assert_eq!(orders, [("buying", 95), ("selling", 110)]);
+    assert_eq!(result.accounts["Ann"].earnings, 150);
+}
+
+#[test]
+fn a_rotating_client_moves_on_to_the_next_sector() {
+    let prefs = ClientPreference {
+        buy_qty_strategy: QuantityStrategy::Fixed(10),
+        ..ClientPreference::sector_rotation(2, "Market", 0, 0)
+    };
+    // Every stock falls on every cycle.
+    let ticks: Vec<PriceTick> = (0..6)
+        .flat_map(|seq| {
+            ["AAPL", "KO", "MRK"].map(|name| PriceTick { seq, ..tick(name, 100 - seq as i32, 99 - seq as i32) })
+        })
+        .collect();
+    let result = broker(&[("Ann", prefs)], BrokerConfig::default(), &ticks);
+
+    let orders: Vec<_> = result.orders.iter().map(|o| (&*o.stock_name, o.price)).collect();
+    assert_eq!(orders, [("AAPL", 99), ("AAPL", 98), ("KO", 97), ("KO", 96), ("MRK", 95), ("MRK", 94)]);
+}
+
+fn strategy(prices: &[i32]) -> Vec<(String, i32, i32)> {
+    let orders = run_strategy(ClientPreference::new(StockType::Tech, "Market", 0, 0), &path("AAPL", prices));
+    orders.into_iter().map(|o| (o.order_type, o.quantity, o.price)).collect()
+}
+
+#[test]
+fn a_strategy_buys_on_a_fall() {
+    assert_eq!(strategy(&[100, 98]), [("buying".to_string(), STRATEGY_QUANTITY, 98)]);
+}
+
+#[test]
+fn a_strategy_sells_on_a_rise() {
+    let orders = strategy(&[100, 98, 103]);
+    assert_eq!(orders.len(), 2);
+    assert_eq!(orders[1], ("selling".to_string(), STRATEGY_QUANTITY, 103));
+}
+
+#[test]
+fn a_strategy_ignores_flat_ticks_and_rises_with_nothing_held() {
+    assert_eq!(strategy(&[100, 100, 100]), []);
+    assert_eq!(strategy(&[100, 101, 104]), []);
+}
+
+#[test]
+fn flat_ticks_are_recorded_only_when_configured() {
+    let flat_signals = |flat_tick| {
+        let prefs = ClientPreference { flat_tick, ..fixed(StockType::Tech, "Market", 10) };
+        let result = broker(&[("Ann", prefs)], BrokerConfig::default(), &path("AAPL", &[100, 100, 99, 99]));
+        assert_eq!(result.orders.len(), 1);
+        result.signals.into_iter().filter(|s| s.side == NO_CHANGE).map(|s| (s.client, s.executed)).collect::<Vec<_>>()
+    };
+
+    assert_eq!(flat_signals(FlatTick::Ignore), []);
+    assert_eq!(flat_signals(FlatTick::Record), [("Ann".to_string(), false), ("Ann".to_string(), false)]);
+}
+
+#[test]
+fn a_value_investor_buys_below_fair_value_and_sells_above_it() {
+    let ticks: Vec<PriceTick> = path("AAPL", &[100, 98, 94, 92, 103, 106])
+        .into_iter()
+        .map(|t| PriceTick { stock: t.stock.with_fair_value(100), ..t })
+        .collect();
+    let prefs = ClientPreference {
+        buy_qty_strategy: QuantityStrategy::Fixed(10),
+        ..ClientPreference::value_investing(StockType::Tech, "Market", 5)
+    };
+    let result = broker(&[("Ann", prefs)], BrokerConfig::default(), &ticks);
+
+    // 98 and 103 are within the threshold of the fair value either way.
+    let orders: Vec<_> = result.orders.iter().map(|o| (o.order_type.as_str(), o.price, o.reason.clone())).collect();
+    assert_eq!(
+        orders,
+        [
+            ("buying", 94, OrderReason::BelowFairValue { fair_value: 100 }),
+            ("buying", 92, OrderReason::BelowFairValue { fair_value: 100 }),
+            ("selling", 106, OrderReason::AboveFairValue { fair_value: 100 }),
+        ]
+    );
+}
+
+fn limit_orders(threshold_inclusivity: ThresholdInclusivity, prices: &[i32]) -> Vec<(String, i32)> {
+    let prefs = ClientPreference {
+        min_change_buy: 5,
+        min_change_sell: 5,
+        threshold_inclusivity,
+        ..fixed(StockType::Tech, "Limit", 10)
+    };
+    let result = broker(&[("Ann", prefs)], BrokerConfig::default(), &path("AAPL", prices));
+    result.orders.into_iter().map(|o| (o.order_type, o.price)).collect()
+}
+
+#[test]
+fn moves_of_exactly_the_threshold_trade_only_when_inclusive() {
+    let both = vec![("buying".to_string(), 95), ("selling".to_string(), 100)];
+    assert_eq!(limit_orders(ThresholdInclusivity::Inclusive, &[100, 95, 100]), both);
+    assert_eq!(limit_orders(ThresholdInclusivity::Exclusive, &[100, 95, 100]), []);
+}
+
+#[test]
+fn moves_past_the_threshold_trade_either_way() {
+    let both = vec![("buying".to_string(), 94), ("selling".to_string(), 100)];
+    assert_eq!(limit_orders(ThresholdInclusivity::Inclusive, &[100, 94, 100]), both);
+    assert_eq!(limit_orders(ThresholdInclusivity::Exclusive, &[100, 94, 100]), both);
+}
+
+#[test]
+fn a_market_client_ignores_moves_under_its_minimum() {
+    let market_orders = |market_min_change| {
+        let prefs = ClientPreference { market_min_change, ..fixed(StockType::Tech, "Market", 10) };
+        let result = broker(&[("Ann", prefs)], BrokerConfig::default(), &path("AAPL", &[100, 99, 100, 94, 101]));
+        result.orders.into_iter().map(|o| (o.order_type, o.price)).collect::<Vec<_>>()
+    };
+
+    let every_move = [("buying", 99), ("selling", 100), ("buying", 94), ("selling", 101)];
+    assert_eq!(market_orders(0), every_move.map(|(side, price)| (side.to_string(), price)));
+    // The $1 moves are noise; the 6 and 7 moves still trade.
+    assert_eq!(market_orders(5), [("buying".to_string(), 94), ("selling".to_string(), 101)]);
+}
+
+#[test]
+fn a_hundred_generated_clients_all_trade() {
+    let clients = generate_clients(100, StockType::Food, "Limit");
+    let names: HashSet<_> = clients.keys().cloned().collect();
+    assert_eq!(names, (1..=100).map(|i| format!("Client {}", i)).collect());
+    for prefs in clients.values() {
+        assert_eq!(prefs.stock_type, StockType::Food);
+        assert_eq!(prefs.order_category, "Limit");
+        assert_eq!((prefs.min_change_buy, prefs.min_change_sell), (0, 0));
+    }
+
+    let quantities = Box::new(ScriptedSource::new(vec![10]));
+    let result = run_broker("Broker", clients, BrokerConfig::default(), &path("KO", &[100, 98]), quantities);
+    let buyers: HashSet<_> = result.orders.iter().map(|o| o.client_name.clone()).collect();
+    assert_eq!(result.orders.len(), 100);
+    assert_eq!(buyers, names);
+}
+
+#[test]
+fn momentum_trades_with_a_run_once_it_reaches_the_lookback() {
+    // Three rises and four falls; the fourth finds nothing left to sell.
+    let ticks = path("AAPL", &[100, 101, 102, 103, 102, 101, 100, 99]);
+    let orders = run_strategy(ClientPreference::momentum(StockType::Tech, "Market", 3), &ticks);
+
+    let trades: Vec<_> = orders.iter().map(|o| (o.order_type.as_str(), o.price, o.reason.clone())).collect();
+    assert_eq!(
+        trades,
+        [
+            ("buying", 103, OrderReason::RisingStreak { ticks: 3 }),
+            ("selling", 100, OrderReason::FallingStreak { ticks: 3 }),
+        ]
+    );
+    // Shorter runs never trade.
+    assert!(run_strategy(ClientPreference::momentum(StockType::Tech, "Market", 4), &ticks[..6]).is_empty());
+}
+
+#[test]
+fn mean_reversion_takes_the_other_side_of_each_run() {
+    // A run of falls, of rises, then of falls again.
+    let ticks = path("AAPL", &[100, 99, 98, 97, 98, 99, 100, 99, 98, 97]);
+    let sides = |prefs: ClientPreference| {
+        let result = broker(&[("Ann", prefs)], BrokerConfig::default(), &ticks);
+        result.signals.into_iter().map(|s| s.side).collect::<Vec<_>>()
+    };
+
+    let momentum = sides(ClientPreference::momentum(StockType::Tech, "Market", 3));
+    let reversion = sides(ClientPreference::mean_reversion(StockType::Tech, "Market", 3));
+    assert_eq!(momentum, ["selling", "buying", "selling"]);
+    assert_eq!(reversion, ["buying", "selling", "buying"]);
+
+    // Momentum has nothing to sell on the first run, so only reversion trades it.
+    let orders = run_strategy(ClientPreference::mean_reversion(StockType::Tech, "Market", 3), &ticks);
+    let trades: Vec<_> = orders.iter().map(|o| (o.order_type.as_str(), o.price, o.reason.clone())).collect();
+    assert_eq!(
+        trades,
+        [
+            ("buying", 97, OrderReason::FallingStreak { ticks: 3 }),
+            ("selling", 100, OrderReason::RisingStreak { ticks: 3 }),
+            ("buying", 97, OrderReason::FallingStreak { ticks: 3 }),
+        ]
+    );
+}
@@ -0,0 +1,40 @@
+// Helpers shared by the integration tests. Not every test file uses all of them.
+#![allow(dead_code)]
+
+use std::collections::HashMap;
+
+use ngwaijie_tp066893::stock::{
+    run_broker, BrokerConfig, BrokerResult, ClientPreference, PriceTick, QuantityStrategy, ScriptedSource, Stock,
+    StockType,
+};
+
+// A tick of `name` moving from `prev` to `v`.
+pub fn tick(name: &str, prev: i32, v: i32) -> PriceTick {
+    PriceTick { stock: Stock::new(name, v).with_prev_v(prev), day: 0, seq: 0 }
+}
+
+// Ticks of one stock following `prices`, each measured against the one before.
+pub fn path(name: &str, prices: &[i32]) -> Vec<PriceTick> {
+    prices
+        .windows(2)
+        .enumerate()
+        .map(|(seq, pair)| PriceTick { seq: seq as u64, ..tick(name, pair[0], pair[1]) })
+        .collect()
+}
+
+// Trades on any move, in orders of `quantity` shares.
+pub fn fixed(stock_type: StockType, category: &str, quantity: i32) -> ClientPreference {
+    ClientPreference {
+        buy_qty_strategy: QuantityStrategy::Fixed(quantity),
+        sell_qty_strategy: QuantityStrategy::Fixed(quantity),
+        ..ClientPreference::new(stock_type, category, 0, 0)
+    }
+}
+
+pub fn clients(prefs: &[(&str, ClientPreference)]) -> HashMap<String, ClientPreference> {
+    prefs.iter().map(|(name, prefs)| (name.to_string(), prefs.clone())).collect()
+}
+
+pub fn broker(prefs: &[(&str, ClientPreference)], config: BrokerConfig, ticks: &[PriceTick]) -> BrokerResult {
+    run_broker("Broker", clients(prefs), config, ticks, Box::new(ScriptedSource::new(vec![50])))
+}
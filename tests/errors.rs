@@ -0,0 +1,212 @@
+mod common;
+
+use std::collections::HashMap;
+use std::ops::Range;
+use std::time::{Duration, Instant};
+
+use common::{broker, clients, fixed, path};
+use ngwaijie_tp066893::error::StockError;
+use ngwaijie_tp066893::exchange::{ExchangeError, StockExchange};
+use ngwaijie_tp066893::report::{merge_broker_results, ExchangeReport, Format, ReportError};
+use ngwaijie_tp066893::stock::{
+    process_broker_actions, replay_prices_csv, BrokerConfig, ClientError, ClientPreference, CopyTrading, RandomSource,
+    ReplayError, SimulationConfig, SimulationControl, Stock, StockType, Verbosity,
+};
+
+fn quiet() -> SimulationConfig {
+    SimulationConfig { speed: 100.0, verbosity: Verbosity::Silent, ..SimulationConfig::default() }
+}
+
+fn listed(config: SimulationConfig) -> StockExchange {
+    let mut exchange = StockExchange::new(config);
+    exchange.add_stock(Stock::new("AAPL", 100));
+    exchange
+}
+
+fn tech(names: &[&str]) -> HashMap<String, ClientPreference> {
+    let prefs: Vec<_> = names.iter().map(|name| (*name, fixed(StockType::Tech, "Market", 10))).collect();
+    clients(&prefs)
+}
+
+fn exchange_error(exchange: StockExchange) -> ExchangeError {
+    match exchange.start() {
+        Err(StockError::Exchange(error)) => error,
+        other => panic!("expected an exchange error, got {:?}", other.err()),
+    }
+}
+
+#[test]
+fn starting_without_stocks_fails() {
+    assert_eq!(exchange_error(StockExchange::new(quiet())), ExchangeError::NoStocks);
+}
+
+#[test]
+fn an_exchange_with_brokers_but_no_stocks_fails_instead_of_hanging() {
+    let mut exchange = StockExchange::new(quiet());
+    exchange.add_broker("Broker", tech(&["Ann", "Bob"]), BrokerConfig::default());
+    assert!(matches!(exchange.validate(), Err(StockError::Exchange(ExchangeError::NoStocks))));
+
+    let started = Instant::now();
+    assert!(matches!(exchange.run(), Err(StockError::Exchange(ExchangeError::NoStocks))));
+    assert!(started.elapsed() < Duration::from_secs(1));
+}
+
+#[test]
+fn starting_with_a_ticker_listed_twice_fails() {
+    let mut exchange = listed(quiet());
+    exchange.add_stock(Stock::new("AAPL", 120));
+    assert_eq!(exchange_error(exchange), ExchangeError::DuplicateTicker("AAPL".to_string()));
+}
+
+#[test]
+fn starting_with_a_client_at_two_brokers_fails() {
+    let mut exchange = listed(quiet());
+    exchange.add_broker("First", tech(&["Ann"]), BrokerConfig::default());
+    exchange.add_broker("Second", tech(&["Ann"]), BrokerConfig::default());
+    assert_eq!(exchange_error(exchange), ExchangeError::DuplicateClient("Ann".to_string()));
+}
+
+#[test]
+fn starting_a_follower_of_an_unknown_leader_fails() {
+    let mut exchange = listed(quiet());
+    let copy = CopyTrading { leader: "Nobody".to_string(), scale: 1.0 };
+    exchange.add_broker("Follower", tech(&["Ann"]), BrokerConfig { copy: Some(copy), ..BrokerConfig::default() });
+    assert_eq!(exchange_error(exchange), ExchangeError::UnknownLeader("Nobody".to_string()));
+}
+
+#[test]
+fn starting_with_an_invalid_client_fails() {
+    let mut exchange = listed(quiet());
+    let prefs = HashMap::from([("Ann".to_string(), ClientPreference::new(StockType::Tech, "Market", -5, 0))]);
+    exchange.add_broker("Broker", prefs, BrokerConfig::default());
+    assert_eq!(
+        exchange_error(exchange),
+        ExchangeError::InvalidClient { client: "Ann".to_string(), error: ClientError::NegativeThreshold(-5) }
+    );
+}
+
+#[test]
+fn starting_with_an_invalid_config_fails() {
+    let result = listed(SimulationConfig { speed: 0.0, ..quiet() }).run();
+    assert!(matches!(result, Err(StockError::InvalidConfig(_))), "{:?}", result.err());
+
+    let result = listed(SimulationConfig { watchdog_timeout: Duration::MAX, speed: 0.5, ..quiet() }).run();
+    assert!(matches!(&result, Err(StockError::Overflow(what)) if what == "watchdog_timeout"), "{:?}", result.err());
+}
+
+#[test]
+fn a_negative_threshold_is_a_client_error() {
+    let result = ClientPreference::try_new(StockType::Food, "Limit", 10, -1);
+    assert!(matches!(result, Err(StockError::Client(ClientError::NegativeThreshold(-1)))));
+}
+
+#[test]
+fn an_unknown_ticker_cannot_be_classified() {
+    assert!(matches!(Stock::new("ZZZZ", 10).classify(), Err(StockError::Unclassified(name)) if name == "ZZZZ"));
+}
+
+#[test]
+fn a_malformed_price_series_is_a_replay_error() {
+    let result = replay_prices_csv("AAPL,1,100\nAAPL,two,101\n".as_bytes());
+    assert!(matches!(result, Err(StockError::Replay(ReplayError::InvalidRow { line: 2, .. }))));
+}
+
+#[test]
+fn an_unwritable_report_is_a_report_error() {
+    let report = ExchangeReport::new(Vec::new(), Vec::new(), Vec::new(), Duration::ZERO);
+    let result = report.write_to_file("/nonexistent/dir/report.json", Format::Json);
+    assert!(matches!(result, Err(StockError::Report(ReportError::Io { .. }))));
+}
+
+#[test]
+fn merging_a_client_twice_fails() {
+    let ticks = path("AAPL", &[100, 99]);
+    let first = broker(&[("Ann", fixed(StockType::Tech, "Market", 10))], BrokerConfig::default(), &ticks);
+    let second = broker(&[("Ann", fixed(StockType::Tech, "Market", 10))], BrokerConfig::default(), &ticks);
+    let result = merge_broker_results(vec![first, second]);
+    assert!(matches!(result, Err(StockError::Exchange(ExchangeError::DuplicateClient(client))) if client == "Ann"));
+}
+
+struct Exploding;
+
+impl RandomSource for Exploding {
+    fn delta(&mut self, _range: Range<i32>) -> i32 {
+        panic!("no quantities left")
+    }
+}
+
+#[test]
+fn a_panicking_broker_fails_the_run() {
+    let mut exchange = listed(SimulationConfig { max_ticks: Some(20), ..quiet() });
+    let prefs = HashMap::from([("Ann".to_string(), ClientPreference::new(StockType::Tech, "Market", 0, 0))]);
+    exchange.add_broker("Doomed", prefs, BrokerConfig::default());
+    exchange.set_quantity_source("Doomed", Box::new(Exploding));
+    match exchange.run() {
+        Err(StockError::Exchange(ExchangeError::ThreadPanicked { thread, message })) => {
+            assert_eq!((thread.as_str(), message.as_str()), ("Doomed", "no quantities left"));
+        }
+        other => panic!("expected the broker's panic, got {:?}", other.err()),
+    }
+}
+
+#[test]
+fn a_broker_panic_names_its_thread() {
+    let control = SimulationControl::new();
+    let (sender, receiver) = crossbeam_channel::unbounded();
+    let prefs = HashMap::from([("Ann".to_string(), ClientPreference::new(StockType::Tech, "Market", 0, 0))]);
+    let handle = process_broker_actions(
+        "Doomed".to_string(),
+        receiver,
+        prefs,
+        10,
+        control,
+        BrokerConfig::default(),
+        Box::new(Exploding),
+        Verbosity::Silent,
+    );
+    assert_eq!(handle.thread().name(), Some("Doomed"));
+    sender.send(path("AAPL", &[100, 99])).unwrap();
+    assert!(handle.join().is_err());
+
+    let mut exchange = listed(SimulationConfig { max_ticks: Some(20), ..quiet() });
+    let prefs = HashMap::from([("Ann".to_string(), ClientPreference::new(StockType::Tech, "Market", 0, 0))]);
+    exchange.add_broker("Doomed", prefs, BrokerConfig::default());
+    exchange.set_quantity_source("Doomed", Box::new(Exploding));
+    let error = exchange.run().unwrap_err();
+    assert_eq!(error.to_string(), "thread Doomed panicked: no quantities left");
+}
+
+#[test]
+fn a_duplicate_client_fails_before_any_price_is_drawn() {
+    let mut exchange = listed(quiet());
+    exchange.add_broker("First", tech(&["Ann"]), BrokerConfig::default());
+    exchange.add_broker("Second", tech(&["Bob", "Ann"]), BrokerConfig::default());
+    // The simulator would panic on its first tick if the run had started.
+    exchange.set_price_source(Box::new(Exploding));
+    assert!(matches!(
+        exchange.run(),
+        Err(StockError::Exchange(ExchangeError::DuplicateClient(client))) if client == "Ann"
+    ));
+}
+
+#[test]
+fn every_negative_threshold_is_rejected_before_the_run() {
+    let base = ClientPreference::new(StockType::Tech, "Limit", 0, 0);
+    assert!(base.validate().is_ok());
+    let negatives = [
+        ClientPreference { min_change_buy: -3, ..base.clone() },
+        ClientPreference { min_change_sell: -3, ..base.clone() },
+        ClientPreference { market_min_change: -3, ..base.clone() },
+    ];
+    for prefs in negatives {
+        assert!(matches!(prefs.validate(), Err(StockError::Client(ClientError::NegativeThreshold(-3)))));
+
+        let mut exchange = listed(quiet());
+        exchange.add_broker("Broker", HashMap::from([("Ann".to_string(), prefs)]), BrokerConfig::default());
+        exchange.set_price_source(Box::new(Exploding));
+        assert!(matches!(
+            exchange.run(),
+            Err(StockError::Exchange(ExchangeError::InvalidClient { error: ClientError::NegativeThreshold(-3), .. }))
+        ));
+    }
+}
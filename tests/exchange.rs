@@ -0,0 +1,607 @@
+mod common;
+
+use std::collections::HashMap;
+use std::thread;
+use std::time::Duration;
+
+use ngwaijie_tp066893::exchange::{MarketSnapshot, StockExchange};
+use common::path;
+use ngwaijie_tp066893::report::ExchangeReport;
+use ngwaijie_tp066893::stock::{
+    pin_current_thread, seeded_rng, AckStatus, BrokerConfig, CandleInterval, ClientPreference, CopyTrading, Ohlc,
+    OrderReason, OrderThrottle, RandomSource, ScriptedSource, SimulationConfig, SkipReason, Stock, StockSettings, StockType,
+    Verbosity,
+};
+
+// Ticks every 10ms. The watchdog is left well clear of a busy test machine:
+// at the 1000x some tests run at, the default would fire after 10ms.
+fn fast() -> SimulationConfig {
+    SimulationConfig {
+        speed: 100.0,
+        verbosity: Verbosity::Silent,
+        watchdog_timeout: Duration::from_secs(600),
+        ..SimulationConfig::default()
+    }
+}
+
+fn tech_clients(names: &[&str]) -> HashMap<String, ClientPreference> {
+    names.iter().map(|name| (name.to_string(), ClientPreference::new(StockType::Tech, "Market", 0, 0))).collect()
+}
+
+#[test]
+fn the_clock_starts_when_the_exchange_starts() {
+    // A 50ms watchdog, well under the time the exchange sits unstarted.
+    let config = SimulationConfig {
+        watchdog_timeout: Duration::from_secs(5),
+        max_ticks: Some(10),
+        transaction_limit: 1000,
+        ..fast()
+    };
+    let mut exchange = StockExchange::new(config);
+    exchange.add_stock(Stock::new("AAPL", 100));
+    exchange.add_broker("Broker", tech_clients(&["Ann"]), BrokerConfig::default());
+    thread::sleep(Duration::from_millis(200));
+
+    let handle = exchange.start().unwrap();
+    assert!(handle.control().elapsed() < Duration::from_millis(100));
+    let report = handle.join().unwrap();
+    assert_eq!(report.ticks, 10, "the watchdog cut the run short");
+    assert!(report.duration < Duration::from_millis(200));
+    let first = report.brokers[0].orders.first().expect("the client trades");
+    assert!(first.ts < Duration::from_millis(100), "order placed {:?} after the start", first.ts);
+}
+
+#[test]
+fn a_shutdown_request_ends_the_run_with_a_report() {
+    let mut exchange = StockExchange::new(SimulationConfig { transaction_limit: i32::MAX, ..fast() });
+    exchange.add_stock(Stock::new("AAPL", 100));
+    exchange.add_broker("Broker", tech_clients(&["Ann"]), BrokerConfig::default());
+    let control = exchange.control();
+    let handle = exchange.start().unwrap();
+    thread::sleep(Duration::from_millis(100));
+
+    control.request_shutdown();
+    let report = handle.join().unwrap();
+    assert!(report.ticks > 0);
+    assert_eq!(report.closing.len(), 1);
+    assert!(report.reconcile().is_ok());
+}
+
+fn zigzag_run(retention: Option<usize>) -> ExchangeReport {
+    let config = SimulationConfig { retention, candle_interval: Some(CandleInterval::Ticks(1)), speed: 1000.0, ..fast() };
+    let mut exchange = StockExchange::new(config);
+    exchange.replay(path("AAPL", &[100, 99, 101].repeat(10)));
+    exchange.add_broker("Broker", tech_clients(&["Ann", "Bob"]), BrokerConfig::default());
+    exchange.set_quantity_source("Broker", Box::new(ScriptedSource::new(vec![3])));
+    exchange.run().unwrap()
+}
+
+#[test]
+fn retention_bounds_the_history_but_not_the_totals() {
+    let full = zigzag_run(None);
+    let bounded = zigzag_run(Some(3));
+    assert!(full.acks.len() > 3 && full.tape.len() > 3);
+    assert_eq!((bounded.acks.len(), bounded.tape.len()), (3, 3));
+    assert_eq!(bounded.candles["AAPL"].len(), 3);
+    assert_eq!(bounded.price_history["AAPL"].len(), 3);
+    assert_eq!(bounded.brokers[0].orders.len(), 3);
+    assert_eq!(bounded.volume("AAPL"), full.volume("AAPL"));
+    assert_eq!(bounded.vwap("AAPL"), full.vwap("AAPL"));
+}
+
+#[test]
+fn stocks_listed_mid_run_start_ticking() {
+    let mut exchange = StockExchange::new(SimulationConfig { transaction_limit: i32::MAX, ..fast() });
+    exchange.add_stock(Stock::new("AAPL", 100));
+    exchange.add_broker("Broker", tech_clients(&["Ann"]), BrokerConfig::default());
+    let control = exchange.control();
+    let handle = exchange.start().unwrap();
+    thread::sleep(Duration::from_millis(50));
+
+    assert!(handle.add_stock_live("IPO", 500, StockType::Tech));
+    assert!(!handle.add_stock_live("IPO", 500, StockType::Tech));
+    thread::sleep(Duration::from_millis(100));
+    assert_eq!(handle.remove_stock_live("AAPL").map(|s| s.name.to_string()), Some("AAPL".to_string()));
+    // The last stock cannot be delisted.
+    assert!(handle.remove_stock_live("IPO").is_none());
+    thread::sleep(Duration::from_millis(50));
+
+    control.request_shutdown();
+    let report = handle.join().unwrap();
+    assert!(report.price_history["IPO"].len() > 1);
+    assert_eq!(report.closing.iter().map(|s| &*s.name).collect::<Vec<_>>(), ["IPO"]);
+    assert!(report.brokers[0].orders.iter().any(|o| &*o.stock_name == "IPO"));
+}
+
+#[test]
+fn broker_start_jitter_is_drawn_apart_from_the_price_path() {
+    // Up to 500ms at 1ms per tick.
+    let jitter = Duration::from_secs(500);
+    let config = SimulationConfig {
+        broker_start_jitter: jitter,
+        seed: Some(4),
+        speed: 1000.0,
+        transaction_limit: i32::MAX,
+        ..fast()
+    };
+    let mut exchange = StockExchange::new(config);
+    exchange.add_stock(Stock::new("AAPL", 100));
+    exchange.add_broker("First", tech_clients(&["Ann"]), BrokerConfig::default());
+    exchange.add_broker("Second", tech_clients(&["Bob"]), BrokerConfig::default());
+    let control = exchange.control();
+    let handle = exchange.start().unwrap();
+    thread::sleep(Duration::from_millis(300));
+    control.request_shutdown();
+    let report = handle.join().unwrap();
+
+    let starts: Vec<Duration> = report.brokers.iter().map(|b| b.orders[0].ts).collect();
+    let apart = |a: Duration, b: Duration| a.max(b) - a.min(b) > Duration::from_millis(50);
+    assert!(apart(starts[0], starts[1]), "brokers started together at {:?}", starts);
+    // Drawn from the seed the prices use, the first delay would be this.
+    let shared = seeded_rng(Some(4)).delta(0..jitter.as_micros() as i32);
+    assert!(apart(starts[0], Duration::from_micros(shared as u64) / 1000), "{:?} follows the price seed", starts[0]);
+}
+
+#[test]
+fn the_tape_has_one_entry_per_executed_order_in_time_order() {
+    let mut exchange = StockExchange::new(SimulationConfig { speed: 1000.0, ..fast() });
+    exchange.replay(path("AAPL", &[100, 90, 95, 85, 90, 80, 88, 70]));
+    let prefs = HashMap::from([
+        ("Ann".to_string(), ClientPreference::new(StockType::Tech, "Limit", 0, 0)),
+        ("Bob".to_string(), ClientPreference::new(StockType::Tech, "Market", 0, 0)),
+    ]);
+    // Ann's orders of 30 fill 10 a tick.
+    exchange.add_broker("Broker", prefs, BrokerConfig { liquidity_per_tick: Some(10), ..BrokerConfig::default() });
+    exchange.set_quantity_source("Broker", Box::new(ScriptedSource::new(vec![30])));
+    let report = exchange.run().unwrap();
+
+    let orders = &report.brokers[0].orders;
+    assert!(orders.iter().any(|o| o.fills.len() > 1), "no order filled in pieces");
+    assert!(report.tape.windows(2).all(|pair| pair[0].ts <= pair[1].ts));
+    let mut printed: Vec<_> = report.tape.iter().map(|e| (e.ts, e.qty, e.side.clone())).collect();
+    let mut executed: Vec<_> = orders
+        .iter()
+        .filter_map(|o| Some((o.fills.last()?.ts, o.filled_qty, o.order_type.clone())))
+        .collect();
+    printed.sort();
+    executed.sort();
+    assert_eq!(printed, executed);
+}
+
+#[test]
+fn the_run_stops_at_exactly_max_ticks() {
+    // Seven ticks end partway through the third cycle of three stocks.
+    let config = SimulationConfig { max_ticks: Some(7), transaction_limit: i32::MAX, speed: 1000.0, ..fast() };
+    let mut exchange = StockExchange::new(config);
+    for name in ["AAPL", "MSFT", "NVDA"] {
+        exchange.add_stock(Stock::new(name, 100));
+    }
+    exchange.add_broker("Broker", tech_clients(&["Ann"]), BrokerConfig::default());
+    let report = exchange.run().unwrap();
+
+    assert_eq!(report.ticks, 7);
+    assert_eq!(report.price_history.values().map(Vec::len).sum::<usize>(), 7);
+}
+
+#[test]
+fn orders_are_returned_with_non_decreasing_timestamps() {
+    let config = SimulationConfig { max_ticks: Some(60), transaction_limit: i32::MAX, speed: 1000.0, ..fast() };
+    let mut exchange = StockExchange::new(config);
+    exchange.add_stock(Stock::new("AAPL", 100));
+    exchange.add_stock(Stock::new("MSFT", 100));
+    exchange.add_broker("First", tech_clients(&["Ann", "Bob"]), BrokerConfig::default());
+    exchange.add_broker("Second", tech_clients(&["Cat"]), BrokerConfig::default());
+    let report = exchange.run().unwrap();
+
+    for broker in &report.brokers {
+        assert!(broker.orders.len() > 1, "{} placed too few orders", broker.name);
+        assert!(broker.orders.windows(2).all(|pair| pair[0].ts <= pair[1].ts), "{} is out of order", broker.name);
+        assert!(broker.orders.iter().all(|o| o.ts <= report.duration));
+    }
+}
+
+fn seeded_run(seed: u64) -> (Vec<i32>, Vec<(String, i32, i32)>) {
+    let config =
+        SimulationConfig { seed: Some(seed), max_ticks: Some(30), transaction_limit: i32::MAX, speed: 1000.0, ..fast() };
+    let mut exchange = StockExchange::new(config);
+    exchange.add_stock(Stock::new("AAPL", 100));
+    exchange.add_broker("Broker", tech_clients(&["Ann"]), BrokerConfig::default());
+    let report = exchange.run().unwrap();
+    let orders = report.brokers[0].orders.iter().map(|o| (o.order_type.clone(), o.quantity, o.price)).collect();
+    (report.price_history["AAPL"].clone(), orders)
+}
+
+#[test]
+fn seeded_exchanges_run_side_by_side_independently() {
+    let runs: Vec<_> = [1, 2, 1].map(|seed| thread::spawn(move || seeded_run(seed))).into_iter().collect();
+    let runs: Vec<_> = runs.into_iter().map(|run| run.join().unwrap()).collect();
+
+    assert_eq!(runs[0], runs[2]);
+    assert_eq!(runs[0], seeded_run(1));
+    assert_ne!(runs[0].0, runs[1].0);
+    assert!(!runs[0].1.is_empty() && !runs[1].1.is_empty());
+}
+
+#[test]
+fn snapshots_hold_the_prices_of_a_single_cycle() {
+    let names = ["AAPL", "MSFT", "NVDA"];
+    let config = SimulationConfig { transaction_limit: i32::MAX, speed: 1000.0, ..fast() };
+    let mut exchange = StockExchange::new(config);
+    for name in names {
+        exchange.add_stock(Stock::new(name, 100));
+    }
+    exchange.add_broker("Broker", tech_clients(&["Ann"]), BrokerConfig::default());
+    let control = exchange.control();
+    let handle = exchange.start().unwrap();
+    let snapshots: Vec<_> = (0..20)
+        .map(|_| {
+            thread::sleep(Duration::from_millis(3));
+            handle.snapshot()
+        })
+        .collect();
+    control.request_shutdown();
+    let report = handle.join().unwrap();
+
+    for snapshot in snapshots.iter().filter(|s| s.ticks > 0) {
+        assert_eq!(snapshot.ticks % 3, 0, "snapshot taken partway through a cycle");
+        let cycle = snapshot.ticks as usize / 3 - 1;
+        for name in names {
+            assert_eq!(snapshot.get(name).unwrap().v, report.price_history[name][cycle], "{} after cycle {}", name, cycle);
+        }
+    }
+    assert!(snapshots.iter().filter(|s| s.ticks > 0).count() > 10);
+}
+
+#[test]
+fn scripted_sources_give_exact_prices_and_quantities() {
+    let config = SimulationConfig { max_ticks: Some(3), speed: 1000.0, ..fast() };
+    let mut exchange = StockExchange::new(config);
+    exchange.add_stock(Stock::new("AAPL", 100));
+    exchange.add_broker("Broker", tech_clients(&["Ann"]), BrokerConfig::default());
+    exchange.set_price_source(Box::new(ScriptedSource::new(vec![-5, 10, -3])));
+    exchange.set_quantity_source("Broker", Box::new(ScriptedSource::new(vec![30, 20])));
+    let report = exchange.run().unwrap();
+
+    assert_eq!(report.price_history["AAPL"], [95, 105, 102]);
+    let orders: Vec<_> = report.brokers[0].orders.iter().map(|o| (o.order_type.as_str(), o.quantity, o.price)).collect();
+    assert_eq!(orders, [("buying", 30, 95), ("selling", 20, 105), ("buying", 30, 102)]);
+}
+
+#[test]
+fn completion_status_turns_true_when_a_broker_finishes() {
+    let mut exchange = StockExchange::new(SimulationConfig { transaction_limit: 3, ..fast() });
+    exchange.add_stock(Stock::new("AAPL", 100));
+    exchange.add_stock(Stock::new("KO", 100));
+    exchange.add_broker("Quick", tech_clients(&["Ann"]), BrokerConfig::default());
+    // Bob waits for a move no stock will make.
+    let idle = HashMap::from([("Bob".to_string(), ClientPreference::new(StockType::Food, "Limit", 1_000, 1_000))]);
+    exchange.add_broker("Idle", idle, BrokerConfig::default());
+    let handle = exchange.start().unwrap();
+
+    let status = |quick, idle| [("Idle".to_string(), idle), ("Quick".to_string(), quick)].into();
+    assert_eq!(handle.completion_status(), status(false, false));
+    let mut polls = 0;
+    while !handle.completion_status()["Quick"] && polls < 500 {
+        thread::sleep(Duration::from_millis(5));
+        polls += 1;
+    }
+    assert_eq!(handle.completion_status(), status(true, false));
+
+    handle.control().request_shutdown();
+    let control = handle.control().clone();
+    let report = handle.join().unwrap();
+    assert_eq!(control.completion_status(), status(true, true));
+    assert_eq!(report.brokers[0].orders.len(), 3);
+}
+
+#[test]
+fn a_follower_at_scale_two_copies_double_quantities() {
+    // The leader stops after four orders, well before the last tick, so the
+    // follower has time to copy every one of them.
+    let config = SimulationConfig { max_ticks: Some(400), transaction_limit: 4, speed: 1000.0, ..fast() };
+    let mut exchange = StockExchange::new(config);
+    exchange.add_stock(Stock::new("AAPL", 100));
+    exchange.add_broker("Leader", tech_clients(&["Ann"]), BrokerConfig::default());
+    let copy = CopyTrading { leader: "Leader".to_string(), scale: 2.0 };
+    exchange.add_broker("Follower", tech_clients(&["Bob"]), BrokerConfig { copy: Some(copy), ..BrokerConfig::default() });
+    exchange.set_quantity_source("Leader", Box::new(ScriptedSource::new(vec![10, 25, 5])));
+    let report = exchange.run().unwrap();
+
+    let broker = |name: &str| report.brokers.iter().find(|b| b.name == name).unwrap();
+    let leader: HashMap<u64, _> = broker("Leader").orders.iter().map(|o| (o.id, o)).collect();
+    let follower = &broker("Follower").orders;
+    assert_eq!((leader.len(), follower.len()), (4, 4));
+    for order in follower {
+        let OrderReason::Copied { leader_order } = order.reason else { panic!("{:?} was not copied", order.reason) };
+        let original = leader[&leader_order];
+        assert_eq!((&order.stock_name, &order.order_type), (&original.stock_name, &original.order_type));
+        assert_eq!(order.quantity, 2 * original.quantity);
+    }
+}
+
+#[test]
+fn the_tech_cap_holds_across_brokers() {
+    let config = SimulationConfig {
+        max_ticks: Some(90),
+        transaction_limit: i32::MAX,
+        speed: 1000.0,
+        sector_transaction_limits: HashMap::from([(StockType::Tech, 3)]),
+        ..fast()
+    };
+    let mut exchange = StockExchange::new(config);
+    for name in ["AAPL", "MSFT", "KO"] {
+        exchange.add_stock(Stock::new(name, 100));
+    }
+    let mut clients = tech_clients(&["Ann", "Bob"]);
+    clients.insert("Cat".to_string(), ClientPreference::new(StockType::Food, "Market", 0, 0));
+    exchange.add_broker("First", clients, BrokerConfig::default());
+    exchange.add_broker("Second", tech_clients(&["Dan"]), BrokerConfig::default());
+    let report = exchange.run().unwrap();
+
+    let orders: Vec<_> = report.brokers.iter().flat_map(|b| &b.orders).collect();
+    let tech = orders.iter().filter(|o| &*o.stock_name != "KO").count();
+    assert_eq!(tech, 3);
+    // Other sectors are not held back by the Tech cap.
+    assert!(orders.len() > tech);
+}
+
+#[test]
+fn ohlc_follows_a_scripted_price_path() {
+    let config = SimulationConfig { max_ticks: Some(5), speed: 1000.0, ..fast() };
+    let mut exchange = StockExchange::new(config);
+    exchange.add_stock(Stock::new("AAPL", 100));
+    exchange.add_broker("Broker", tech_clients(&["Ann"]), BrokerConfig::default());
+    exchange.set_price_source(Box::new(ScriptedSource::new(vec![5, 10, -20, 3, 4])));
+    let report = exchange.run().unwrap();
+
+    assert_eq!(report.price_history["AAPL"], [105, 115, 95, 98, 102]);
+    assert_eq!(report.ohlc("AAPL"), Some(Ohlc { open: 100, high: 115, low: 95, close: 102 }));
+    assert_eq!(report.ohlc("MSFT"), None);
+}
+
+#[test]
+fn five_tick_candles_split_a_scripted_path() {
+    let config = SimulationConfig {
+        max_ticks: Some(12),
+        transaction_limit: i32::MAX,
+        speed: 1000.0,
+        candle_interval: Some(CandleInterval::Ticks(5)),
+        ..fast()
+    };
+    let mut exchange = StockExchange::new(config);
+    exchange.add_stock(Stock::new("AAPL", 100));
+    exchange.add_broker("Broker", tech_clients(&["Ann"]), BrokerConfig::default());
+    exchange.set_price_source(Box::new(ScriptedSource::new(vec![-5, -5, 10, -3, 2, 4, -6, -1, 7, 3, -2, 5])));
+    exchange.set_quantity_source("Broker", Box::new(ScriptedSource::new(vec![10])));
+    let report = exchange.run().unwrap();
+
+    assert_eq!(report.price_history["AAPL"], [95, 90, 100, 97, 99, 103, 97, 96, 103, 106, 104, 109]);
+    // Each candle opens at the price its first tick moved from.
+    let candles: Vec<_> = report.candles("AAPL").iter().map(|c| (c.ohlc, c.ticks)).collect();
+    assert_eq!(
+        candles,
+        [
+            (Ohlc { open: 100, high: 100, low: 90, close: 99 }, 5),
+            (Ohlc { open: 99, high: 106, low: 96, close: 106 }, 5),
+            (Ohlc { open: 106, high: 109, low: 104, close: 109 }, 2),
+        ]
+    );
+    let filled: i64 = report.brokers[0].orders.iter().map(|o| o.filled_qty as i64).sum();
+    assert!(filled > 0);
+    assert_eq!(report.candles("AAPL").iter().map(|c| c.volume).sum::<i64>(), filled);
+}
+
+#[test]
+fn a_client_trades_only_inside_its_active_window() {
+    let config = SimulationConfig { max_ticks: Some(40), transaction_limit: i32::MAX, ..fast() };
+    let mut exchange = StockExchange::new(config);
+    exchange.add_stock(Stock::new("AAPL", 100));
+    let mut clients = tech_clients(&["Ann", "Bob"]);
+    // Simulated seconds 10 to 25, which at 100x speed are 100ms to 250ms in.
+    clients.get_mut("Ann").unwrap().active_window = Some((Duration::from_secs(10), Duration::from_secs(25)));
+    let (from, until) = (Duration::from_millis(100), Duration::from_millis(250));
+    exchange.add_broker("Broker", clients, BrokerConfig::default());
+    let report = exchange.run().unwrap();
+
+    let times = |client: &str| -> Vec<Duration> {
+        report.brokers[0].orders.iter().filter(|o| o.client_name == client).map(|o| o.ts).collect()
+    };
+    // An order is timed just after the window was checked, so one that
+    // passed the check right at the end may be stamped a moment later.
+    let ann = times("Ann");
+    assert!(!ann.is_empty());
+    assert!(ann.iter().all(|ts| *ts >= from && *ts < until + Duration::from_millis(5)), "{:?}", ann);
+    // Bob, with no window, also trades outside it.
+    let bob = times("Bob");
+    assert!(bob.iter().any(|ts| !(from..until).contains(ts)), "{:?}", bob);
+}
+
+#[test]
+fn every_thread_joins_promptly_after_a_shutdown() {
+    let config = SimulationConfig { transaction_limit: i32::MAX, tick_buffer: Some(1), ..fast() };
+    let mut exchange = StockExchange::new(config);
+    exchange.add_stock(Stock::new("AAPL", 100));
+    exchange.add_stock(Stock::new("MSFT", 100));
+    exchange.add_broker("First", tech_clients(&["Ann"]), BrokerConfig::default());
+    exchange.add_broker("Second", tech_clients(&["Bob", "Cat"]), BrokerConfig::default());
+    // Never sent a tick, so it is waiting on its channel when the run stops.
+    let idle = HashMap::from([("Dan".to_string(), ClientPreference::new(StockType::Food, "Market", 0, 0))]);
+    exchange.add_broker("Idle", idle, BrokerConfig::default());
+    let control = exchange.control();
+    let handle = exchange.start().unwrap();
+    thread::sleep(Duration::from_millis(100));
+
+    control.request_shutdown();
+    let (done, joined) = std::sync::mpsc::channel();
+    thread::spawn(move || done.send(handle.join()).unwrap());
+    let report = joined.recv_timeout(Duration::from_secs(2)).expect("the run hung on shutdown").unwrap();
+    assert_eq!(report.brokers.len(), 3);
+    assert!(report.ticks > 0);
+}
+
+#[test]
+fn every_order_is_acknowledged_exactly_once() {
+    let config = SimulationConfig { max_ticks: Some(60), transaction_limit: i32::MAX, speed: 1000.0, ..fast() };
+    let mut exchange = StockExchange::new(config);
+    exchange.add_stock(Stock::new("AAPL", 100));
+    exchange.add_stock(Stock::new("MSFT", 100));
+    exchange.add_broker("First", tech_clients(&["Ann", "Bob"]), BrokerConfig::default());
+    // Limit orders there fill at most 5 shares a tick.
+    let thin = BrokerConfig { liquidity_per_tick: Some(5), ..BrokerConfig::default() };
+    let limit = HashMap::from([("Cat".to_string(), ClientPreference::new(StockType::Tech, "Limit", 0, 0))]);
+    exchange.add_broker("Second", limit, thin);
+    let report = exchange.run().unwrap();
+
+    let mut acked: Vec<_> = report.acks.iter().map(|ack| (ack.broker.clone(), ack.id)).collect();
+    acked.sort();
+    let mut placed: Vec<_> =
+        report.brokers.iter().flat_map(|b| b.orders.iter().map(|o| (b.name.clone(), o.id))).collect();
+    placed.sort();
+    assert!(placed.len() > 2);
+    assert_eq!(acked, placed);
+    assert!(report.acks.iter().any(|ack| ack.status == AckStatus::PartiallyFilled));
+}
+
+#[test]
+fn brokers_run_whether_or_not_they_can_be_pinned() {
+    // Core 0 exists everywhere pinning is supported; usize::MAX nowhere.
+    for core in [0, usize::MAX] {
+        let config = SimulationConfig { max_ticks: Some(10), speed: 1000.0, ..fast() };
+        let mut exchange = StockExchange::new(config);
+        exchange.add_stock(Stock::new("AAPL", 100));
+        let config = BrokerConfig { cpu_affinity: Some(core), ..BrokerConfig::default() };
+        exchange.add_broker("Broker", tech_clients(&["Ann"]), config);
+        let report = exchange.run().unwrap();
+        assert_eq!(report.ticks, 10);
+    }
+}
+
+#[cfg(all(feature = "affinity", target_os = "linux"))]
+#[test]
+fn pinning_succeeds_only_on_a_usable_core() {
+    // Pinned on their own threads so the test thread keeps every core.
+    assert!(thread::spawn(|| pin_current_thread(0)).join().unwrap());
+    assert!(!thread::spawn(|| pin_current_thread(usize::MAX)).join().unwrap());
+}
+
+#[cfg(not(all(feature = "affinity", target_os = "linux")))]
+#[test]
+fn pinning_is_unsupported_without_the_affinity_feature() {
+    assert!(!thread::spawn(|| pin_current_thread(0)).join().unwrap());
+}
+
+#[test]
+fn earnings_convert_at_the_configured_rate() {
+    let fx_rates = HashMap::from([("EUR".to_string(), 0.5), ("JPY".to_string(), 150.0)]);
+    let config = SimulationConfig { max_ticks: Some(3), speed: 1000.0, fx_rates, ..fast() };
+    let mut exchange = StockExchange::new(config);
+    exchange.add_stock(Stock::new("AAPL", 100));
+    exchange.add_broker("Broker", tech_clients(&["Ann"]), BrokerConfig::default());
+    exchange.set_price_source(Box::new(ScriptedSource::new(vec![-5, 10, -3])));
+    exchange.set_quantity_source("Broker", Box::new(ScriptedSource::new(vec![30, 20])));
+    let report = exchange.run().unwrap();
+
+    // Selling 20 on the rise from 95 to 105.
+    assert_eq!(report.earnings()["Ann"], 200);
+    assert_eq!(report.earnings_in("EUR"), Some(HashMap::from([("Ann".to_string(), 100.0)])));
+    assert_eq!(report.earnings_in("JPY"), Some(HashMap::from([("Ann".to_string(), 30_000.0)])));
+    assert_eq!(report.earnings_in("GBP"), None);
+}
+
+#[test]
+fn orders_over_a_stock_throttle_are_skipped() {
+    // Two AAPL orders across both brokers for the rest of the run.
+    let throttle = OrderThrottle { max_orders: 2, interval: Duration::from_secs(36_000) };
+    let settings = StockSettings { throttle: Some(throttle), ..StockSettings::default() };
+    let config = SimulationConfig {
+        max_ticks: Some(60),
+        transaction_limit: i32::MAX,
+        speed: 1000.0,
+        stock_settings: HashMap::from([("AAPL".to_string(), settings)]),
+        ..fast()
+    };
+    let mut exchange = StockExchange::new(config);
+    exchange.add_stock(Stock::new("AAPL", 100));
+    exchange.add_stock(Stock::new("MSFT", 100));
+    exchange.add_broker("First", tech_clients(&["Ann"]), BrokerConfig::default());
+    exchange.add_broker("Second", tech_clients(&["Bob"]), BrokerConfig::default());
+    let report = exchange.run().unwrap();
+
+    let placed = |stock: &str| report.brokers.iter().flat_map(|b| &b.orders).filter(|o| &*o.stock_name == stock).count();
+    let throttled: Vec<_> = report
+        .brokers
+        .iter()
+        .flat_map(|b| &b.skipped)
+        .filter(|s| matches!(s.reason, SkipReason::Throttled { .. }))
+        .map(|s| (s.stock_name.to_string(), s.reason.clone()))
+        .collect();
+    assert_eq!(placed("AAPL"), 2);
+    assert!(!throttled.is_empty());
+    // The interval is reported at the run's speed.
+    let reason = SkipReason::Throttled { max_orders: 2, interval: Duration::from_secs(36) };
+    assert!(throttled.iter().all(|skip| *skip == ("AAPL".to_string(), reason.clone())), "{:?}", throttled);
+    assert!(placed("MSFT") > 2);
+}
+
+#[test]
+fn a_snapshot_diff_covers_added_and_removed_stocks() {
+    let snapshot = |stocks: Vec<Stock>| MarketSnapshot { stocks, ts: Duration::ZERO, ticks: 0 };
+    let before = snapshot(vec![Stock::new("AAPL", 100), Stock::new("KO", 60), Stock::new("MSFT", 200)]);
+    let after = snapshot(vec![Stock::new("MSFT", 195), Stock::new("AAPL", 104), Stock::new("NVDA", 80)]);
+
+    // KO was delisted and NVDA listed in between.
+    let expected = [("AAPL", 4), ("KO", -60), ("MSFT", -5), ("NVDA", 80)].map(|(name, delta)| (name.to_string(), delta));
+    assert_eq!(before.diff(&after), expected);
+    assert!(before.diff(&before).iter().all(|(_, delta)| *delta == 0));
+}
+
+#[test]
+fn a_broker_without_clients_finishes_at_once_and_takes_no_ticks() {
+    let mut exchange = StockExchange::new(SimulationConfig { max_ticks: Some(3), ..fast() });
+    exchange.add_stock(Stock::new("AAPL", 100));
+    exchange.add_broker("Empty", HashMap::new(), BrokerConfig::default());
+    exchange.add_broker("Busy", tech_clients(&["Ann"]), BrokerConfig::default());
+    exchange.set_price_source(Box::new(ScriptedSource::new(vec![-5, 10, -3])));
+    exchange.set_quantity_source("Busy", Box::new(ScriptedSource::new(vec![30, 20])));
+    let handle = exchange.start().unwrap();
+
+    let mut polls = 0;
+    while !handle.completion_status()["Empty"] && polls < 100 {
+        thread::sleep(Duration::from_millis(5));
+        polls += 1;
+    }
+    assert!(handle.completion_status()["Empty"], "the clientless broker is still running");
+    let report = handle.join().unwrap();
+
+    let broker = |name: &str| report.brokers.iter().find(|b| b.name == name).unwrap();
+    assert!(broker("Empty").orders.is_empty() && broker("Empty").accounts.is_empty());
+    // Busy still sees every tick.
+    let orders: Vec<_> = broker("Busy").orders.iter().map(|o| (o.order_type.as_str(), o.quantity, o.price)).collect();
+    assert_eq!(orders, [("buying", 30, 95), ("selling", 20, 105), ("buying", 30, 102)]);
+}
+
+#[test]
+fn a_rejected_copy_is_not_recorded_as_executed() {
+    let config = SimulationConfig { max_ticks: Some(60), transaction_limit: 2, speed: 1000.0, ..fast() };
+    let mut exchange = StockExchange::new(config);
+    exchange.add_stock(Stock::new("AAPL", 100));
+    exchange.add_broker("Leader", tech_clients(&["Ann"]), BrokerConfig::default());
+    // The follower passes up the buy at half price as a fat finger, so it
+    // holds nothing when the sell is copied.
+    let copy = CopyTrading { leader: "Leader".to_string(), scale: 1.0 };
+    let follower = BrokerConfig { copy: Some(copy), fat_finger_bps: Some(2000), ..BrokerConfig::default() };
+    exchange.add_broker("Follower", tech_clients(&["Bob"]), follower);
+    let mut prices = vec![-50, 5];
+    prices.resize(100, 0);
+    exchange.set_price_source(Box::new(ScriptedSource::new(prices)));
+    let report = exchange.run().unwrap();
+
+    let follower = report.brokers.iter().find(|b| b.name == "Follower").unwrap();
+    assert_eq!(follower.orders.len(), 1);
+    let sell = &follower.orders[0];
+    assert_eq!((sell.order_type.as_str(), sell.filled_qty), ("selling", 0));
+    let ack = report.acks.iter().find(|ack| ack.id == sell.id).unwrap();
+    assert_eq!(ack.status, AckStatus::Rejected);
+    let signals: Vec<_> = follower.signals.iter().map(|s| (s.side.as_str(), s.executed)).collect();
+    assert_eq!(signals, [("buying", false), ("selling", false)]);
+}
@@ -0,0 +1,41 @@
+#![cfg(feature = "parquet")]
+
+mod common;
+
+use std::fs::{self, File};
+
+use arrow_array::{Int32Array, RecordBatch, StringArray};
+use common::{broker, fixed, path};
+use ngwaijie_tp066893::export::{orders_batch, ticks_batch, write_orders_parquet, write_ticks_parquet};
+use ngwaijie_tp066893::stock::{BrokerConfig, StockType};
+use parquet::arrow::arrow_reader::ParquetRecordBatchReaderBuilder;
+
+fn read_back(file: File) -> RecordBatch {
+    let batches: Vec<_> = ParquetRecordBatchReaderBuilder::try_new(file).unwrap().build().unwrap().collect();
+    assert_eq!(batches.len(), 1);
+    batches.into_iter().next().unwrap().unwrap()
+}
+
+#[test]
+fn orders_and_ticks_read_back_from_parquet() {
+    let ticks = path("AAPL", &[100, 95, 105, 90]);
+    let result = broker(&[("Ann", fixed(StockType::Tech, "Market", 10))], BrokerConfig::default(), &ticks);
+    let dir = std::env::temp_dir().join(format!("parquet-round-trip-{}", std::process::id()));
+    fs::create_dir_all(&dir).unwrap();
+
+    let orders_path = dir.join("orders.parquet");
+    write_orders_parquet(&result.orders, File::create(&orders_path).unwrap()).unwrap();
+    let orders = read_back(File::open(&orders_path).unwrap());
+    assert_eq!(orders, orders_batch(&result.orders).unwrap());
+    let sides = orders.column_by_name("order_type").unwrap().as_any().downcast_ref::<StringArray>().unwrap();
+    assert_eq!(sides.iter().flatten().collect::<Vec<_>>(), ["buying", "selling", "buying"]);
+
+    let ticks_path = dir.join("ticks.parquet");
+    write_ticks_parquet(&ticks, File::create(&ticks_path).unwrap()).unwrap();
+    let read = read_back(File::open(&ticks_path).unwrap());
+    assert_eq!(read, ticks_batch(&ticks).unwrap());
+    let prices = read.column_by_name("v").unwrap().as_any().downcast_ref::<Int32Array>().unwrap();
+    assert_eq!(prices.values(), &[95, 105, 90]);
+
+    fs::remove_dir_all(&dir).unwrap();
+}
@@ -0,0 +1,24 @@
+#![cfg(all(feature = "ctrlc", unix))]
+
+use std::process::Command;
+use std::thread;
+use std::time::{Duration, Instant};
+
+use ngwaijie_tp066893::stock::{install_interrupt_handler, SimulationControl};
+
+#[test]
+fn ctrl_c_stops_the_latest_run() {
+    let first = SimulationControl::new();
+    let second = SimulationControl::new();
+    install_interrupt_handler(&first);
+    install_interrupt_handler(&second);
+
+    let status = Command::new("kill").args(["-INT", &std::process::id().to_string()]).status().unwrap();
+    assert!(status.success());
+    let deadline = Instant::now() + Duration::from_secs(5);
+    while !second.is_shutdown() && Instant::now() < deadline {
+        thread::sleep(Duration::from_millis(10));
+    }
+    assert!(second.is_shutdown());
+    assert!(!first.is_shutdown());
+}
@@ -0,0 +1,79 @@
+mod common;
+
+use common::{broker, fixed, path};
+use ngwaijie_tp066893::stock::{run_strategy, BrokerConfig, ClientPreference, OrderReason, QuantityStrategy, StockType};
+
+#[test]
+fn order_reasons_display_as_text() {
+    let reasons = [
+        (OrderReason::PriceDecrease { to: 95 }, "Executed a buy due to price decrease to 95"),
+        (OrderReason::PriceIncrease { to: 105 }, "Executed a sell due to price increase to 105"),
+        (OrderReason::StopLoss, "Executed a sell due to stop-loss"),
+        (OrderReason::TrailingStop { high: 120 }, "Executed a sell due to trailing stop from a high of 120"),
+        (OrderReason::TakeProfit, "Executed a sell due to take-profit"),
+        (OrderReason::Copied { leader_order: 7 }, "Executed a copy of leader order 7"),
+        (OrderReason::BelowFairValue { fair_value: 110 }, "Executed a buy due to price below fair value of 110"),
+        (OrderReason::AboveFairValue { fair_value: 90 }, "Executed a sell due to price above fair value of 90"),
+        (OrderReason::PairDiverged { ratio_bps: 8000 }, "Executed a pair trade on the ratio diverging to 8000 bps"),
+        (OrderReason::PairReverted { ratio_bps: 10_000 }, "Unwound a pair trade on the ratio reverting to 10000 bps"),
+        (OrderReason::RisingStreak { ticks: 3 }, "Executed after 3 consecutive rising ticks"),
+        (OrderReason::FallingStreak { ticks: 4 }, "Executed after 4 consecutive falling ticks"),
+        (OrderReason::Drawdown { drawdown: 250 }, "Executed a sell to liquidate after a drawdown of $250"),
+    ];
+    for (reason, text) in reasons {
+        assert_eq!(reason.to_string(), text);
+    }
+}
+
+#[test]
+fn order_reasons_can_be_matched() {
+    let orders = run_strategy(ClientPreference::new(StockType::Tech, "Market", 0, 0), &path("AAPL", &[100, 95, 105]));
+    let moves: Vec<i32> = orders
+        .iter()
+        .map(|o| match o.reason {
+            OrderReason::PriceDecrease { to } => -to,
+            OrderReason::PriceIncrease { to } => to,
+            ref other => panic!("unexpected reason {:?}", other),
+        })
+        .collect();
+    assert_eq!(moves, [-95, 105]);
+}
+
+#[test]
+fn a_large_limit_order_fills_in_two_parts() {
+    let config = BrokerConfig { liquidity_per_tick: Some(10), ..BrokerConfig::default() };
+    let prefs = ClientPreference { min_change_buy: 5, min_change_sell: 5, ..fixed(StockType::Tech, "Limit", 20) };
+    // A buy of 20 at 90 and a sell of 20 at 100, each filling 10 a tick.
+    let result = broker(&[("Ann", prefs)], config, &path("AAPL", &[100, 90, 88, 100, 103]));
+
+    let fills: Vec<_> = result
+        .orders
+        .iter()
+        .map(|o| (o.order_type.as_str(), o.filled_qty, o.fills.iter().map(|f| (f.quantity, f.price)).collect::<Vec<_>>()))
+        .collect();
+    assert_eq!(fills, [("buying", 20, vec![(10, 90), (10, 88)]), ("selling", 20, vec![(10, 100), (10, 103)])]);
+    assert_eq!(result.orders[0].average_fill_price(), Some(89.0));
+    // Each sold piece realizes its own PnL against the average cost of 89.
+    assert_eq!(result.accounts["Ann"].realized_pnl, 10 * 11 + 10 * 14);
+}
+
+#[test]
+fn a_sell_closes_earlier_buys_first_in_first_out() {
+    let prefs = ClientPreference { sell_qty_strategy: QuantityStrategy::Fixed(15), ..fixed(StockType::Tech, "Market", 10) };
+    let result = broker(&[("Ann", prefs)], BrokerConfig::default(), &path("AAPL", &[100, 99, 98, 105, 107]));
+    let orders: Vec<_> = result.orders.iter().map(|o| (o.order_type.as_str(), o.price, &o.closes, o.fifo_pnl)).collect();
+    let (first, second) = (result.orders[0].id, result.orders[1].id);
+    assert_ne!(first, second);
+
+    // The first sell takes all of the buy at 99 and half of the one at 98;
+    // the second sell closes what is left of the buy at 98.
+    assert_eq!(
+        orders,
+        [
+            ("buying", 99, &vec![], 0),
+            ("buying", 98, &vec![], 0),
+            ("selling", 105, &vec![first, second], 10 * 6 + 5 * 7),
+            ("selling", 107, &vec![second], 5 * 9),
+        ]
+    );
+}
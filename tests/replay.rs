@@ -0,0 +1,88 @@
+use std::collections::HashMap;
+use std::time::Duration;
+
+use ngwaijie_tp066893::exchange::StockExchange;
+use ngwaijie_tp066893::stock::{
+    replay_prices_csv, run_broker, run_strategy, BrokerConfig, ClientPreference, Order, OrderReason, QuantityStrategy,
+    ScriptedSource, SimulationConfig, StockType, Verbosity,
+};
+
+const SERIES: &str = "ticker, timestamp, price
+AAPL, 2, 95
+AAPL, 1, 100
+MSFT, 1, 50
+AAPL, 3, 105
+MSFT, 3, 45
+";
+
+fn summary<'a>(orders: impl IntoIterator<Item = &'a Order>) -> Vec<(String, String, i32, i32)> {
+    orders.into_iter().map(|o| (o.stock_name.to_string(), o.order_type.clone(), o.quantity, o.price)).collect()
+}
+
+fn expected() -> Vec<(String, String, i32, i32)> {
+    vec![
+        ("AAPL".to_string(), "buying".to_string(), 50, 95),
+        ("AAPL".to_string(), "selling".to_string(), 50, 105),
+        ("MSFT".to_string(), "buying".to_string(), 50, 45),
+    ]
+}
+
+#[test]
+fn csv_rows_become_ticks_in_timestamp_order() {
+    let ticks = replay_prices_csv(SERIES.as_bytes()).unwrap();
+    let derived: Vec<(&str, i32, i32, u64)> =
+        ticks.iter().map(|t| (&*t.stock.name, t.stock.prev_v, t.stock.v, t.seq)).collect();
+    assert_eq!(
+        derived,
+        [("AAPL", 100, 100, 0), ("MSFT", 50, 50, 0), ("AAPL", 100, 95, 1), ("AAPL", 95, 105, 2), ("MSFT", 50, 45, 2)]
+    );
+}
+
+#[test]
+fn a_strategy_trades_the_replayed_series() {
+    let ticks = replay_prices_csv(SERIES.as_bytes()).unwrap();
+    let orders = run_strategy(ClientPreference::new(StockType::Tech, "Market", 0, 0), &ticks);
+    assert_eq!(summary(&orders), expected());
+    assert_eq!(orders[0].reason, OrderReason::PriceDecrease { to: 95 });
+}
+
+#[test]
+fn a_broker_trades_the_replayed_series() {
+    let ticks = replay_prices_csv(SERIES.as_bytes()).unwrap();
+    let prefs = ClientPreference {
+        buy_qty_strategy: QuantityStrategy::Fixed(50),
+        sell_qty_strategy: QuantityStrategy::Fixed(50),
+        ..ClientPreference::new(StockType::Tech, "Market", 0, 0)
+    };
+    let clients = HashMap::from([("Ann".to_string(), prefs.clone()), ("Bob".to_string(), prefs)]);
+    let result = run_broker("Broker", clients, BrokerConfig::default(), &ticks, Box::new(ScriptedSource::new(vec![1])));
+    assert_eq!(result.orders.len(), 6);
+    for client in ["Ann", "Bob"] {
+        assert_eq!(summary(result.orders.iter().filter(|o| o.client_name == client)), expected());
+        assert_eq!(result.accounts[client].earnings, 500);
+    }
+}
+
+#[test]
+fn an_exchange_trades_the_replayed_series() {
+    let config = SimulationConfig {
+        speed: 1000.0,
+        verbosity: Verbosity::Silent,
+        watchdog_timeout: Duration::from_secs(600),
+        ..SimulationConfig::default()
+    };
+    let mut exchange = StockExchange::new(config);
+    exchange.replay(replay_prices_csv(SERIES.as_bytes()).unwrap());
+    exchange.add_broker(
+        "Broker",
+        HashMap::from([("Ann".to_string(), ClientPreference::new(StockType::Tech, "Market", 0, 0))]),
+        BrokerConfig::default(),
+    );
+    exchange.set_quantity_source("Broker", Box::new(ScriptedSource::new(vec![50])));
+
+    let report = exchange.run().unwrap();
+    assert_eq!(report.ticks, 5);
+    assert_eq!(report.price_history["AAPL"], [100, 95, 105]);
+    assert_eq!(report.price_history["MSFT"], [50, 45]);
+    assert_eq!(summary(&report.brokers[0].orders), expected());
+}
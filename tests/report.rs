@@ -0,0 +1,274 @@
+mod common;
+
+use std::collections::HashMap;
+use std::fs;
+use std::time::Duration;
+
+use common::{broker, clients, fixed, path};
+use ngwaijie_tp066893::report::{merge_broker_results, ClientPnl, ExchangeReport, Format};
+use ngwaijie_tp066893::stock::{
+    realized_vol, run_broker, BrokerConfig, BrokerMode, ClientPreference, CommissionTier, PositionMode,
+    PriceTick, QuantityStrategy, ScriptedSource, Stock, StockType,
+};
+
+#[test]
+fn csv_order_values_do_not_overflow() {
+    let ticks = path("AAPL", &[100_000, 99_999]);
+    let result = broker(&[("Ann", fixed(StockType::Tech, "Market", 100_000))], BrokerConfig::default(), &ticks);
+    let report = ExchangeReport::new(vec![result], Vec::new(), Vec::new(), Duration::ZERO);
+
+    let csv = report.to_csv();
+    let order = csv.lines().find(|line| line.starts_with("order,")).unwrap();
+    assert_eq!(order.split(',').nth(7), Some("9999900000"));
+}
+
+#[test]
+fn pooled_brokers_keep_separate_leaderboard_entries() {
+    // Each round trip earns the 10 the share rose, times the quantity.
+    let ticks = path("AAPL", &[100, 99, 109]);
+    let run = |name: &str, mode, names: [&str; 2], quantity| {
+        let prefs = names.map(|client| (client, fixed(StockType::Tech, "Market", quantity)));
+        run_broker(name, clients(&prefs), BrokerConfig::new(mode), &ticks, Box::new(ScriptedSource::new(vec![1])))
+    };
+    let brokers = vec![
+        run("Alpha", BrokerMode::Pooled, ["Ann", "Bob"], 10),
+        run("Beta", BrokerMode::Pooled, ["Cat", "Dan"], 30),
+        run("Gamma", BrokerMode::Individual, ["Eve", "Fay"], 20),
+    ];
+    let report = ExchangeReport::new(brokers, Vec::new(), Vec::new(), Duration::ZERO);
+
+    assert_eq!(
+        report.leaderboard(),
+        [
+            ("Beta/Pool".to_string(), 600),
+            ("Alpha/Pool".to_string(), 200),
+            ("Eve".to_string(), 200),
+            ("Fay".to_string(), 200),
+        ]
+    );
+    assert_eq!(report.client_pnl().keys().collect::<Vec<_>>(), ["Alpha/Pool", "Beta/Pool", "Eve", "Fay"]);
+}
+
+fn traded() -> ExchangeReport {
+    let config = BrokerConfig {
+        commission_tiers: vec![CommissionTier { min_volume: 0, rate_bps: 20 }],
+        taker_fee_bps: 10,
+        ..BrokerConfig::default()
+    };
+    let prefs = [("Ann", fixed(StockType::Tech, "Market", 30)), ("Bob", fixed(StockType::Tech, "Market", 50))];
+    let result = broker(&prefs, config, &path("AAPL", &[100, 97, 104, 90, 95]));
+    ExchangeReport::new(vec![result], Vec::new(), vec![Stock::new("AAPL", 95)], Duration::ZERO)
+}
+
+#[test]
+fn a_scripted_run_reconciles() {
+    let report = traded();
+    assert!(report.brokers[0].accounts.values().all(|a| a.commissions > 0 && a.fees > 0));
+    assert!(report.reconcile().is_ok());
+}
+
+#[test]
+fn an_unbalanced_account_fails_reconciliation() {
+    let mut report = traded();
+    report.brokers[0].accounts.get_mut("Bob").unwrap().cash += 7;
+    let discrepancies = report.reconcile().unwrap_err();
+    assert_eq!(discrepancies.len(), 1);
+    let discrepancy = &discrepancies[0];
+    assert_eq!((discrepancy.broker.as_str(), discrepancy.account.as_str()), ("Broker", "Bob"));
+    assert_eq!(discrepancy.net_worth - discrepancy.pnl, 7);
+}
+
+#[test]
+fn gainers_and_losers_rank_by_percentage_change() {
+    let opening = vec![Stock::new("AAPL", 100), Stock::new("KO", 50), Stock::new("MRK", 200), Stock::new("TSLA", 10)];
+    let closing = vec![Stock::new("AAPL", 110), Stock::new("KO", 40), Stock::new("MRK", 220), Stock::new("TSLA", 15)];
+    let report = ExchangeReport::new(Vec::new(), opening, closing, Duration::ZERO);
+
+    let names = |ranked: Vec<(String, f64)>| ranked.into_iter().map(|(name, _)| name).collect::<Vec<_>>();
+    // AAPL and MRK both rose 10%, and tie on the ticker.
+    assert_eq!(names(report.top_gainers(3)), ["TSLA", "AAPL", "MRK"]);
+    assert_eq!(names(report.top_losers(2)), ["KO", "AAPL"]);
+    assert_eq!(report.top_gainers(1), [("TSLA".to_string(), 50.0)]);
+
+    let mut stocks = [Stock::new("MRK", 220), Stock::new("KO", 40), Stock::new("AAPL", 40)];
+    stocks.sort();
+    assert_eq!(stocks.iter().map(|s| &*s.name).collect::<Vec<_>>(), ["AAPL", "KO", "MRK"]);
+}
+
+#[test]
+fn a_report_written_to_a_file_reads_back() {
+    let report = traded();
+    let dir = std::env::temp_dir().join(format!("report-round-trip-{}", std::process::id()));
+    fs::create_dir_all(&dir).unwrap();
+
+    let json_path = dir.join("report.json");
+    report.write_to_file(&json_path, Format::Json).unwrap();
+    let json: serde_json::Value = serde_json::from_str(&fs::read_to_string(&json_path).unwrap()).unwrap();
+    let broker = &json["brokers"][0];
+    assert_eq!(broker["orders"].as_array().unwrap().len(), report.brokers[0].orders.len());
+    assert_eq!(broker["accounts"]["Ann"]["earnings"], report.brokers[0].accounts["Ann"].earnings);
+    assert_eq!(json["closing"][0]["name"], "AAPL");
+
+    let csv_path = dir.join("report.csv");
+    report.write_to_file(&csv_path, Format::Csv).unwrap();
+    let csv = fs::read_to_string(&csv_path).unwrap();
+    assert_eq!(csv, report.to_csv());
+    assert!(csv.starts_with("record,broker,client,"));
+    let orders = csv.lines().filter(|line| line.starts_with("order,")).count();
+    assert_eq!(orders, report.brokers[0].orders.len());
+
+    fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn order_flow_imbalance_is_bought_less_sold() {
+    let ticks = path("AAPL", &[100, 95, 105, 90]);
+    let seller = ClientPreference { sell_qty_strategy: QuantityStrategy::Fixed(10), ..fixed(StockType::Tech, "Market", 50) };
+    // Buys of 30 and 30 against a sell of 30, and buys of 50 and 50 against a sell of 10.
+    let brokers = vec![
+        broker(&[("Ann", fixed(StockType::Tech, "Market", 30))], BrokerConfig::default(), &ticks),
+        broker(&[("Bob", seller)], BrokerConfig::default(), &ticks),
+    ];
+    let report = ExchangeReport::new(brokers, Vec::new(), Vec::new(), Duration::ZERO);
+
+    assert_eq!(report.order_flow_imbalance("AAPL"), (30 + 30 + 50 + 50) - (30 + 10));
+    assert_eq!(report.order_flow_imbalance("MSFT"), 0);
+}
+
+#[test]
+fn an_imbalance_past_i32_max_saturates() {
+    let ticks = path("AAPL", &[100, 95]);
+    let buyer = |name| broker(&[(name, fixed(StockType::Tech, "Market", 2_000_000_000))], BrokerConfig::default(), &ticks);
+    let report = ExchangeReport::new(vec![buyer("Ann"), buyer("Bob")], Vec::new(), Vec::new(), Duration::ZERO);
+
+    assert_eq!(report.trade_stats("AAPL").bought, 4_000_000_000);
+    assert_eq!(report.order_flow_imbalance("AAPL"), i32::MAX);
+}
+
+#[test]
+fn realized_and_unrealized_pnl_are_reported_apart() {
+    // A round trip in AAPL from 95 to 105, and 10 MSFT bought at 50 still held.
+    let mut ticks = path("AAPL", &[100, 95, 105]);
+    ticks.extend(path("MSFT", &[55, 50]));
+    let result = broker(&[("Ann", fixed(StockType::Tech, "Market", 10))], BrokerConfig::default(), &ticks);
+    let closing = vec![Stock::new("AAPL", 105), Stock::new("MSFT", 62)];
+    let report = ExchangeReport::new(vec![result], Vec::new(), closing, Duration::ZERO);
+
+    assert_eq!(report.client_pnl()["Ann"], ClientPnl { realized: 100, unrealized: 120 });
+    assert!(report.reconcile().is_ok());
+}
+
+#[test]
+fn realized_volatility_matches_a_hand_calculation() {
+    let mut report = ExchangeReport::new(Vec::new(), Vec::new(), Vec::new(), Duration::ZERO);
+    report.price_history.insert("AAPL".to_string(), vec![100, 110, 99, 99]);
+
+    // Returns of +10%, -10% and 0 have mean 0 and sample variance 0.02 / 2.
+    assert!((report.realized_vol("AAPL", 3).unwrap() - 0.1).abs() < 1e-12);
+    // The last two, -10% and 0, have mean -5% and sample variance 0.005.
+    assert!((report.realized_vol("AAPL", 2).unwrap() - 0.005f64.sqrt()).abs() < 1e-12);
+    assert_eq!(report.realized_vol("AAPL", 4), None);
+    assert_eq!(report.realized_vol("MSFT", 2), None);
+}
+
+#[test]
+fn slippage_is_fill_less_arrival_averaged_per_client() {
+    let config = BrokerConfig { liquidity_per_tick: Some(10), ..BrokerConfig::default() };
+    let prefs = ClientPreference { min_change_buy: 5, min_change_sell: 5, ..fixed(StockType::Tech, "Limit", 20) };
+    // A buy arriving at 90 fills at 90 and 88; a sell arriving at 100 fills at 100 and 103.
+    let result = broker(&[("Ann", prefs)], config, &path("AAPL", &[100, 90, 88, 100, 103]));
+    let arrivals: Vec<_> = result.orders.iter().map(|o| (o.arrival_price, o.slippage())).collect();
+    assert_eq!(arrivals, [(90, Some(-1.0)), (100, Some(1.5))]);
+
+    let report = ExchangeReport::new(vec![result], Vec::new(), Vec::new(), Duration::ZERO);
+    assert_eq!(report.client_slippage().into_iter().collect::<Vec<_>>(), [("Ann".to_string(), 0.25)]);
+}
+
+#[test]
+fn sharpe_is_mean_sell_pnl_over_its_deviation() {
+    let run = |name: &str, prices: &[i32]| {
+        let clients = clients(&[("Ann", fixed(StockType::Tech, "Market", 10))]);
+        run_broker(name, clients, BrokerConfig::default(), &path("AAPL", prices), Box::new(ScriptedSource::new(vec![])))
+    };
+    let brokers = vec![
+        // Sells making 100, 20 and 60: mean 60, sample deviation 40.
+        run("Varied", &[100, 90, 100, 95, 97, 90, 96]),
+        run("Single", &[100, 90, 100]),
+        run("Steady", &[100, 90, 95, 90, 95]),
+    ];
+    let report = ExchangeReport::new(brokers, Vec::new(), Vec::new(), Duration::ZERO);
+
+    assert_eq!(report.broker_sharpe("Varied"), Some(1.5));
+    assert_eq!(report.broker_sharpe("Single"), None);
+    assert_eq!(report.broker_sharpe("Steady"), None);
+    assert_eq!(report.broker_sharpe("Missing"), None);
+}
+
+#[test]
+fn a_gross_broker_has_no_sharpe() {
+    // The same sells as "Varied" above, but opening short legs instead of closing lots.
+    let config = BrokerConfig { position_mode: PositionMode::Gross, ..BrokerConfig::default() };
+    let ticks = path("AAPL", &[100, 90, 100, 95, 97, 90, 96]);
+    let result = broker(&[("Ann", fixed(StockType::Tech, "Market", 10))], config, &ticks);
+    assert!(result.orders.iter().filter(|o| o.order_type == "selling").all(|o| o.filled_qty > 0 && o.fifo_pnl == 0));
+    let report = ExchangeReport::new(vec![result], Vec::new(), Vec::new(), Duration::ZERO);
+
+    assert_eq!(report.broker_sharpe("Broker"), None);
+}
+
+#[test]
+fn changes_off_a_zero_price_count_as_no_change() {
+    assert_eq!(Stock::new("AAPL", 50).with_prev_v(0).percent_change(), 0.0);
+    // Only the move from 10 to 20 has a size.
+    assert!((realized_vol(&[0, 10, 20]).unwrap() - 0.5f64.sqrt()).abs() < 1e-12);
+
+    let prefs = ClientPreference { trailing_stop_bps: Some(500), ..fixed(StockType::Tech, "Market", 10) };
+    let result = broker(&[("Ann", prefs)], BrokerConfig::default(), &path("AAPL", &[10, 0, 20, 0]));
+    let opening = vec![Stock::new("AAPL", 0), Stock::new("MSFT", 40)];
+    let closing = vec![Stock::new("AAPL", 20), Stock::new("MSFT", 50)];
+    let report = ExchangeReport::new(vec![result], opening, closing, Duration::ZERO);
+    assert_eq!(report.percent_change("AAPL"), Some(0.0));
+    assert_eq!(report.top_gainers(2), [("MSFT".to_string(), 25.0), ("AAPL".to_string(), 0.0)]);
+}
+
+#[test]
+fn merged_results_combine_both_brokers() {
+    let run = |name: &str, client: &str, ticks: &[PriceTick]| {
+        let clients = clients(&[(client, fixed(StockType::Tech, "Market", 10))]);
+        run_broker(name, clients, BrokerConfig::default(), ticks, Box::new(ScriptedSource::new(vec![])))
+    };
+    let first = run("First", "Ann", &path("AAPL", &[100, 90, 100]));
+    // Bob is left holding 20 MSFT bought for 850 and worth 800 at the close.
+    let mut ticks = path("AAPL", &[100, 95, 98, 99]);
+    ticks.extend(path("MSFT", &[50, 45, 40]));
+    let second = run("Second", "Bob", &ticks);
+    let orders = first.orders.len() + second.orders.len();
+
+    let report = merge_broker_results(vec![first, second]).unwrap();
+    assert_eq!(report.brokers.len(), 2);
+    assert_eq!(report.brokers.iter().map(|b| b.orders.len()).sum::<usize>(), orders);
+    assert_eq!(report.earnings(), HashMap::from([("Ann".to_string(), 100), ("Bob".to_string(), 30)]));
+    assert_eq!(report.volume("AAPL"), 40);
+
+    let prices = |stocks: &[Stock]| stocks.iter().map(|s| (s.name.to_string(), s.v)).collect::<Vec<_>>();
+    assert_eq!(prices(&report.opening), [("AAPL".to_string(), 100), ("MSFT".to_string(), 50)]);
+    assert_eq!(prices(&report.closing), [("AAPL".to_string(), 99), ("MSFT".to_string(), 40)]);
+    assert_eq!(report.client_pnl()["Bob"].unrealized, -50);
+    assert!(report.reconcile().is_ok());
+}
+
+#[test]
+fn realized_pnl_is_attributed_to_the_stock_that_made_it() {
+    // A gain of 10 a share in AAPL. In MSFT, half the shares bought at 50
+    // and 40 are sold at 42, 3 a share below their average cost.
+    let mut ticks = path("AAPL", &[100, 95, 105]);
+    ticks.extend(path("MSFT", &[55, 50, 40, 42]));
+    let prefs = [("Ann", fixed(StockType::Tech, "Market", 10)), ("Bob", fixed(StockType::Tech, "Market", 20))];
+    let result = broker(&prefs, BrokerConfig::default(), &ticks);
+    let report = ExchangeReport::new(vec![result], Vec::new(), Vec::new(), Duration::ZERO);
+
+    let expected = HashMap::from([("AAPL".to_string(), 300), ("MSFT".to_string(), -90)]);
+    assert_eq!(report.pnl_by_stock(), expected);
+    let realized: i64 = report.brokers[0].accounts.values().map(|a| a.realized_pnl).sum();
+    assert_eq!(realized, 300 - 90);
+}
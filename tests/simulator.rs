@@ -0,0 +1,524 @@
+mod common;
+
+use std::collections::{BTreeSet, HashMap, HashSet};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use common::fixed;
+use crossbeam_channel::Receiver;
+use ngwaijie_tp066893::stock::{
+    broker_sectors, process_broker_actions, run_broker, seeded_rng, simulate_stock_changes, spawn_watchdog,
+    BrokerConfig, CandleInterval, ClientPreference, MarketSimulator, OrderReason, PriceModel, PriceTick, RandomSource,
+    ScriptedSource, SendRetry, SimulationConfig, SimulationControl, Stock, StockSettings, StockType, TickDispatcher, Verbosity, VirtualClock,
+};
+use scheduled_thread_pool::ScheduledThreadPool;
+
+fn quiet() -> SimulationConfig {
+    SimulationConfig { verbosity: Verbosity::Silent, ..SimulationConfig::default() }
+}
+
+#[test]
+fn stalled_broker_does_not_hold_the_stock_lock() {
+    let config = SimulationConfig {
+        tick_buffer: Some(1),
+        send_retry: SendRetry { attempts: 3, backoff: Duration::from_millis(100) },
+        ..quiet()
+    };
+    let mut dispatcher = TickDispatcher::with_buffer(config.tick_buffer);
+    let stalled = dispatcher.subscribe("Stalled", HashSet::from([StockType::Tech]));
+    let stocks = Arc::new(Mutex::new(vec![Stock::new("AAPL", 100), Stock::new("MSFT", 100)]));
+    let clock = Arc::new(VirtualClock::new());
+    let control = SimulationControl::new();
+    simulate_stock_changes(&*clock, stocks.clone(), dispatcher, control, &config, Box::new(ScriptedSource::new(vec![1])));
+
+    // The second tick of the cycle finds the channel full and is retried
+    // for 700ms before being dropped.
+    let cycle = {
+        let clock = clock.clone();
+        thread::spawn(move || clock.advance(Duration::from_millis(1)))
+    };
+    thread::sleep(Duration::from_millis(100));
+    let waited = Instant::now();
+    drop(stocks.lock().unwrap());
+    assert!(waited.elapsed() < Duration::from_millis(200), "locking took {:?}", waited.elapsed());
+    cycle.join().unwrap();
+
+    // The stalled broker is retried, not dropped: once it drains its channel
+    // it gets the next cycle's ticks.
+    assert_eq!(stalled.try_iter().count(), 1);
+    clock.advance(Duration::from_secs(1));
+    let ticks = stalled.try_recv().expect("the broker still receives ticks");
+    assert_eq!(ticks[0].seq, 1);
+}
+
+// A simulator of AAPL alone feeding one Tech broker through a one-message
+// channel, retried `attempts` times from `backoff`.
+fn retrying(attempts: u32, backoff: Duration) -> (MarketSimulator, Receiver<Vec<PriceTick>>) {
+    let config = SimulationConfig { tick_buffer: Some(1), send_retry: SendRetry { attempts, backoff }, ..quiet() };
+    let mut dispatcher = TickDispatcher::with_buffer(config.tick_buffer);
+    let receiver = dispatcher.subscribe("Broker", HashSet::from([StockType::Tech]));
+    let source = Box::new(ScriptedSource::new(vec![1]));
+    (MarketSimulator::new(config, dispatcher, SimulationControl::new(), source), receiver)
+}
+
+#[test]
+fn a_full_channel_is_retried_until_the_broker_catches_up() {
+    let (mut simulator, receiver) = retrying(3, Duration::from_millis(100));
+    let mut stocks = vec![Stock::new("AAPL", 100)];
+    simulator.cycle(&mut stocks);
+
+    // The second batch finds the channel full. The broker drains it 150ms in,
+    // between the retries at 100ms and 300ms.
+    let drain = {
+        let receiver = receiver.clone();
+        thread::spawn(move || {
+            thread::sleep(Duration::from_millis(150));
+            receiver.recv().unwrap()
+        })
+    };
+    let started = Instant::now();
+    simulator.cycle(&mut stocks);
+    let waited = started.elapsed();
+
+    assert_eq!(drain.join().unwrap()[0].seq, 0);
+    assert_eq!(receiver.try_recv().expect("the retry delivers the batch")[0].seq, 1);
+    assert!(waited >= Duration::from_millis(150), "delivered after {:?}", waited);
+    // Giving up would have taken 100 + 200 + 400ms.
+    assert!(waited < Duration::from_millis(700), "delivered after {:?}", waited);
+}
+
+#[test]
+fn a_batch_is_dropped_only_once_every_retry_is_used() {
+    let (mut simulator, receiver) = retrying(3, Duration::from_millis(50));
+    let mut stocks = vec![Stock::new("AAPL", 100)];
+    simulator.cycle(&mut stocks);
+
+    let started = Instant::now();
+    simulator.cycle(&mut stocks);
+    let waited = started.elapsed();
+
+    // Three retries wait 50 + 100 + 200ms; a fourth would add 400ms more.
+    assert!(waited >= Duration::from_millis(350), "dropped after {:?}", waited);
+    assert!(waited < Duration::from_millis(750), "dropped after {:?}", waited);
+    let batches: Vec<_> = receiver.try_iter().map(|batch| batch[0].seq).collect();
+    assert_eq!(batches, [0]);
+}
+
+fn flat_simulator(config: SimulationConfig, control: &SimulationControl) -> MarketSimulator {
+    MarketSimulator::new(config, TickDispatcher::new(), control.clone(), Box::new(ScriptedSource::new(vec![0])))
+}
+
+#[test]
+fn news_shocks_every_stock_in_the_sector_on_the_next_tick() {
+    let control = SimulationControl::new();
+    let mut simulator = flat_simulator(quiet(), &control);
+    let mut stocks = vec![
+        Stock::new("AAPL", 200),
+        Stock::new("MSFT", 150),
+        Stock::new("KO", 300),
+        Stock::new("IPO", 1_000_000_000).with_sector(StockType::Tech),
+    ];
+    simulator.cycle(&mut stocks);
+    control.inject_news(StockType::Tech, -1000);
+    simulator.cycle(&mut stocks);
+    let prices: Vec<i32> = stocks.iter().map(|s| s.v).collect();
+    assert_eq!(prices, [180, 135, 300, 900_000_000]);
+
+    // The shock applies once.
+    simulator.cycle(&mut stocks);
+    assert_eq!(stocks[0].v, 180);
+}
+
+#[test]
+fn large_news_shocks_do_not_overflow() {
+    let control = SimulationControl::new();
+    let mut simulator = flat_simulator(quiet(), &control);
+    let mut stocks = vec![Stock::new("AAPL", 1_000_000_000), Stock::new("MSFT", 2_000_000_000)];
+    control.inject_news(StockType::Tech, 10_000);
+    simulator.cycle(&mut stocks);
+    assert_eq!(stocks[0].v, 2_000_000_000);
+    assert_eq!(stocks[1].v, i32::MAX);
+}
+
+// Ticks of 5 with limits of 7 up and 13 down from the open at 100, neither a
+// multiple of the tick.
+fn ticked(source: Box<dyn RandomSource>) -> MarketSimulator {
+    let settings = StockSettings {
+        tick_size: Some(5),
+        daily_limit_up: Some(7),
+        daily_limit_down: Some(13),
+        ..StockSettings::default()
+    };
+    let config = SimulationConfig { stock_settings: HashMap::from([("AAPL".to_string(), settings)]), ..quiet() };
+    MarketSimulator::new(config, TickDispatcher::new(), SimulationControl::new(), source)
+}
+
+#[test]
+fn prices_round_to_ticks_inside_the_daily_limits() {
+    for (delta, expected) in [(60, 105), (-40, 90), (3, 105), (-2, 100)] {
+        let mut stocks = vec![Stock::new("AAPL", 100)];
+        ticked(Box::new(ScriptedSource::new(vec![delta]))).cycle(&mut stocks);
+        assert_eq!(stocks[0].v, expected, "moved by {}", delta);
+    }
+
+    let mut simulator = ticked(Box::new(seeded_rng(Some(1))));
+    let mut stocks = vec![Stock::new("AAPL", 100)];
+    for _ in 0..1000 {
+        simulator.cycle(&mut stocks);
+        assert_eq!(stocks[0].v % 5, 0);
+        assert!((90..=105).contains(&stocks[0].v), "{} breaches the limits", stocks[0].v);
+    }
+}
+
+#[test]
+fn ranges_ending_at_i32_max_do_not_overflow() {
+    let config = SimulationConfig {
+        market_shock_bps: i32::MAX..=i32::MAX,
+        fair_value_drift: 0..=i32::MAX,
+        ..quiet()
+    };
+    let source = Box::new(seeded_rng(Some(1)));
+    let mut simulator = MarketSimulator::new(config, TickDispatcher::new(), SimulationControl::new(), source);
+    let mut stocks = vec![Stock::new("AAPL", 100).with_fair_value(100)];
+    simulator.cycle(&mut stocks);
+    assert!(stocks[0].fair_value.unwrap() >= 100);
+}
+
+#[test]
+fn a_prev_v_above_the_listed_price_buys_on_the_first_tick() {
+    let control = SimulationControl::new();
+    let config = SimulationConfig { candle_interval: Some(CandleInterval::Ticks(5)), ..quiet() };
+    let mut simulator = flat_simulator(config, &control);
+    let mut stocks = vec![Stock::new("AAPL", 100).with_prev_v(150)];
+    let ticks = simulator.generate(&mut stocks);
+    assert_eq!((ticks[0].stock.prev_v, ticks[0].stock.v), (150, 100));
+
+    let clients = HashMap::from([("Ann".to_string(), ClientPreference::new(StockType::Tech, "Market", 0, 0))]);
+    let result = run_broker("Broker", clients, BrokerConfig::default(), &ticks, Box::new(ScriptedSource::new(vec![10])));
+    assert_eq!(result.orders[0].order_type, "buying");
+    assert_eq!(result.orders[0].reason, OrderReason::PriceDecrease { to: 100 });
+
+    // The stock never traded at 150.
+    let ohlc = control.ohlc()["AAPL"];
+    assert_eq!((ohlc.open, ohlc.high, ohlc.low), (100, 100, 100));
+    assert_eq!(control.candles()["AAPL"][0].ohlc.open, 100);
+}
+
+#[test]
+fn the_watchdog_fires_once_the_simulator_stops() {
+    let control = SimulationControl::new();
+    let mut dispatcher = TickDispatcher::new();
+    let receiver = dispatcher.subscribe("Broker", HashSet::from([StockType::Tech]));
+    let clients = HashMap::from([("Ann".to_string(), ClientPreference::new(StockType::Tech, "Market", 0, 0))]);
+    let broker = process_broker_actions(
+        "Broker".to_string(),
+        receiver,
+        clients,
+        10,
+        control.clone(),
+        BrokerConfig::default(),
+        Box::new(ScriptedSource::new(vec![10])),
+        Verbosity::Silent,
+    );
+    let mut simulator = MarketSimulator::new(quiet(), dispatcher, control.clone(), Box::new(ScriptedSource::new(vec![-5])));
+    simulator.cycle(&mut [Stock::new("AAPL", 100)]);
+
+    // The simulator stops ticking but stays alive, so the broker keeps waiting.
+    let timeout = Duration::from_millis(200);
+    let stopped = Instant::now();
+    let watchdog = spawn_watchdog(control.clone(), timeout, true, Verbosity::Silent);
+    assert!(watchdog.join().unwrap(), "the watchdog did not fire");
+    let waited = stopped.elapsed();
+    assert!(waited >= timeout - Duration::from_millis(50) && waited < timeout * 2, "fired after {:?}", waited);
+    assert!(control.is_shutdown());
+    assert_eq!(broker.join().unwrap().orders.len(), 1);
+    drop(simulator);
+}
+
+#[test]
+fn two_days_are_split_by_an_overnight_gap() {
+    let config = SimulationConfig { days: 2, ticks_per_day: Some(2), ..quiet() };
+    // Two moves a day, with the 30 drawn for the gap between them.
+    let source = Box::new(ScriptedSource::new(vec![-5, 10, 30, -5, 10]));
+    let control = SimulationControl::new();
+    let mut simulator = MarketSimulator::new(config, TickDispatcher::new(), control.clone(), source);
+    let mut stocks = vec![Stock::new("AAPL", 100)];
+    let ticks: Vec<_> = (0..5).flat_map(|_| simulator.generate(&mut stocks)).collect();
+
+    let moves: Vec<_> = ticks.iter().map(|t| (t.day, t.stock.prev_v, t.stock.v)).collect();
+    assert_eq!(moves, [(0, 100, 95), (0, 95, 105), (1, 135, 130), (1, 130, 140)]);
+    assert!(control.is_shutdown());
+
+    let clients = HashMap::from([("Ann".to_string(), fixed(StockType::Tech, "Market", 10))]);
+    let result = run_broker("Broker", clients, BrokerConfig::default(), &ticks, Box::new(ScriptedSource::new(vec![])));
+    assert_eq!(result.daily_earnings().into_iter().collect::<Vec<_>>(), [(0, 100), (1, 100)]);
+}
+
+#[test]
+fn a_closed_weekend_has_no_ticks_and_a_wider_gap() {
+    let moves = |closed_days: BTreeSet<u32>| {
+        let config = SimulationConfig { days: 2, ticks_per_day: Some(2), closed_days, ..quiet() };
+        // The same draws as above: 30 is the gap for each night shut.
+        let source = Box::new(ScriptedSource::new(vec![-5, 10, 30, -5, 10]));
+        let mut simulator = MarketSimulator::new(config, TickDispatcher::new(), SimulationControl::new(), source);
+        let mut stocks = vec![Stock::new("AAPL", 100)];
+        let ticks: Vec<_> = (0..5).flat_map(|_| simulator.generate(&mut stocks)).collect();
+        ticks.iter().map(|t| (t.day, t.stock.prev_v, t.stock.v)).collect::<Vec<_>>()
+    };
+
+    assert_eq!(moves(BTreeSet::new()), [(0, 100, 95), (0, 95, 105), (1, 135, 130), (1, 130, 140)]);
+    // Days 1 and 2 are shut, so day 3 opens after three nights.
+    let reopened = moves(BTreeSet::from([1, 2]));
+    assert!(reopened.iter().all(|&(day, _, _)| day != 1 && day != 2));
+    assert_eq!(reopened, [(0, 100, 95), (0, 95, 105), (3, 195, 190), (3, 190, 200)]);
+}
+
+#[test]
+fn a_tech_only_broker_gets_only_tech_ticks() {
+    let clients = HashMap::from([
+        ("Ann".to_string(), ClientPreference::new(StockType::Tech, "Market", 0, 0)),
+        ("Bob".to_string(), ClientPreference::new(StockType::Tech, "Limit", 5, 5)),
+    ]);
+    let mut dispatcher = TickDispatcher::new();
+    let tech = dispatcher.subscribe("Tech", broker_sectors(&clients));
+    let all = dispatcher.subscribe("All", HashSet::from([StockType::Tech, StockType::Food, StockType::Healthcare]));
+    let mut simulator = MarketSimulator::new(quiet(), dispatcher, SimulationControl::new(), Box::new(seeded_rng(Some(1))));
+    let mut stocks = vec![Stock::new("AAPL", 100), Stock::new("KO", 100), Stock::new("MRK", 100), Stock::new("MSFT", 100)];
+    for _ in 0..10 {
+        simulator.cycle(&mut stocks);
+    }
+
+    let names = |receiver: &crossbeam_channel::Receiver<Vec<PriceTick>>| {
+        receiver.try_iter().flatten().map(|t| t.stock.name.to_string()).collect::<HashSet<_>>()
+    };
+    assert_eq!(names(&tech), HashSet::from(["AAPL".to_string(), "MSFT".to_string()]));
+    assert_eq!(names(&all).len(), 4);
+}
+
+#[test]
+fn large_moves_are_clamped_to_the_daily_limits() {
+    let settings = StockSettings { daily_limit_up: Some(10), daily_limit_down: Some(5), ..StockSettings::default() };
+    let config = SimulationConfig { stock_settings: HashMap::from([("AAPL".to_string(), settings)]), ..quiet() };
+    let source = Box::new(ScriptedSource::new(vec![60, 8, -60]));
+    let mut simulator = MarketSimulator::new(config, TickDispatcher::new(), SimulationControl::new(), source);
+    let mut stocks = vec![Stock::new("AAPL", 100), Stock::new("MSFT", 100)];
+
+    // AAPL draws 60, then -60, both clamped against the open at 100. MSFT,
+    // which has no limits, draws 8, then 60.
+    let mut prices = Vec::new();
+    for _ in 0..2 {
+        simulator.cycle(&mut stocks);
+        prices.push((stocks[0].v, stocks[1].v));
+    }
+    assert_eq!(prices, [(110, 108), (95, 168)]);
+}
+
+// Lag-1 autocorrelation of the price deltas of `cycles` cycles, seeded.
+fn delta_autocorrelation(rho: f64, cycles: usize) -> f64 {
+    let config = SimulationConfig { autocorrelation: rho, ..quiet() };
+    let source = Box::new(seeded_rng(Some(131)));
+    let mut simulator = MarketSimulator::new(config, TickDispatcher::new(), SimulationControl::new(), source);
+    let mut stocks = vec![Stock::new("AAPL", 1_000_000)];
+    let deltas: Vec<f64> = (0..cycles)
+        .map(|_| {
+            simulator.cycle(&mut stocks);
+            stocks[0].change() as f64
+        })
+        .collect();
+    let mean = deltas.iter().sum::<f64>() / deltas.len() as f64;
+    let covariance: f64 = deltas.windows(2).map(|pair| (pair[0] - mean) * (pair[1] - mean)).sum();
+    let variance: f64 = deltas.iter().map(|d| (d - mean).powi(2)).sum();
+    covariance / variance
+}
+
+#[test]
+fn deltas_follow_the_configured_autocorrelation() {
+    for rho in [0.0, 0.5, -0.4] {
+        let measured = delta_autocorrelation(rho, 5000);
+        assert!((measured - rho).abs() < 0.05, "rho {} measured as {}", rho, measured);
+    }
+}
+
+#[test]
+fn gbm_log_returns_have_the_configured_moments() {
+    let (mu, sigma) = (0.001, 0.01);
+    let settings = StockSettings { model: PriceModel::Gbm { mu, sigma }, ..StockSettings::default() };
+    let config = SimulationConfig { stock_settings: HashMap::from([("AAPL".to_string(), settings)]), ..quiet() };
+    let source = Box::new(seeded_rng(Some(136)));
+    let mut simulator = MarketSimulator::new(config, TickDispatcher::new(), SimulationControl::new(), source);
+    // A high price keeps rounding to whole units from distorting the returns.
+    let mut stocks = vec![Stock::new("AAPL", 1_000_000)];
+    let returns: Vec<f64> = (0..5000)
+        .map(|_| {
+            simulator.cycle(&mut stocks);
+            (stocks[0].v as f64 / stocks[0].prev_v as f64).ln()
+        })
+        .collect();
+
+    let mean = returns.iter().sum::<f64>() / returns.len() as f64;
+    let variance = returns.iter().map(|r| (r - mean).powi(2)).sum::<f64>() / (returns.len() - 1) as f64;
+    let expected_mean = mu - sigma * sigma / 2.0;
+    // The standard error of the mean is sigma / sqrt(5000), about 0.00014.
+    assert!((mean - expected_mean).abs() < 0.0005, "mean {} expected {}", mean, expected_mean);
+    assert!((variance / (sigma * sigma) - 1.0).abs() < 0.1, "variance {} expected {}", variance, sigma * sigma);
+}
+
+#[test]
+fn batched_ticks_arrive_as_one_message_per_cycle() {
+    let messages = |batch_ticks| {
+        let mut dispatcher = TickDispatcher::new();
+        let all = dispatcher.subscribe("All", HashSet::from([StockType::Tech, StockType::Food, StockType::Healthcare]));
+        let config = SimulationConfig { batch_ticks, ..quiet() };
+        let mut simulator = MarketSimulator::new(config, dispatcher, SimulationControl::new(), Box::new(seeded_rng(Some(1))));
+        let mut stocks = vec![Stock::new("AAPL", 100), Stock::new("KO", 100), Stock::new("MRK", 100)];
+        for _ in 0..5 {
+            simulator.cycle(&mut stocks);
+        }
+        all.try_iter().map(|batch| batch.iter().map(|t| t.stock.name.to_string()).collect::<Vec<_>>()).collect::<Vec<_>>()
+    };
+
+    let batched = messages(true);
+    assert_eq!(batched.len(), 5);
+    for batch in &batched {
+        assert_eq!(batch, &["AAPL", "KO", "MRK"]);
+    }
+    let unbatched = messages(false);
+    assert_eq!(unbatched.len(), 15);
+    assert!(unbatched.iter().all(|message| message.len() == 1));
+}
+
+#[test]
+fn the_first_change_of_a_day_is_measured_from_the_open() {
+    let first_of_day_two = |reset_reference_at_open| {
+        let config = SimulationConfig { days: 2, ticks_per_day: Some(2), reset_reference_at_open, ..quiet() };
+        // Closes day one at 105, gaps up 30 overnight and then falls 5.
+        let source = Box::new(ScriptedSource::new(vec![-5, 10, 30, -5]));
+        let mut simulator = MarketSimulator::new(config, TickDispatcher::new(), SimulationControl::new(), source);
+        let mut stocks = vec![Stock::new("AAPL", 100)];
+        let ticks: Vec<_> = (0..3).flat_map(|_| simulator.generate(&mut stocks)).collect();
+        let stock = &ticks[2].stock;
+        (ticks[2].day, stock.prev_v, stock.v, stock.change())
+    };
+
+    assert_eq!(first_of_day_two(true), (1, 135, 130, -5));
+    assert_eq!(first_of_day_two(false), (1, 105, 130, 25));
+}
+
+#[test]
+fn every_tick_and_order_shares_the_listed_ticker() {
+    let mut stocks = vec![Stock::new("AAPL", 100)];
+    let listed = stocks[0].name.clone();
+    let mut simulator = MarketSimulator::new(quiet(), TickDispatcher::new(), SimulationControl::new(), Box::new(seeded_rng(Some(142))));
+    let ticks: Vec<_> = (0..1000).flat_map(|_| simulator.generate(&mut stocks)).collect();
+    assert_eq!(ticks.len(), 1000);
+    assert!(ticks.iter().all(|t| Arc::ptr_eq(&t.stock.name, &listed)));
+
+    let clients = HashMap::from([("Ann".to_string(), fixed(StockType::Tech, "Market", 10))]);
+    let result = run_broker("Broker", clients, BrokerConfig::default(), &ticks, Box::new(ScriptedSource::new(vec![])));
+    assert!(!result.orders.is_empty());
+    assert!(result.orders.iter().all(|o| Arc::ptr_eq(&o.stock_name, &listed)));
+}
+
+// Mean wall-clock gap between the first few ticks of a real-time run.
+fn tick_interval(speed: f64) -> Duration {
+    let config = SimulationConfig { speed, ..quiet() };
+    let mut dispatcher = TickDispatcher::new();
+    let receiver = dispatcher.subscribe("Tech", HashSet::from([StockType::Tech]));
+    let pool = ScheduledThreadPool::new(1);
+    let control = SimulationControl::new();
+    let stocks = Arc::new(Mutex::new(vec![Stock::new("AAPL", 100)]));
+    simulate_stock_changes(&pool, stocks, dispatcher, control.clone(), &config, Box::new(seeded_rng(Some(145))));
+
+    let arrivals: Vec<Instant> = (0..6).map(|_| receiver.recv().map(|_| Instant::now()).unwrap()).collect();
+    control.request_shutdown();
+    (arrivals[5] - arrivals[0]) / 5
+}
+
+#[test]
+fn ten_times_the_speed_ticks_ten_times_as_often() {
+    let interval = tick_interval(10.0);
+    assert!(interval > Duration::from_millis(80) && interval < Duration::from_millis(150), "ticked every {:?}", interval);
+    let interval = tick_interval(100.0);
+    assert!(interval > Duration::from_millis(8) && interval < Duration::from_millis(40), "ticked every {:?}", interval);
+}
+
+#[test]
+fn a_thousand_virtual_ticks_run_without_waiting() {
+    let mut dispatcher = TickDispatcher::new();
+    let receiver = dispatcher.subscribe("Tech", HashSet::from([StockType::Tech]));
+    let clock = VirtualClock::new();
+    let stocks = Arc::new(Mutex::new(vec![Stock::new("AAPL", 100)]));
+    let config = quiet();
+    let source = Box::new(seeded_rng(Some(159)));
+    simulate_stock_changes(&clock, stocks.clone(), dispatcher, SimulationControl::new(), &config, source);
+
+    // One tick a simulated second, the first 100us in.
+    let started = Instant::now();
+    clock.advance(Duration::from_secs(1000));
+    // A wall-clock run at this speed would take over 16 minutes.
+    assert!(started.elapsed() < Duration::from_millis(500), "took {:?}", started.elapsed());
+
+    assert_eq!(clock.now(), Duration::from_secs(1000));
+    let ticks: Vec<_> = receiver.try_iter().flatten().collect();
+    assert_eq!(ticks.len(), 1000);
+    assert_eq!(ticks.last().unwrap().stock.v, stocks.lock().unwrap()[0].v);
+}
+
+#[test]
+fn moves_near_the_open_are_scaled_up_by_the_volatility_curve() {
+    let curve = vec![(Duration::ZERO, 3.0), (Duration::from_secs(5), 1.0), (Duration::from_secs(15), 2.0)];
+    let config = SimulationConfig { volatility_curve: curve, ..quiet() };
+    // Every draw is the same move of 10 before scaling.
+    let source = Box::new(ScriptedSource::new(vec![10]));
+    let mut simulator = MarketSimulator::new(config, TickDispatcher::new(), SimulationControl::new(), source);
+    let mut stocks = vec![Stock::new("AAPL", 1000)];
+    let changes: Vec<_> = (0..20).flat_map(|_| simulator.generate(&mut stocks)).map(|t| t.stock.change()).collect();
+
+    assert_eq!(changes[..5], [30; 5]);
+    assert_eq!(changes[5..15], [10; 10]);
+    assert_eq!(changes[15..], [20; 5]);
+}
+
+// Pearson correlation of two equally long series.
+fn correlation(xs: &[f64], ys: &[f64]) -> f64 {
+    let mean = |v: &[f64]| v.iter().sum::<f64>() / v.len() as f64;
+    let (mx, my) = (mean(xs), mean(ys));
+    let covariance: f64 = xs.iter().zip(ys).map(|(x, y)| (x - mx) * (y - my)).sum();
+    let spread = |v: &[f64], m: f64| v.iter().map(|x| (x - m).powi(2)).sum::<f64>().sqrt();
+    covariance / (spread(xs, mx) * spread(ys, my))
+}
+
+#[test]
+fn high_beta_stocks_follow_the_market_more_closely() {
+    // Every cycle starts from 10,000, where the default shock of up to 50 bps
+    // moves a beta-one stock by up to 50, about as much as its own noise.
+    let beta = |market_beta| StockSettings { market_beta, ..StockSettings::default() };
+    let stock_settings = HashMap::from([
+        ("INDEX".to_string(), beta(1.0)),
+        ("HIGH".to_string(), beta(2.0)),
+        ("LOW".to_string(), beta(0.2)),
+    ]);
+    let config = SimulationConfig { stock_settings, ..quiet() };
+    let source = Box::new(seeded_rng(Some(198)));
+    let mut simulator = MarketSimulator::new(config, TickDispatcher::new(), SimulationControl::new(), source);
+    let mut stocks = vec![Stock::new("INDEX", 10_000), Stock::new("HIGH", 10_000), Stock::new("LOW", 10_000)];
+    let mut deltas = vec![Vec::new(); 3];
+    for _ in 0..5000 {
+        for stock in &mut stocks {
+            stock.v = 10_000;
+        }
+        simulator.cycle(&mut stocks);
+        for (deltas, stock) in deltas.iter_mut().zip(&stocks) {
+            deltas.push(stock.change() as f64);
+        }
+    }
+
+    let high = correlation(&deltas[1], &deltas[0]);
+    let low = correlation(&deltas[2], &deltas[0]);
+    assert!(high > 0.5, "high beta correlates {}", high);
+    assert!(low < 0.25, "low beta correlates {}", low);
+    // The high-beta stock also moves further.
+    let spread = |v: &[f64]| v.iter().map(|d| d.abs()).sum::<f64>();
+    assert!(spread(&deltas[1]) > spread(&deltas[2]));
+}
@@ -0,0 +1,90 @@
+mod common;
+
+use std::collections::HashMap;
+
+use common::{broker, fixed, tick};
+use ngwaijie_tp066893::exchange::StockExchange;
+use ngwaijie_tp066893::stock::{
+    replay_prices_csv, run_strategy, seeded_rng, BrokerConfig, ClientPreference, RandomSource, SimulationConfig, Stock,
+    StockCatalog, StockType, Verbosity,
+};
+
+const ALPHABET: &[char] = &['A', 'Z', 'a', '0', '9', '.', '-', ',', ' ', '"', '\n', '\0', 'é', '股', '🚀'];
+
+// Up to 8 characters, empty included, with known tickers mixed in.
+fn random_tickers(n: usize) -> Vec<String> {
+    let mut rng = seeded_rng(Some(199));
+    let mut tickers: Vec<String> = (0..n)
+        .map(|_| (0..rng.delta(0..9)).map(|_| ALPHABET[rng.delta(0..ALPHABET.len() as i32) as usize]).collect())
+        .collect();
+    tickers.extend(["AAPL", "KO", "PFE", "aapl", "AAPL "].map(String::from));
+    tickers
+}
+
+#[test]
+fn random_tickers_never_panic() {
+    let sectors = [StockType::Tech, StockType::Food, StockType::Healthcare];
+    for ticker in random_tickers(300) {
+        let stock = Stock::new(&ticker, 100).with_prev_v(0).with_fair_value(90).with_metadata(&ticker, &ticker);
+        assert_eq!(stock.classify().ok(), stock.try_stock_type());
+        assert_eq!(stock.stock_type(), stock.try_stock_type());
+        assert_eq!((stock.change(), stock.percent_change()), (100, 0.0));
+        let typed = stock.clone().with_sector(StockType::Food);
+        assert_eq!((typed.stock_type(), typed.classify().ok()), (Some(StockType::Food), Some(StockType::Food)));
+        let mut catalog = StockCatalog::from_stocks(std::slice::from_ref(&stock));
+        catalog.insert(&typed);
+        assert_eq!(catalog.get(&ticker).unwrap().stock_type, Some(StockType::Food));
+
+        let mut exchange = StockExchange::new(SimulationConfig::default());
+        exchange.add_stock(stock.clone());
+        let snapshot = exchange.snapshot();
+        assert_eq!((snapshot.get(&ticker).map(|s| s.v), snapshot.fair_value(&ticker)), (Some(100), Some(90)));
+        assert_eq!(snapshot.diff(&exchange.snapshot()), [(ticker.clone(), 0)]);
+
+        let _ = replay_prices_csv(format!("{},1,100\n{},2,90\n", ticker, ticker).as_bytes());
+        let ticks = [tick(&ticker, 100, 100), tick(&ticker, 100, 90), tick(&ticker, 90, 120)];
+        for sector in &sectors {
+            run_strategy(ClientPreference::new(sector.clone(), "Market", 0, 0), &ticks);
+            broker(&[("Ann", fixed(sector.clone(), "Limit", 10))], BrokerConfig::default(), &ticks);
+        }
+    }
+}
+
+#[test]
+fn an_exchange_lists_random_tickers_without_panicking() {
+    let config =
+        SimulationConfig { speed: 1000.0, max_ticks: Some(3), verbosity: Verbosity::Silent, ..Default::default() };
+    for ticker in random_tickers(10) {
+        let mut exchange = StockExchange::new(config.clone());
+        exchange.add_stock(Stock::new(&ticker, 100));
+        exchange.add_stock(Stock::new("IPO", 50).with_sector(StockType::Tech));
+        let prefs = HashMap::from([("Ann".to_string(), ClientPreference::new(StockType::Tech, "Market", 0, 0))]);
+        exchange.add_broker("Broker", prefs, BrokerConfig::default());
+        let Ok(handle) = exchange.start() else { continue };
+        handle.add_stock_live(&format!("{}2", ticker), 10, StockType::Food);
+        handle.remove_stock_live(&ticker);
+        handle.join().unwrap();
+    }
+}
+
+#[test]
+fn metadata_round_trips_without_changing_the_sector() {
+    let stock = Stock::new("AAPL", 150).with_metadata("Apple Inc.", "NASDAQ");
+    let json = serde_json::to_string(&stock).unwrap();
+    let parsed: Stock = serde_json::from_str(&json).unwrap();
+    assert_eq!(parsed.display_name.as_deref(), Some("Apple Inc."));
+    assert_eq!(parsed.exchange.as_deref(), Some("NASDAQ"));
+    assert_eq!((&*parsed.name, parsed.v), ("AAPL", 150));
+    assert_eq!(parsed.try_stock_type(), Some(StockType::Tech));
+
+    // A stock without metadata leaves the fields out, and reads back without them.
+    let plain = serde_json::to_string(&Stock::new("KO", 60)).unwrap();
+    assert!(!plain.contains("display_name") && !plain.contains("exchange"));
+    let parsed: Stock = serde_json::from_str(&plain).unwrap();
+    assert_eq!((parsed.display_name.as_deref(), parsed.try_stock_type()), (None, Some(StockType::Food)));
+
+    let catalog = StockCatalog::from_stocks(&[stock]);
+    let catalog: StockCatalog = serde_json::from_str(&serde_json::to_string(&catalog).unwrap()).unwrap();
+    let entry = catalog.get("AAPL").unwrap();
+    assert_eq!((entry.display_name.as_deref(), entry.stock_type.clone()), (Some("Apple Inc."), Some(StockType::Tech)));
+}
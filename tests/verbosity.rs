@@ -0,0 +1,48 @@
+use std::env;
+use std::process::Command;
+
+use ngwaijie_tp066893::report::Format;
+use ngwaijie_tp066893::stock::{run_simulation_with_config, SimulationConfig, Verbosity};
+
+// Set to a verbosity level when this binary runs `print_a_run` for a parent test.
+const CHILD: &str = "VERBOSITY_CHILD";
+
+#[test]
+fn print_a_run() {
+    let verbosity = match env::var(CHILD).as_deref() {
+        Ok("Silent") => Verbosity::Silent,
+        Ok(_) => Verbosity::All,
+        Err(_) => return,
+    };
+    let config = SimulationConfig {
+        verbosity,
+        speed: 1000.0,
+        max_ticks: Some(200),
+        // Fails to write, which would otherwise be reported.
+        report_file: Some(("/nonexistent/dir/report.json".into(), Format::Json)),
+        ..SimulationConfig::default()
+    };
+    println!("BEGIN");
+    run_simulation_with_config(config).unwrap();
+    println!("END");
+}
+
+// What the run printed at `verbosity`, in a child process so that every
+// thread's output is seen.
+fn printed(verbosity: &str) -> String {
+    let output = Command::new(env::current_exe().unwrap())
+        .args(["print_a_run", "--exact", "--nocapture", "--test-threads=1"])
+        .env(CHILD, verbosity)
+        .output()
+        .unwrap();
+    assert!(output.status.success());
+    let stdout = String::from_utf8(output.stdout).unwrap();
+    let run = stdout.split("BEGIN\n").nth(1).and_then(|rest| rest.split("END\n").next());
+    run.expect("the child printed its markers").to_string()
+}
+
+#[test]
+fn silent_runs_print_nothing() {
+    assert_eq!(printed("Silent"), "");
+    assert!(printed("All").contains("STOCK UPDATE"));
+}